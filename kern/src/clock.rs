@@ -0,0 +1,41 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+
+use fat32::vfat::Timestamp;
+use kernel_api::{OsError, OsResult};
+use pi::timer;
+
+use crate::param::BOOT_UNIX_TIME;
+
+/// The wall-clock time at which the system timer read zero, in nanoseconds
+/// since the Unix epoch.
+static EPOCH_OFFSET_NS: AtomicU64 = AtomicU64::new(BOOT_UNIX_TIME * 1_000_000_000);
+
+/// Returns the current wall-clock time as a duration since the Unix epoch.
+///
+/// The Pi has no battery-backed real-time clock, so the wall clock starts at
+/// `BOOT_UNIX_TIME` and advances with the system timer until it is `set()`.
+pub fn now() -> Duration {
+    Duration::from_nanos(EPOCH_OFFSET_NS.load(Ordering::Relaxed)) + timer::current_time()
+}
+
+/// Sets the wall clock to `now`, a duration since the Unix epoch.
+///
+/// # Errors
+///
+/// Returns `OsError::InvalidArgument` if `now` is earlier than the time since
+/// boot or too far in the future to be kept in nanoseconds.
+pub fn set(now: Duration) -> OsResult<()> {
+    let offset = now
+        .checked_sub(timer::current_time())
+        .and_then(|offset| u64::try_from(offset.as_nanos()).ok())
+        .ok_or(OsError::InvalidArgument)?;
+    EPOCH_OFFSET_NS.store(offset, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Returns the current wall-clock time as a FAT32 timestamp. This is the
+/// clock the file system stamps new entries and writes with.
+pub fn fat_now() -> Timestamp {
+    Timestamp::from_unix(now().as_secs())
+}
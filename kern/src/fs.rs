@@ -1,5 +1,8 @@
 pub mod sd;
+pub mod tmpfs;
+pub mod vfs;
 
+use alloc::boxed::Box;
 use alloc::rc::Rc;
 use core::fmt::{self, Debug};
 use shim::io;
@@ -7,8 +10,11 @@ use shim::path::Path;
 
 pub use fat32::traits;
 use fat32::vfat::{Dir, Entry as EntryStruct, File, VFat, VFatHandle};
+use kernel_api::Stat;
 
 use crate::mutex::Mutex;
+use crate::process::ProcessFileT;
+use self::vfs::{FileSystemT, Vnode};
 
 #[derive(Clone)]
 pub struct PiVFatHandle(Rc<Mutex<VFat<Self>>>);
@@ -51,15 +57,40 @@ impl FileSystem {
     /// The caller should assure that the method is invoked only once during the
     /// kernel initialization.
     ///
+    /// A volume that was not synced before the last shutdown is checked and
+    /// repaired before it is used. Entries are stamped with the kernel's wall
+    /// clock.
+    ///
     /// # Panics
     ///
     /// Panics if the underlying disk or file sytem failed to initialize.
     pub unsafe fn initialize(&self) {
         let mut t = self.0.lock();
         let sd_card = sd::Sd::new().expect("sd card failed to load");
-        let fs = VFat::from(sd_card).expect("failed to make fs");
+        let fs = VFat::<PiVFatHandle>::from(sd_card).expect("failed to make fs");
+        fs.lock(|vfat| {
+            vfat.set_clock(crate::clock::fat_now);
+            if !vfat.is_dirty() {
+                return;
+            }
+            info!("fs: volume was not unmounted cleanly, checking it");
+            let findings = vfat.repair().expect("failed to check fs");
+            for finding in &findings {
+                warn!("fs: {}", finding);
+            }
+            vfat.sync().expect("failed to sync fs");
+        });
         let _handle: &mut PiVFatHandle = t.insert(fs);
     }
+
+    /// Writes every modified sector of the file system back to the SD card.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the file system is not initialized.
+    pub fn sync(&self) -> io::Result<()> {
+        self.0.lock().as_ref().expect("filesystem not initialized").lock(|vfat| vfat.sync())
+    }
 }
 
 impl fat32::traits::FileSystem for &FileSystem {
@@ -72,4 +103,69 @@ impl fat32::traits::FileSystem for &FileSystem {
     fn open<P: AsRef<Path>>(self, path: P) -> io::Result<Self::Entry> {
         self.0.lock().as_mut().expect("filesystem not initialized").open(path)
     }
+
+    fn create_dir<P: AsRef<Path>>(self, path: P) -> io::Result<Self::Dir> {
+        self.0.lock().as_mut().expect("filesystem not initialized").create_dir(path)
+    }
+
+    fn remove_dir<P: AsRef<Path>>(self, path: P) -> io::Result<()> {
+        self.0.lock().as_mut().expect("filesystem not initialized").remove_dir(path)
+    }
+
+    fn rename<P: AsRef<Path>, Q: AsRef<Path>>(self, from: P, to: Q) -> io::Result<()> {
+        self.0.lock().as_mut().expect("filesystem not initialized").rename(from, to)
+    }
+}
+
+impl FileSystemT for FileSystem {
+    fn lookup(&'static self, path: &Path) -> io::Result<Box<dyn Vnode>> {
+        Ok(Box::new(traits::FileSystem::open(self, path)?))
+    }
+
+    fn create_file(&'static self, path: &Path) -> io::Result<Box<dyn Vnode>> {
+        let (parent, name) = vfs::split(path)?;
+        let file = traits::FileSystem::open_dir(self, parent)?.create_file(name)?;
+        Ok(Box::new(EntryStruct::FileEntry(file)))
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        let (parent, name) = vfs::split(path)?;
+        traits::FileSystem::open_dir(self, parent)?.remove(name)
+    }
+
+    fn create_dir(&self, path: &Path) -> io::Result<()> {
+        traits::FileSystem::create_dir(self, path).map(|_| ())
+    }
+
+    fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        traits::FileSystem::remove_dir(self, path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        traits::FileSystem::rename(self, from, to)
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        FileSystem::sync(self)
+    }
+}
+
+impl Vnode for EntryStruct<PiVFatHandle> {
+    fn is_dir(&self) -> bool {
+        traits::Entry::is_dir(self)
+    }
+
+    fn stat(&self) -> io::Result<Stat> {
+        match self {
+            EntryStruct::FileEntry(file) => file.stat(),
+            EntryStruct::DirEntry(dir) => dir.stat(),
+        }
+    }
+
+    fn open(self: Box<Self>) -> Box<dyn ProcessFileT> {
+        match *self {
+            EntryStruct::FileEntry(file) => Box::new(file),
+            EntryStruct::DirEntry(dir) => Box::new(dir),
+        }
+    }
 }
@@ -5,7 +5,12 @@ use fat32::traits::BlockDevice;
 
 extern "C" {
     /// A global representing the last SD controller error that occured.
-    static sd_err: i64; // TODO: process this
+    static sd_err: i64;
+
+    /// The SD configuration register that `sd_init` read from the card. The
+    /// driver sets bit 0 of `sd_scr[0]` if the card reported that it is
+    /// block addressed (CCS), and `sd_readsector` addresses the card by it.
+    static sd_scr: [u64; 2];
 
     /// Initializes the SD card controller.
     ///
@@ -29,6 +34,7 @@ extern "C" {
 }
 
 
+use pi::emmc::{self, Emmc};
 use pi::timer;
 
 #[no_mangle]
@@ -38,7 +44,11 @@ fn wait_micros(us : u32) {
 
 /// A handle to an SD card controller.
 #[derive(Debug)]
-pub struct Sd;
+pub struct Sd {
+    /// Whether the card takes block numbers (SDHC and larger) or byte offsets
+    /// (SDSC) as the address of a read or a write.
+    block_addressed: bool,
+}
 
 impl Sd {
     /// Initializes the SD card controller and returns a handle to it.
@@ -49,7 +59,8 @@ impl Sd {
     pub unsafe fn new() -> Result<Sd, io::Error> {
         match sd_init() {
             0 => {
-                return Ok(Sd);
+                let block_addressed = sd_scr[0] & SCR_SUPP_CCS != 0;
+                Ok(Sd { block_addressed })
             },
             -1 => {
                 debug!("sdcard err: {}", sd_err);
@@ -65,6 +76,14 @@ impl Sd {
             }
         }
     }
+
+    /// Returns the card address of sector `n`: its block number or its byte
+    /// offset, depending on how the card is addressed.
+    fn address(&self, n: u64) -> io::Result<u32> {
+        let addr = if self.block_addressed { Some(n) } else { n.checked_mul(SECTOR_SIZE as u64) };
+        addr.and_then(|addr| addr.try_into().ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "sector number is out of range"))
+    }
 }
 
 impl BlockDevice for Sd {
@@ -81,11 +100,91 @@ impl BlockDevice for Sd {
     ///
     /// An error of kind `Other` is returned for all other errors.
     fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
-        let bytes_read = unsafe { sd_readsector(n.try_into().unwrap(), buf as *mut [u8] as *mut u8) };
-        Ok(bytes_read.try_into().unwrap())
+        if buf.len() < SECTOR_SIZE {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "buffer is smaller than a sector"));
+        }
+        let n: i32 = n
+            .try_into()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "sector number is out of range"))?;
+
+        let bytes_read = unsafe { sd_readsector(n, buf.as_mut_ptr()) };
+        if bytes_read > 0 {
+            return Ok(bytes_read as usize);
+        }
+
+        match unsafe { sd_err } {
+            -1 => Err(io::Error::new(io::ErrorKind::TimedOut, "SD card read timed out")),
+            err => {
+                debug!("sdcard err: {}", err);
+                Err(io::Error::new(io::ErrorKind::Other, "SD card read failed"))
+            }
+        }
+    }
+
+    /// Reads consecutive sectors starting at sector `n` into `buf`, whose
+    /// length must be a multiple of 512, using multi-block transfers. On
+    /// success, the number of bytes read is returned.
+    ///
+    /// # Errors
+    ///
+    /// An I/O error of kind `InvalidInput` is returned if `buf.len()` is not
+    /// a multiple of 512 or the sectors cannot be addressed on the card.
+    ///
+    /// An error of kind `TimedOut` is returned if a timeout occurs while
+    /// reading from the SD card.
+    ///
+    /// An error of kind `Other` is returned for all other errors.
+    fn read_sectors(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.len() % SECTOR_SIZE != 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "buffer is not a multiple of a sector"));
+        }
+
+        let mut emmc = unsafe { Emmc::new() };
+        for (i, chunk) in buf.chunks_mut(emmc::MAX_BLOCKS * SECTOR_SIZE).enumerate() {
+            let addr = self.address(n + (i * emmc::MAX_BLOCKS) as u64)?;
+            emmc.read_blocks(addr, chunk).map_err(emmc_error)?;
+        }
+        Ok(buf.len())
     }
 
-    fn write_sector(&mut self, _n: u64, _buf: &[u8]) -> io::Result<usize> {
-        unimplemented!("SD card and file system are read only")
+    /// Writes the first 512 bytes of `buf` to sector `n` of the SD card. On
+    /// success, the number of bytes written is returned.
+    ///
+    /// # Errors
+    ///
+    /// An I/O error of kind `InvalidInput` is returned if `buf.len() < 512` or
+    /// `n` cannot be addressed on the card.
+    ///
+    /// An error of kind `TimedOut` is returned if a timeout occurs while
+    /// writing to the SD card.
+    ///
+    /// An error of kind `Other` is returned for all other errors.
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        if buf.len() < SECTOR_SIZE {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "buffer is smaller than a sector"));
+        }
+        let addr = self.address(n)?;
+        let block: &[u8; SECTOR_SIZE] = buf[..SECTOR_SIZE].try_into().unwrap();
+        unsafe { Emmc::new() }
+            .write_block(addr, block)
+            .map_err(emmc_error)?;
+        Ok(SECTOR_SIZE)
+    }
+}
+
+/// The size of a sector of the SD card.
+const SECTOR_SIZE: usize = emmc::BLOCK_SIZE;
+
+/// The bit of `sd_scr[0]` that the C driver sets for block addressed cards.
+const SCR_SUPP_CCS: u64 = 0x1;
+
+/// Maps an EMMC controller error to an I/O error.
+fn emmc_error(err: emmc::Error) -> io::Error {
+    match err {
+        emmc::Error::TimedOut => io::Error::new(io::ErrorKind::TimedOut, "SD card timed out"),
+        emmc::Error::Failed(status) => {
+            debug!("sdcard interrupt status: {:#x}", status);
+            io::Error::new(io::ErrorKind::Other, "SD card command failed")
+        }
     }
 }
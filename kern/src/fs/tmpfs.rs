@@ -0,0 +1,448 @@
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+use kernel_api::{Stat, ATTR_ARCHIVE, ATTR_DIRECTORY, ATTR_HIDDEN, ATTR_READ_ONLY, ATTR_SYSTEM};
+use shim::io;
+use shim::path::{Component, Path};
+
+use crate::mutex::Mutex;
+use crate::process::ProcessFileT;
+use super::vfs::{self, FileSystemT, Vnode};
+
+/// Inode number of the root directory.
+const ROOT: u64 = 0;
+
+/// Attributes that `set_attributes` can change.
+const SETTABLE: u64 = ATTR_READ_ONLY | ATTR_HIDDEN | ATTR_SYSTEM | ATTR_ARCHIVE;
+
+/// The longest name an entry can have, in bytes.
+const MAX_NAME_LEN: usize = 255;
+
+/// The largest a file can grow, in bytes. Like on FAT32, sizes fit in 32 bits.
+const MAX_FILE_SIZE: u64 = u32::MAX as u64;
+
+enum Data {
+    File(Vec<u8>),
+    Dir(BTreeMap<String, u64>), // entry name -> inode number
+}
+
+struct Inode {
+    data: Data,
+    linked: bool, // false once the entry is removed from its directory
+    handles: usize, // number of live `Node`s
+    attributes: u64,
+    created: u64,
+    accessed: u64,
+    modified: u64,
+}
+
+impl Inode {
+    fn new(data: Data) -> Inode {
+        let now = crate::clock::now().as_secs();
+        Inode { data, linked: true, handles: 0, attributes: 0, created: now, accessed: now, modified: now }
+    }
+
+    fn is_dir(&self) -> bool {
+        matches!(self.data, Data::Dir(_))
+    }
+
+    fn stat(&self) -> Stat {
+        let (size, kind) = match &self.data {
+            Data::File(bytes) => (bytes.len() as u64, 0),
+            Data::Dir(_) => (0, ATTR_DIRECTORY),
+        };
+        Stat {
+            size,
+            attributes: self.attributes | kind,
+            created: self.created,
+            accessed: self.accessed,
+            modified: self.modified,
+        }
+    }
+}
+
+struct Inodes {
+    inodes: BTreeMap<u64, Inode>,
+    next: u64,
+}
+
+fn not_found() -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, "no such file or directory")
+}
+
+fn too_large() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, "file too large")
+}
+
+/// Resizes `bytes` to `len`, zero-filling, without aborting if memory runs
+/// out. Fails with `InvalidInput` if `len` is above `MAX_FILE_SIZE`.
+fn resize(bytes: &mut Vec<u8>, len: u64) -> io::Result<()> {
+    if len > MAX_FILE_SIZE {
+        return Err(too_large());
+    }
+    let len = len as usize;
+    if len > bytes.len() {
+        bytes
+            .try_reserve(len - bytes.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "out of memory"))?;
+    }
+    bytes.resize(len, 0);
+    Ok(())
+}
+
+impl Inodes {
+    fn get(&self, ino: u64) -> &Inode {
+        self.inodes.get(&ino).expect("inode is alive")
+    }
+
+    fn get_mut(&mut self, ino: u64) -> &mut Inode {
+        self.inodes.get_mut(&ino).expect("inode is alive")
+    }
+
+    fn entries(&self, ino: u64) -> io::Result<&BTreeMap<String, u64>> {
+        match &self.get(ino).data {
+            Data::Dir(entries) => Ok(entries),
+            Data::File(_) => Err(not_found()),
+        }
+    }
+
+    fn entries_mut(&mut self, ino: u64) -> io::Result<&mut BTreeMap<String, u64>> {
+        match &mut self.get_mut(ino).data {
+            Data::Dir(entries) => Ok(entries),
+            Data::File(_) => Err(not_found()),
+        }
+    }
+
+    /// Returns the inode number of the entry at `path`.
+    fn walk(&self, path: &Path) -> io::Result<u64> {
+        let mut ino = ROOT;
+        for component in path.components() {
+            if let Component::Normal(name) = component {
+                let name = name.to_str().ok_or_else(not_found)?;
+                ino = *self.entries(ino)?.get(name).ok_or_else(not_found)?;
+            }
+        }
+        Ok(ino)
+    }
+
+    /// Returns the inode number of the directory holding the entry at `path`
+    /// and the entry's name.
+    fn walk_parent<'a>(&self, path: &'a Path) -> io::Result<(u64, &'a str)> {
+        let (parent, name) = vfs::split(path)?;
+        let parent = self.walk(parent)?;
+        self.entries(parent)?;
+        Ok((parent, name))
+    }
+
+    /// Adds a new inode holding `data` to the directory `parent` as `name`.
+    fn insert(&mut self, parent: u64, name: &str, data: Data) -> io::Result<u64> {
+        if name.len() > MAX_NAME_LEN {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "name is too long"));
+        }
+        if self.entries(parent)?.contains_key(name) {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, "entry already exists"));
+        }
+        let ino = self.next;
+        self.next += 1;
+        self.inodes.insert(ino, Inode::new(data));
+        self.entries_mut(parent)?.insert(String::from(name), ino);
+        self.touch(parent);
+        Ok(ino)
+    }
+
+    /// Removes the entry `name` from the directory `parent`. The inode is
+    /// freed once no handle refers to it.
+    fn unlink(&mut self, parent: u64, name: &str) {
+        let ino = self.entries_mut(parent).ok().and_then(|entries| entries.remove(name));
+        let ino = ino.expect("entry exists");
+        self.get_mut(ino).linked = false;
+        self.release(ino);
+        self.touch(parent);
+    }
+
+    /// Frees inode `ino` if it is neither linked nor open.
+    fn release(&mut self, ino: u64) {
+        let inode = self.get(ino);
+        if !inode.linked && inode.handles == 0 {
+            self.inodes.remove(&ino);
+        }
+    }
+
+    /// Sets the modification time of inode `ino` to now.
+    fn touch(&mut self, ino: u64) {
+        self.get_mut(ino).modified = crate::clock::now().as_secs();
+    }
+}
+
+/// A RAM-backed file system, mounted at `/tmp`.
+///
+/// Entries have names of up to 255 bytes and are lost when the machine is
+/// reset. As on Unix, a removed file or directory stays usable through the
+/// handles that have it open, and its memory is freed when the last of them
+/// is closed.
+pub struct TmpFs(Mutex<Inodes>);
+
+impl fmt::Debug for TmpFs {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "TmpFs")
+    }
+}
+
+impl TmpFs {
+    /// Returns an uninitialized `TmpFs`.
+    ///
+    /// The file system must be initialized by calling `initialize()` before it
+    /// is mounted.
+    pub const fn uninitialized() -> Self {
+        TmpFs(Mutex::new(Inodes { inodes: BTreeMap::new(), next: ROOT + 1 }))
+    }
+
+    /// Creates the empty root directory.
+    pub fn initialize(&self) {
+        self.0.lock().inodes.insert(ROOT, Inode::new(Data::Dir(BTreeMap::new())));
+    }
+
+    /// Returns a handle to inode `ino`, which keeps the inode alive.
+    fn node(&'static self, inodes: &mut Inodes, ino: u64) -> Box<Node> {
+        inodes.get_mut(ino).handles += 1;
+        Box::new(Node { fs: self, ino, offset: 0 })
+    }
+}
+
+impl FileSystemT for TmpFs {
+    fn lookup(&'static self, path: &Path) -> io::Result<Box<dyn Vnode>> {
+        let mut inodes = self.0.lock();
+        let ino = inodes.walk(path)?;
+        Ok(self.node(&mut inodes, ino))
+    }
+
+    fn create_file(&'static self, path: &Path) -> io::Result<Box<dyn Vnode>> {
+        let mut inodes = self.0.lock();
+        let (parent, name) = inodes.walk_parent(path)?;
+        let ino = inodes.insert(parent, name, Data::File(Vec::new()))?;
+        Ok(self.node(&mut inodes, ino))
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        let mut inodes = self.0.lock();
+        let (parent, name) = inodes.walk_parent(path)?;
+        let ino = inodes.walk(path)?;
+        if inodes.get(ino).is_dir() {
+            return Err(io::Error::new(io::ErrorKind::Other, "entry is a directory"));
+        }
+        inodes.unlink(parent, name);
+        Ok(())
+    }
+
+    fn create_dir(&self, path: &Path) -> io::Result<()> {
+        let mut inodes = self.0.lock();
+        let (parent, name) = inodes.walk_parent(path)?;
+        inodes.insert(parent, name, Data::Dir(BTreeMap::new())).map(|_| ())
+    }
+
+    fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        let mut inodes = self.0.lock();
+        let (parent, name) = inodes.walk_parent(path)?;
+        let ino = inodes.walk(path)?;
+        match &inodes.get(ino).data {
+            Data::Dir(entries) if entries.is_empty() => {}
+            Data::Dir(_) => return Err(io::Error::new(io::ErrorKind::Other, "directory is not empty")),
+            Data::File(_) => return Err(io::Error::new(io::ErrorKind::Other, "entry is not a directory")),
+        }
+        inodes.unlink(parent, name);
+        Ok(())
+    }
+
+    /// Moves the entry at `from` to `to`. A file replaces a file at `to`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `AlreadyExists` if an entry exists at `to`
+    /// that can not be replaced, and of kind `InvalidInput` if `to` is inside
+    /// `from`.
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        if from == to {
+            return Ok(());
+        }
+        if to.starts_with(from) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "cannot move a directory into itself"));
+        }
+
+        let mut inodes = self.0.lock();
+        let (from_parent, from_name) = inodes.walk_parent(from)?;
+        let (to_parent, to_name) = inodes.walk_parent(to)?;
+        if to_name.len() > MAX_NAME_LEN {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "name is too long"));
+        }
+        let ino = inodes.walk(from)?;
+        if let Some(&existing) = inodes.entries(to_parent)?.get(to_name) {
+            if inodes.get(existing).is_dir() || inodes.get(ino).is_dir() {
+                return Err(io::Error::new(io::ErrorKind::AlreadyExists, "entry already exists"));
+            }
+            inodes.unlink(to_parent, to_name);
+        }
+
+        inodes.entries_mut(from_parent)?.remove(from_name);
+        inodes.entries_mut(to_parent)?.insert(String::from(to_name), ino);
+        inodes.touch(from_parent);
+        inodes.touch(to_parent);
+        Ok(())
+    }
+}
+
+/// An open file or directory of a `TmpFs`.
+struct Node {
+    fs: &'static TmpFs,
+    ino: u64,
+    offset: u64,
+}
+
+impl fmt::Debug for Node {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "TmpFs inode {}", self.ino)
+    }
+}
+
+impl Drop for Node {
+    fn drop(&mut self) {
+        let mut inodes = self.fs.0.lock();
+        inodes.get_mut(self.ino).handles -= 1;
+        inodes.release(self.ino);
+    }
+}
+
+impl Node {
+    /// Calls `f` with the inode's contents, or fails if it is a directory.
+    fn with_bytes<R>(&self, f: impl FnOnce(&mut Inode, &mut Vec<u8>) -> io::Result<R>) -> io::Result<R> {
+        let mut inodes = self.fs.0.lock();
+        let inode = inodes.get_mut(self.ino);
+        let mut data = core::mem::replace(&mut inode.data, Data::File(Vec::new()));
+        let result = match &mut data {
+            Data::File(bytes) => f(inode, bytes),
+            Data::Dir(_) => Err(io::Error::new(io::ErrorKind::PermissionDenied, "entry is a directory")),
+        };
+        inode.data = data;
+        result
+    }
+}
+
+impl Vnode for Node {
+    fn is_dir(&self) -> bool {
+        self.fs.0.lock().get(self.ino).is_dir()
+    }
+
+    fn stat(&self) -> io::Result<Stat> {
+        Ok(self.fs.0.lock().get(self.ino).stat())
+    }
+
+    fn open(self: Box<Self>) -> Box<dyn ProcessFileT> {
+        self
+    }
+}
+
+impl ProcessFileT for Node {
+    fn is_dir(&self) -> bool {
+        Vnode::is_dir(self)
+    }
+    fn is_readable(&self) -> bool { true }
+    fn is_writable(&self) -> bool { !Vnode::is_dir(self) }
+
+    fn size(&self) -> Option<usize> {
+        match &self.fs.0.lock().get(self.ino).data {
+            Data::File(bytes) => Some(bytes.len()),
+            Data::Dir(_) => None,
+        }
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let offset = self.offset as usize;
+        let read = self.with_bytes(|inode, bytes| {
+            let available = bytes.get(offset..).unwrap_or(&[]);
+            let len = buf.len().min(available.len());
+            buf[..len].copy_from_slice(&available[..len]);
+            inode.accessed = crate::clock::now().as_secs();
+            Ok(len)
+        })?;
+        self.offset += read as u64;
+        Ok(read)
+    }
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let offset = self.offset;
+        self.with_bytes(|inode, bytes| {
+            let end = offset.checked_add(buf.len() as u64).ok_or_else(too_large)?;
+            resize(bytes, end.max(bytes.len() as u64))?;
+            bytes[offset as usize..end as usize].copy_from_slice(buf);
+            inode.modified = crate::clock::now().as_secs();
+            inode.attributes |= ATTR_ARCHIVE;
+            Ok(())
+        })?;
+        self.offset += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        let len = self.with_bytes(|_, bytes| Ok(bytes.len() as u64))?;
+        let offset = match pos {
+            io::SeekFrom::Start(offset) => Some(offset),
+            io::SeekFrom::Current(delta) => self.offset.checked_add_signed(delta),
+            io::SeekFrom::End(delta) => len.checked_add_signed(delta),
+        };
+        let offset = offset.ok_or(io::Error::new(io::ErrorKind::InvalidInput, "seek before start of file"))?;
+        if offset > MAX_FILE_SIZE {
+            return Err(too_large());
+        }
+        self.offset = offset;
+        Ok(offset)
+    }
+
+    fn readdir(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let inodes = self.fs.0.lock();
+        let entries = match &inodes.get(self.ino).data {
+            Data::Dir(entries) => entries,
+            Data::File(_) => return Err(io::Error::new(io::ErrorKind::InvalidInput, "Not a directory")),
+        };
+        let mut names = String::from(".\n..\n");
+        for name in entries.keys() {
+            names.push_str(name);
+            names.push('\n');
+        }
+        let bytes = names.as_bytes();
+        let len = buf.len().min(bytes.len());
+        buf[..len].copy_from_slice(&bytes[..len]);
+        Ok(len)
+    }
+
+    fn stat(&self) -> io::Result<Stat> {
+        Vnode::stat(self)
+    }
+
+    fn set_times(&mut self, accessed: Option<u64>, modified: Option<u64>) -> io::Result<()> {
+        let mut inodes = self.fs.0.lock();
+        let inode = inodes.get_mut(self.ino);
+        inode.accessed = accessed.unwrap_or(inode.accessed);
+        inode.modified = modified.unwrap_or(inode.modified);
+        Ok(())
+    }
+
+    fn set_attributes(&mut self, attributes: u8) -> io::Result<()> {
+        let attributes = attributes as u64;
+        if attributes & !SETTABLE != 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "attribute can not be set"));
+        }
+        self.fs.0.lock().get_mut(self.ino).attributes = attributes;
+        Ok(())
+    }
+
+    fn set_len(&mut self, len: u64) -> io::Result<()> {
+        self.with_bytes(|inode, bytes| {
+            resize(bytes, len)?;
+            inode.modified = crate::clock::now().as_secs();
+            inode.attributes |= ATTR_ARCHIVE;
+            Ok(())
+        })
+    }
+}
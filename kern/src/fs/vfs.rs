@@ -0,0 +1,238 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::fmt;
+
+use kernel_api::Stat;
+use shim::io;
+use shim::path::{Component, Path, PathBuf};
+
+use crate::mutex::Mutex;
+use crate::process::ProcessFileT;
+
+/// A file or directory of a mounted file system.
+pub trait Vnode {
+    fn is_dir(&self) -> bool;
+
+    /// Returns the size, attributes and timestamps of the node.
+    fn stat(&self) -> io::Result<Stat>;
+
+    /// Opens the node, returning the handle that a process reads, writes or
+    /// lists it through.
+    fn open(self: Box<Self>) -> Box<dyn ProcessFileT>;
+}
+
+/// A file system that can be mounted into the `Vfs`.
+///
+/// Every path handed to a mounted file system is absolute and relative to its
+/// own root, and is free of `.` and `..` components. Mounted file systems
+/// live for as long as the kernel, so the nodes they return may borrow them.
+pub trait FileSystemT: Send + Sync {
+    /// Returns the node at `path`.
+    fn lookup(&'static self, path: &Path) -> io::Result<Box<dyn Vnode>>;
+
+    /// Creates an empty file at `path` and returns it.
+    fn create_file(&'static self, path: &Path) -> io::Result<Box<dyn Vnode>>;
+
+    /// Removes the file at `path`.
+    fn remove_file(&self, path: &Path) -> io::Result<()>;
+
+    /// Creates an empty directory at `path`.
+    fn create_dir(&self, path: &Path) -> io::Result<()>;
+
+    /// Removes the empty directory at `path`.
+    fn remove_dir(&self, path: &Path) -> io::Result<()>;
+
+    /// Moves the entry at `from` to `to`.
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+
+    /// Writes any cached changes back to the backing store.
+    fn sync(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+struct Mount {
+    point: PathBuf,
+    fs: &'static dyn FileSystemT,
+}
+
+/// The kernel's single file system namespace, made of the file systems
+/// mounted into it.
+///
+/// A path belongs to the file system mounted at its longest leading mount
+/// point. Mount points other than the root must be existing directories, as
+/// on Unix, so that listing their parent shows them.
+pub struct Vfs(Mutex<Vec<Mount>>);
+
+impl fmt::Debug for Vfs {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mounts = self.0.lock();
+        f.debug_list().entries(mounts.iter().map(|mount| &mount.point)).finish()
+    }
+}
+
+/// Returns `path` with its `.` and `..` components resolved.
+///
+/// # Errors
+///
+/// Returns an error of kind `InvalidInput` if `path` is not absolute.
+pub fn normalize(path: &Path) -> io::Result<PathBuf> {
+    if !path.has_root() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "path is not absolute"));
+    }
+    let mut normal = PathBuf::from("/");
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => normal.push(name),
+            Component::ParentDir => {
+                normal.pop();
+            }
+            _ => {}
+        }
+    }
+    Ok(normal)
+}
+
+/// Splits `path` into its parent directory and its last component.
+///
+/// # Errors
+///
+/// Returns an error of kind `InvalidInput` if `path` has no last component,
+/// like `/`.
+pub fn split(path: &Path) -> io::Result<(&Path, &str)> {
+    match (path.parent(), path.file_name().and_then(|name| name.to_str())) {
+        (Some(parent), Some(name)) => Ok((parent, name)),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "path has no file name")),
+    }
+}
+
+impl Vfs {
+    /// Returns a `Vfs` with nothing mounted.
+    ///
+    /// A file system must be mounted at `/` by calling `initialize()` before
+    /// any path is looked up.
+    pub const fn uninitialized() -> Self {
+        Vfs(Mutex::new(Vec::new()))
+    }
+
+    /// Mounts the SD card's FAT32 file system at `/` and `TMPFS` at `/tmp`.
+    /// `/tmp` is created on the SD card the first time.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `FILESYSTEM` is not initialized or a mount fails.
+    pub fn initialize(&self) {
+        self.mount("/", &crate::FILESYSTEM).expect("failed to mount root file system");
+        match self.create_dir("/tmp") {
+            Ok(()) => crate::FILESYSTEM.sync().expect("failed to sync fs"),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+            Err(e) => panic!("failed to create /tmp: {:?}", e),
+        }
+        self.mount("/tmp", &crate::TMPFS).expect("failed to mount /tmp");
+    }
+
+    /// Mounts `fs` at `point`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `AlreadyExists` if a file system is already
+    /// mounted at `point`, and of kind `InvalidInput` if `point` is not an
+    /// existing directory. The first file system must be mounted at `/`.
+    pub fn mount<P: AsRef<Path>>(&self, point: P, fs: &'static dyn FileSystemT) -> io::Result<()> {
+        let point = normalize(point.as_ref())?;
+        let is_root = point.as_path() == Path::new("/");
+        if self.is_mount_point(&point) {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, "already a mount point"));
+        }
+        if self.0.lock().is_empty() != is_root {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "root must be mounted first"));
+        }
+        if !is_root && !self.open(&point)?.is_dir() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "mount point is not a directory"));
+        }
+        self.0.lock().push(Mount { point, fs });
+        Ok(())
+    }
+
+    fn is_mount_point(&self, path: &Path) -> bool {
+        self.0.lock().iter().any(|mount| mount.point.as_path() == path)
+    }
+
+    /// Returns the file system that `path` belongs to and `path` relative to
+    /// that file system's root.
+    fn resolve(&self, path: &Path) -> io::Result<(&'static dyn FileSystemT, PathBuf)> {
+        let path = normalize(path)?;
+        let mounts = self.0.lock();
+        let mount = mounts
+            .iter()
+            .filter(|mount| path.starts_with(&mount.point))
+            .max_by_key(|mount| mount.point.components().count())
+            .ok_or(io::Error::new(io::ErrorKind::NotFound, "no file system is mounted"))?;
+        let relative = path.strip_prefix(&mount.point).expect("path starts with mount point");
+        Ok((mount.fs, Path::new("/").join(relative)))
+    }
+
+    /// Returns `path` normalized, or an error if a file system is mounted at
+    /// it. Mount points can not be removed or moved.
+    fn unmounted(&self, path: &Path) -> io::Result<PathBuf> {
+        let path = normalize(path)?;
+        if self.is_mount_point(&path) {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, "path is a mount point"));
+        }
+        Ok(path)
+    }
+
+    /// Returns the node at `path`.
+    pub fn open<P: AsRef<Path>>(&self, path: P) -> io::Result<Box<dyn Vnode>> {
+        let (fs, path) = self.resolve(path.as_ref())?;
+        fs.lookup(&path)
+    }
+
+    /// Creates an empty file at `path` and returns it.
+    pub fn create_file<P: AsRef<Path>>(&self, path: P) -> io::Result<Box<dyn Vnode>> {
+        let (fs, path) = self.resolve(path.as_ref())?;
+        fs.create_file(&path)
+    }
+
+    /// Removes the file at `path`.
+    pub fn remove_file<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let (fs, path) = self.resolve(path.as_ref())?;
+        fs.remove_file(&path)
+    }
+
+    /// Creates an empty directory at `path`.
+    pub fn create_dir<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let (fs, path) = self.resolve(path.as_ref())?;
+        fs.create_dir(&path)
+    }
+
+    /// Removes the empty directory at `path`.
+    pub fn remove_dir<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = self.unmounted(path.as_ref())?;
+        let (fs, path) = self.resolve(&path)?;
+        fs.remove_dir(&path)
+    }
+
+    /// Moves the entry at `from` to `to`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `InvalidInput` if `from` and `to` belong to
+    /// different file systems.
+    pub fn rename<P: AsRef<Path>, Q: AsRef<Path>>(&self, from: P, to: Q) -> io::Result<()> {
+        let from = self.unmounted(from.as_ref())?;
+        let (from_fs, from) = self.resolve(&from)?;
+        let (to_fs, to) = self.resolve(to.as_ref())?;
+        if !core::ptr::addr_eq(from_fs, to_fs) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "cannot rename across file systems"));
+        }
+        from_fs.rename(&from, &to)
+    }
+
+    /// Writes the cached changes of every mounted file system back to its
+    /// backing store, returning the first error.
+    pub fn sync(&self) -> io::Result<()> {
+        let filesystems: Vec<_> = self.0.lock().iter().map(|mount| mount.fs).collect();
+        filesystems.iter().map(|fs| fs.sync()).fold(Ok(()), |result, synced| result.and(synced))
+    }
+}
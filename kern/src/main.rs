@@ -24,6 +24,7 @@ extern crate heap;
 // import files
 mod init;
 mod allocator;
+mod clock;
 mod console;
 mod fs;
 mod logger;
@@ -38,6 +39,8 @@ mod vm;
 
 use aarch64::with_fiq_enabled;
 use allocator::Allocator;
+use fs::tmpfs::TmpFs;
+use fs::vfs::Vfs;
 use fs::FileSystem;
 use net::uspi::Usb;
 use net::GlobalEthernetDriver;
@@ -48,6 +51,8 @@ use vm::VMManager;
 #[global_allocator]
 static ALLOCATOR: Allocator = Allocator::uninitialized();
 static FILESYSTEM: FileSystem = FileSystem::uninitialized();
+static TMPFS: TmpFs = TmpFs::uninitialized();
+static VFS: Vfs = Vfs::uninitialized();
 static SCHEDULER: GlobalScheduler = GlobalScheduler::uninitialized();
 static VMM: VMManager = VMManager::uninitialized();
 static USB: Usb = Usb::uninitialized();
@@ -83,16 +88,21 @@ unsafe fn kmain() -> ! {
     log_layout();
     ALLOCATOR.initialize();
     FILESYSTEM.initialize();
+    TMPFS.initialize();
+    VFS.initialize();
     VMM.initialize();
     SCHEDULER.initialize();
     
     // Network initialization
     with_fiq_enabled(|| {
         USB.initialize();
+        if USB.is_eth_available() {
+            while !USB.is_eth_link_up() {}
+            debug!("USB Ethernet link up");
+        } else {
+            warn!("USB Ethernet not available, using a loopback device");
+        }
         ETHERNET.initialize();
-        assert!(USB.is_eth_available(), "USB Ethernet not available");
-        while !USB.is_eth_link_up() {}
-        debug!("USB Ethernet link up");
     });
     init::initialize_app_cores();
     per_core_main()
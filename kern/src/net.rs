@@ -1,4 +1,5 @@
 ///! Network device that wraps USPi in smoltcp abstraction
+pub mod config;
 pub mod uspi;
 
 use aarch64::affinity;
@@ -11,21 +12,25 @@ use core::convert::TryInto;
 use core::fmt;
 use core::time::Duration;
 
-use smoltcp::iface::{EthernetInterfaceBuilder, NeighborCache};
+use smoltcp::dhcp::Dhcpv4Client;
+use smoltcp::iface::{EthernetInterfaceBuilder, NeighborCache, Routes};
 use smoltcp::phy::{self, Device, DeviceCapabilities};
-use smoltcp::socket::{SocketHandle, SocketRef, TcpSocketBuffer};
+use smoltcp::socket::{RawPacketMetadata, RawSocketBuffer, SocketHandle, SocketRef, TcpSocketBuffer, TcpState, UdpPacketMetadata, UdpSocketBuffer};
 use smoltcp::time::Instant;
-use smoltcp::wire::{IpAddress, IpCidr};
+use smoltcp::wire::{EthernetAddress, IpAddress, IpCidr, Ipv4Address, Ipv4Cidr};
 
 use crate::console::kprint;
 use crate::mutex::Mutex;
-use crate::param::MTU;
+use crate::param::{DHCP_FALLBACK_TIMEOUT, MTU};
 use crate::percore::get_preemptive_counter;
 use crate::USB;
 
+use self::config::IpConfig;
+
 // We always use owned buffer as internal storage
 pub type SocketSet = smoltcp::socket::SocketSet<'static, 'static, 'static>;
 pub type TcpSocket = smoltcp::socket::TcpSocket<'static>;
+pub type UdpSocket = smoltcp::socket::UdpSocket<'static, 'static>;
 pub type EthernetInterface<T> = smoltcp::iface::EthernetInterface<'static, 'static, 'static, T>;
 
 /// 8-byte aligned `u8` slice.
@@ -139,13 +144,122 @@ impl phy::TxToken for TxToken {
     }
 }
 
-/// Creates and returns a new ethernet interface using `UsbEthernet` struct.
-pub fn create_interface() -> EthernetInterface<UsbEthernet> {
+/// The network device behind `EthernetDriver`: the USB Ethernet adapter or,
+/// when there is none, like under QEMU, a software loopback device that
+/// hands every sent frame back to the interface.
+#[derive(Debug)]
+pub enum NetDevice {
+    Usb(UsbEthernet),
+    Loopback(phy::Loopback),
+}
+
+impl NetDevice {
+    /// Returns the USB Ethernet device if an adapter is available, and the
+    /// loopback device otherwise.
+    pub fn probe() -> NetDevice {
+        if USB.is_eth_available() {
+            NetDevice::Usb(UsbEthernet)
+        } else {
+            NetDevice::Loopback(phy::Loopback::new())
+        }
+    }
+
+    /// Returns the MAC address of the device. The loopback device uses a
+    /// locally administered address.
+    pub fn ethernet_addr(&self) -> EthernetAddress {
+        match self {
+            NetDevice::Usb(_) => USB.get_eth_addr(),
+            NetDevice::Loopback(_) => EthernetAddress([0x02, 0x00, 0x00, 0x00, 0x00, 0x01]),
+        }
+    }
+}
+
+impl<'a> Device<'a> for NetDevice {
+    type RxToken = NetRxToken;
+    type TxToken = NetTxToken<'a>;
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        match self {
+            NetDevice::Usb(device) => device.capabilities(),
+            NetDevice::Loopback(device) => device.capabilities(),
+        }
+    }
+
+    fn receive(&'a mut self) -> Option<(Self::RxToken, Self::TxToken)> {
+        match self {
+            NetDevice::Usb(device) => device
+                .receive()
+                .map(|(rx, tx)| (NetRxToken::Usb(rx), NetTxToken::Usb(tx))),
+            NetDevice::Loopback(device) => device
+                .receive()
+                .map(|(rx, tx)| (NetRxToken::Loopback(rx), NetTxToken::Loopback(tx))),
+        }
+    }
+
+    fn transmit(&'a mut self) -> Option<Self::TxToken> {
+        match self {
+            NetDevice::Usb(device) => device.transmit().map(NetTxToken::Usb),
+            NetDevice::Loopback(device) => device.transmit().map(NetTxToken::Loopback),
+        }
+    }
+}
+
+pub enum NetRxToken {
+    Usb(RxToken),
+    Loopback(<phy::Loopback as Device<'static>>::RxToken),
+}
+
+impl phy::RxToken for NetRxToken {
+    fn consume<R, F>(self, timestamp: Instant, f: F) -> smoltcp::Result<R>
+    where
+        F: FnOnce(&mut [u8]) -> smoltcp::Result<R>,
+    {
+        match self {
+            NetRxToken::Usb(token) => token.consume(timestamp, f),
+            NetRxToken::Loopback(token) => token.consume(timestamp, f),
+        }
+    }
+}
+
+pub enum NetTxToken<'a> {
+    Usb(TxToken),
+    Loopback(<phy::Loopback as Device<'a>>::TxToken),
+}
+
+impl<'a> phy::TxToken for NetTxToken<'a> {
+    fn consume<R, F>(self, timestamp: Instant, len: usize, f: F) -> smoltcp::Result<R>
+    where
+        F: FnOnce(&mut [u8]) -> smoltcp::Result<R>,
+    {
+        match self {
+            NetTxToken::Usb(token) => token.consume(timestamp, len, f),
+            NetTxToken::Loopback(token) => token.consume(timestamp, len, f),
+        }
+    }
+}
+
+/// Returns the address the interface falls back to when no DHCP server
+/// answers.
+fn link_local_addr() -> Ipv4Cidr {
+    Ipv4Cidr::new(Ipv4Address::new(169, 254, 32, 10), 16)
+}
+
+/// Returns the placeholder address the interface holds while DHCP has no
+/// lease.
+fn unspecified_addr() -> Ipv4Cidr {
+    Ipv4Cidr::new(Ipv4Address::UNSPECIFIED, 0)
+}
+
+/// Creates and returns a new ethernet interface on top of `device`.
+///
+/// The first address of the interface is the one set by `config`, or a
+/// placeholder for DHCP to replace. The second one is `127.0.0.1/8`.
+pub fn create_interface(device: NetDevice, config: IpConfig) -> EthernetInterface<NetDevice> {
     // Lab 5 2.B
     // Finish create_interface() in kern/src/net.rs. You should use smoltcp’s EthernetInterfaceBuilder. 
     // When creating the interface, use UsbEthernet as an inner physical device and MAC address obtained from USPi as Ethernet address of the interface:
-    let mac = USB.get_eth_addr();
-    kprint!("USB MAC: ");
+    let mac = device.ethernet_addr();
+    kprint!("MAC: ");
     for (i, byte) in mac.0.iter().enumerate() {
         if i != 0 {
             kprint!(":");
@@ -154,44 +268,186 @@ pub fn create_interface() -> EthernetInterface<UsbEthernet> {
         kprint!("{:02x}", byte);
     }
     kprint!("\n");
-    let usb_ethernet = UsbEthernet;
-    use smoltcp::wire::Ipv4Address;
-    let builder = EthernetInterfaceBuilder::new(usb_ethernet);
+    let builder = EthernetInterfaceBuilder::new(device);
     // Then, add an empty neighbor cache using BTreeMap. Finally, add two CIDR blocks as its IP addresses: 169.254.32.10/16 and 127.0.0.1/8. When you are done, implement EthernetDriver::new() using create_interface().
     let neighbor_cache = NeighborCache::new(BTreeMap::new());
-    let ip_addr1 = IpCidr::new(IpAddress::from(Ipv4Address::new(169, 254, 32, 10)), 16);
+    let mut routes = Routes::new(BTreeMap::new());
+    let ip_addr1 = match config {
+        IpConfig::Dhcp => IpCidr::Ipv4(unspecified_addr()),
+        IpConfig::Static { address, gateway } => {
+            if let Some(gateway) = gateway {
+                routes.add_default_ipv4_route(gateway).expect("routes are growable");
+            }
+            IpCidr::Ipv4(address)
+        }
+    };
     let ip_addr2 = IpCidr::new(IpAddress::from(Ipv4Address::new(127, 0, 0, 1)), 8);
     let ethernet = builder
         .ethernet_addr(mac)
         .neighbor_cache(neighbor_cache)
         .ip_addrs(vec![ip_addr1, ip_addr2])
+        .routes(routes)
         .finalize();
     ethernet
 }
 
+/// Returns the current time as a smoltcp `Instant`.
+fn now() -> Instant {
+    Instant::from_millis(current_time().as_millis() as i64)
+}
+
+/// A DHCP client together with the lease it has configured.
+struct Dhcp {
+    client: Dhcpv4Client,
+    /// When to fall back to the link-local address if there is still no
+    /// lease. `None` once an address is configured.
+    fallback_at: Option<Instant>,
+}
+
+impl Dhcp {
+    fn new(socket_set: &mut SocketSet, now: Instant) -> Dhcp {
+        let rx_buffer = RawSocketBuffer::new(vec![RawPacketMetadata::EMPTY; 1], vec![0; 900]);
+        let tx_buffer = RawSocketBuffer::new(vec![RawPacketMetadata::EMPTY; 1], vec![0; 600]);
+        Dhcp {
+            client: Dhcpv4Client::new(socket_set, rx_buffer, tx_buffer, now),
+            fallback_at: Some(now + DHCP_FALLBACK_TIMEOUT.into()),
+        }
+    }
+
+    /// Runs the client and applies the address and default route of any
+    /// lease it acquires or renews. The client renews a lease every minute,
+    /// well before any lease a server hands out expires, and starts over with
+    /// the placeholder address when a renewal goes unanswered.
+    fn poll(&mut self, ethernet: &mut EthernetInterface<NetDevice>, socket_set: &mut SocketSet, now: Instant) {
+        let config = match self.client.poll(ethernet, socket_set, now) {
+            Ok(config) => config,
+            Err(e) => {
+                debug!("DHCP poll error: {:?}", e);
+                None
+            }
+        };
+
+        if let Some(config) = config {
+            if let Some(address) = config.address {
+                if address == unspecified_addr() {
+                    info!("DHCP lease lost");
+                    self.fallback_at = Some(now + DHCP_FALLBACK_TIMEOUT.into());
+                } else {
+                    info!("DHCP address: {}", address);
+                    self.fallback_at = None;
+                }
+                set_address(ethernet, address);
+            }
+            ethernet.routes_mut().update(|routes| {
+                routes.remove(&IpCidr::Ipv4(unspecified_addr()));
+            });
+            if let Some(router) = config.router {
+                info!("DHCP gateway: {}", router);
+                ethernet.routes_mut().add_default_ipv4_route(router).expect("routes are growable");
+            }
+        }
+
+        if self.fallback_at.is_some_and(|fallback_at| now >= fallback_at) {
+            info!("No DHCP server answered, using {}", link_local_addr());
+            self.fallback_at = None;
+            set_address(ethernet, link_local_addr());
+        }
+    }
+
+    /// Returns how long the client can wait before it is polled again.
+    fn poll_delay(&self, now: Instant) -> Duration {
+        let delay = self.client.next_poll(now);
+        let delay = match self.fallback_at {
+            Some(fallback_at) if fallback_at > now => delay.min(fallback_at - now),
+            Some(_) => smoltcp::time::Duration::from_millis(0),
+            None => delay,
+        };
+        delay.into()
+    }
+}
+
+/// Replaces the first address of `ethernet`, the one that DHCP manages.
+fn set_address(ethernet: &mut EthernetInterface<NetDevice>, address: Ipv4Cidr) {
+    ethernet.update_ip_addrs(|addrs| {
+        if let Some(addr) = addrs.iter_mut().next() {
+            *addr = IpCidr::Ipv4(address);
+        }
+    });
+}
+
 const PORT_MAP_SIZE: usize = 65536 / 64;
 
+/// The sockets that take the connections of a listening port.
+struct Listener {
+    /// The socket of the listening socket descriptor
+    owner: SocketHandle,
+    /// More sockets listening on the port, so that connections can be
+    /// established before they are accepted
+    pool: Vec<SocketHandle>,
+}
+
+/// Returns whether a connection of `socket` is established, or was and the
+/// remote end has since closed its half.
+fn is_connected(socket: &TcpSocket) -> bool {
+    socket.may_send() || socket.may_recv()
+}
+
+/// Returns whether `socket` waits for its connection to be set up.
+fn is_connecting(socket: &TcpSocket) -> bool {
+    matches!(socket.state(), TcpState::Listen | TcpState::SynSent | TcpState::SynReceived)
+}
+
+/// Returns whether receiving on `socket` does not have to wait: data has
+/// arrived, or the connection is closed and none will.
+pub fn can_recv_or_closed(socket: &TcpSocket) -> bool {
+    socket.can_recv() || !(socket.may_recv() || is_connecting(socket))
+}
+
+/// Returns whether sending on `socket` does not have to wait: there is room
+/// in its buffer, or the connection is closed.
+pub fn can_send_or_closed(socket: &TcpSocket) -> bool {
+    socket.can_send() || !(socket.may_send() || is_connecting(socket))
+}
+
 pub struct EthernetDriver {
     /// A set of sockets
     socket_set: SocketSet,
     /// Bitmap to track the port usage
     port_map: [u64; PORT_MAP_SIZE],
     /// Internal ethernet interface
-    ethernet: EthernetInterface<UsbEthernet>,
+    ethernet: EthernetInterface<NetDevice>,
+    /// The DHCP client, unless the address is static
+    dhcp: Option<Dhcp>,
+    /// The sockets of each listening port
+    listeners: BTreeMap<u16, Listener>,
 }
 
 impl EthernetDriver {
-    /// Creates a fresh ethernet driver.
+    /// Creates a fresh ethernet driver on the USB Ethernet adapter, configured
+    /// by `NET_CONFIG_PATH`, or on the loopback device if there is no adapter.
     fn new() -> EthernetDriver {
         // Lab 5 2.B
         // When you are done, implement EthernetDriver::new() using create_interface().
-        let ethernet = create_interface();
-        let socket_set = SocketSet::new(vec![]);
+        let device = NetDevice::probe();
+        // Nothing but this kernel answers on the loopback device
+        let config = match device {
+            NetDevice::Usb(_) => IpConfig::load(),
+            NetDevice::Loopback(_) => IpConfig::Static { address: link_local_addr(), gateway: None },
+        };
+        info!("Network device: {:?}, IP configuration: {:?}", device, config);
+        let ethernet = create_interface(device, config);
+        let mut socket_set = SocketSet::new(vec![]);
+        let dhcp = match config {
+            IpConfig::Dhcp => Some(Dhcp::new(&mut socket_set, now())),
+            IpConfig::Static { .. } => None,
+        };
         let port_map = [0; PORT_MAP_SIZE];
         EthernetDriver {
             socket_set,
             port_map,
             ethernet,
+            dhcp,
+            listeners: BTreeMap::new(),
         }
     }
 
@@ -204,15 +460,22 @@ impl EthernetDriver {
                 error!("Ethernet poll error: {:?}", e);
             }
         }
+        if let Some(dhcp) = self.dhcp.as_mut() {
+            dhcp.poll(&mut self.ethernet, &mut self.socket_set, timestamp);
+        }
     }
 
     /// Returns an advisory wait time to call `poll()` the next time.
     /// See also `smoltcp::iface::EthernetInterface::poll_delay()`.
     fn poll_delay(&mut self, timestamp: Instant) -> Duration {
-        if let Some(delay) = self.ethernet.poll_delay(&mut self.socket_set, timestamp) {
+        let delay = if let Some(delay) = self.ethernet.poll_delay(&mut self.socket_set, timestamp) {
             delay.into()
         } else {
             Duration::from_millis(10) // default delay?
+        };
+        match &self.dhcp {
+            Some(dhcp) => delay.min(dhcp.poll_delay(timestamp)),
+            None => delay,
         }
     }
 
@@ -258,6 +521,11 @@ impl EthernetDriver {
         self.socket_set.get::<TcpSocket>(handle)
     }
 
+    /// Finds a UDP socket with a `SocketHandle`.
+    pub fn get_udp_socket(&mut self, handle: SocketHandle) -> SocketRef<'_, UdpSocket> {
+        self.socket_set.get::<UdpSocket>(handle)
+    }
+
     /// This function creates a new TCP socket, adds it to the internal socket
     /// set, and returns the `SocketHandle` of the new socket.
     pub fn add_socket(&mut self) -> SocketHandle {
@@ -267,6 +535,108 @@ impl EthernetDriver {
         self.socket_set.add(tcp_socket)
     }
 
+    /// This function creates a new UDP socket, adds it to the internal socket
+    /// set, and returns the `SocketHandle` of the new socket. Each direction
+    /// buffers up to 16 datagrams.
+    pub fn add_udp_socket(&mut self) -> SocketHandle {
+        let rx_buffer = UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY; 16], vec![0; 16384]);
+        let tx_buffer = UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY; 16], vec![0; 16384]);
+        let udp_socket = UdpSocket::new(rx_buffer, tx_buffer);
+        self.socket_set.add(udp_socket)
+    }
+
+    /// Creates a new TCP socket listening on `port`.
+    fn add_listening_socket(&mut self, port: u16) -> SocketHandle {
+        let handle = self.add_socket();
+        self.get_socket(handle).listen(port).expect("a new socket can listen");
+        handle
+    }
+
+    /// Puts the socket `handle` in listen state on `port`, along with
+    /// `backlog - 1` more sockets, so that up to `backlog` connections can be
+    /// established before `accept()` takes them.
+    pub fn listen(&mut self, handle: SocketHandle, port: u16, backlog: usize) -> smoltcp::Result<()> {
+        if self.listeners.contains_key(&port) {
+            return Err(smoltcp::Error::Illegal);
+        }
+        self.get_socket(handle).listen(port)?;
+        let pool = (1..backlog).map(|_| self.add_listening_socket(port)).collect();
+        self.listeners.insert(port, Listener { owner: handle, pool });
+        Ok(())
+    }
+
+    /// Returns whether a socket passed to `listen()` listens on `port`.
+    pub fn is_listening_port(&self, port: u16) -> bool {
+        self.listeners.contains_key(&port)
+    }
+
+    /// Takes an established connection off the port that the socket
+    /// `*handle` listens on, and arms a new socket in its place. If the
+    /// connection is the one of `*handle` itself, `*handle` is replaced by the
+    /// new socket, so that it keeps listening.
+    ///
+    /// Returns `Error::Illegal` if `*handle` was not passed to `listen()`,
+    /// and `Error::Exhausted` if no connection is established.
+    pub fn accept(&mut self, handle: &mut SocketHandle) -> smoltcp::Result<SocketHandle> {
+        let (port, sockets) = self
+            .listeners
+            .iter()
+            .find(|(_, listener)| listener.owner == *handle)
+            .map(|(&port, listener)| (port, listener.pool.clone()))
+            .ok_or(smoltcp::Error::Illegal)?;
+
+        // Re-arm the sockets whose connections were reset before acceptance
+        for &pooled in &sockets {
+            let mut socket = self.get_socket(pooled);
+            if !socket.is_open() {
+                socket.listen(port)?;
+            }
+        }
+
+        let connection = core::iter::once(*handle)
+            .chain(sockets)
+            .find(|&candidate| is_connected(&self.get_socket(candidate)))
+            .ok_or(smoltcp::Error::Exhausted)?;
+        let fresh = self.add_listening_socket(port);
+        let listener = self.listeners.get_mut(&port).expect("listener exists");
+        if connection == listener.owner {
+            listener.owner = fresh;
+            *handle = fresh;
+        } else {
+            listener.pool.retain(|&pooled| pooled != connection);
+            listener.pool.push(fresh);
+        }
+        Ok(connection)
+    }
+
+    /// Returns whether `accept()` on `handle` does not have to wait for a
+    /// connection, either because one is established or because `handle` is
+    /// not listening.
+    pub fn can_accept(&mut self, handle: SocketHandle) -> bool {
+        let sockets = match self.listeners.values().find(|listener| listener.owner == handle) {
+            Some(listener) => listener.pool.clone(),
+            None => return true,
+        };
+        core::iter::once(handle)
+            .chain(sockets)
+            .any(|candidate| is_connected(&self.get_socket(candidate)))
+    }
+
+    /// Stops listening with the socket `handle`, releasing the sockets armed
+    /// along with it and aborting the connections nobody accepted. Does
+    /// nothing if `handle` was not passed to `listen()`.
+    pub fn unlisten(&mut self, handle: SocketHandle) {
+        let port = match self.listeners.iter().find(|(_, listener)| listener.owner == handle) {
+            Some((&port, _)) => port,
+            None => return,
+        };
+        let listener = self.listeners.remove(&port).expect("listener exists");
+        for pooled in listener.pool {
+            self.get_socket(pooled).abort();
+            self.release(pooled);
+        }
+    }
+
     /// Releases a socket from the internal socket set.
     pub fn release(&mut self, handle: SocketHandle) {
         self.socket_set.release(handle);
@@ -348,6 +718,29 @@ impl GlobalEthernetDriver {
         f(&mut socket)
     }
 
+    pub fn add_udp_socket(&self) -> SocketHandle {
+        self.0
+            .lock()
+            .as_mut()
+            .expect("Uninitialized EthernetDriver")
+            .add_udp_socket()
+    }
+
+    /// Enters a critical region and execute the provided closure with a mutable
+    /// reference to the UDP socket.
+    pub fn with_udp_socket<F, R>(&self, handle: SocketHandle, f: F) -> R
+    where
+        F: FnOnce(&mut SocketRef<'_, UdpSocket>) -> R,
+    {
+        let mut guard = self.0.lock();
+        let mut socket = guard
+            .as_mut()
+            .expect("Uninitialized EthernetDriver")
+            .get_udp_socket(handle);
+
+        f(&mut socket)
+    }
+
     /// Enters a critical region and execute the provided closure with a mutable
     /// reference to the inner ethernet driver.
     pub fn critical<F, R>(&self, f: F) -> R
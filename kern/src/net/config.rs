@@ -0,0 +1,109 @@
+use alloc::vec;
+use core::str::FromStr;
+
+use shim::io;
+use smoltcp::wire::{IpAddress, Ipv4Address, Ipv4Cidr};
+
+use crate::param::NET_CONFIG_PATH;
+use crate::VFS;
+
+/// How the ethernet interface gets its IPv4 address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpConfig {
+    /// Lease an address from a DHCP server, falling back to the link-local
+    /// address when no server answers.
+    Dhcp,
+    /// Use a fixed address and, when given, route everything off the subnet
+    /// through `gateway`.
+    Static {
+        address: Ipv4Cidr,
+        gateway: Option<Ipv4Address>,
+    },
+}
+
+fn invalid(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+impl IpConfig {
+    /// Parses a network configuration file.
+    ///
+    /// Each line holds a key and a value separated by whitespace, and `#`
+    /// starts a comment. The keys are:
+    ///
+    /// - `address`: the static address, like `10.0.0.5` or `10.0.0.5/24`
+    /// - `netmask`: the netmask, like `255.255.255.0`, if `address` has no
+    ///   prefix length
+    /// - `gateway`: the default gateway, like `10.0.0.1`
+    ///
+    /// A file without an `address` selects DHCP.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `InvalidData` for an unknown key or a value
+    /// that does not parse.
+    pub fn parse(text: &str) -> io::Result<IpConfig> {
+        let mut address = None;
+        let mut netmask = None;
+        let mut gateway = None;
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let mut words = line.split_whitespace();
+            let key = words.next().unwrap_or("");
+            let value = words.next().ok_or(invalid("missing value"))?;
+            if words.next().is_some() {
+                return Err(invalid("trailing characters"));
+            }
+            match key {
+                "address" => address = Some(value),
+                "netmask" => netmask = Some(Ipv4Address::from_str(value).map_err(|_| invalid("bad netmask"))?),
+                "gateway" => gateway = Some(Ipv4Address::from_str(value).map_err(|_| invalid("bad gateway"))?),
+                _ => return Err(invalid("unknown key")),
+            }
+        }
+
+        let address = match (address, netmask) {
+            (None, _) => return Ok(IpConfig::Dhcp),
+            (Some(cidr), None) if cidr.contains('/') => {
+                Ipv4Cidr::from_str(cidr).map_err(|_| invalid("bad address"))?
+            }
+            (Some(_), None) => return Err(invalid("address needs a prefix length or a netmask")),
+            (Some(addr), Some(mask)) => {
+                let addr = Ipv4Address::from_str(addr).map_err(|_| invalid("bad address"))?;
+                let prefix_len = IpAddress::Ipv4(mask).to_prefix_len().ok_or(invalid("bad netmask"))?;
+                Ipv4Cidr::new(addr, prefix_len)
+            }
+        };
+        Ok(IpConfig::Static { address, gateway })
+    }
+
+    /// Reads the configuration from `NET_CONFIG_PATH`. DHCP is used if the
+    /// file does not exist or is invalid.
+    pub fn load() -> IpConfig {
+        match Self::read() {
+            Ok(config) => config,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => IpConfig::Dhcp,
+            Err(e) => {
+                warn!("Ignoring {}: {:?}", NET_CONFIG_PATH, e);
+                IpConfig::Dhcp
+            }
+        }
+    }
+
+    fn read() -> io::Result<IpConfig> {
+        let mut file = VFS.open(NET_CONFIG_PATH)?.open();
+        let mut data = vec![0u8; file.size().unwrap_or(0)];
+        let mut filled = 0;
+        while filled < data.len() {
+            match file.read(&mut data[filled..])? {
+                0 => break,
+                n => filled += n,
+            }
+        }
+        let text = core::str::from_utf8(&data[..filled]).map_err(|_| invalid("not UTF-8"))?;
+        Self::parse(text)
+    }
+}
@@ -73,10 +73,14 @@ mod inner {
 
     impl USPi {
         /// The caller should assure that this function is called only once
-        /// during the lifetime of the kernel.
-        pub unsafe fn initialize() -> Self {
-            assert!(USPiInitialize() != 0);
-            USPi(())
+        /// during the lifetime of the kernel. Returns `None` if the USB host
+        /// controller fails to initialize, like under QEMU.
+        pub unsafe fn initialize() -> Option<Self> {
+            if USPiInitialize() != 0 {
+                Some(USPi(()))
+            } else {
+                None
+            }
         }
 
         /// Returns whether ethernet is available on RPi
@@ -116,8 +120,9 @@ mod inner {
             }
         }
 
-        /// A wrapper function to `TimerStartKernelHandler`.
-        pub fn start_kernel_timer(&mut self, delay: Duration, handler: TKernelTimerHandler) {
+        /// A wrapper function to `TimerStartKernelHandler`. The timer does not
+        /// depend on the USB host controller.
+        pub fn start_kernel_timer(delay: Duration, handler: TKernelTimerHandler) {
             trace!(
                 "Core {}, delay {:?}, handler {:?}",
                 aarch64::affinity(),
@@ -299,10 +304,12 @@ impl Usb {
         Usb(Mutex::new(None))
     }
 
+    /// Initializes the USB host controller. If that fails, `Usb` stays
+    /// uninitialized and reports that ethernet is not available.
     pub fn initialize(&self) {
         let mut inner = self.0.lock();
         if let None = *inner {
-            *inner = Some(unsafe { USPi::initialize() });
+            *inner = unsafe { USPi::initialize() };
         }
     }
 
@@ -310,8 +317,7 @@ impl Usb {
         self.0
             .lock()
             .as_mut()
-            .expect("USB not initialized")
-            .is_eth_available()
+            .map_or(false, |uspi| uspi.is_eth_available())
     }
 
     pub fn get_eth_addr(&self) -> EthernetAddress {
@@ -349,10 +355,7 @@ impl Usb {
     }
 
     pub fn start_kernel_timer(&self, delay: Duration, handler: TKernelTimerHandler) {
-        self.0
-            .lock()
-            .as_mut()
-            .expect("USB not initialized")
-            .start_kernel_timer(delay, handler)
+        let _guard = self.0.lock();
+        USPi::start_kernel_timer(delay, handler)
     }
 }
@@ -26,6 +26,9 @@ pub const KERN_STACK_BASE: usize = 0x80_000;
 pub const KERN_STACK_ALIGN: usize = PAGE_ALIGN;
 pub const KERN_STACK_SIZE: usize = PAGE_SIZE;
 
+/// The Unix time the wall clock reads at boot, 2025-01-01 00:00:00 UTC.
+pub const BOOT_UNIX_TIME: u64 = 1_735_689_600;
+
 /// The `tick` time.
 #[cfg(feature = "transmit")]
 pub const TICK: Duration = Duration::from_millis(1);
@@ -40,6 +43,22 @@ pub const USPI_TIMER_HZ: usize = 10;
 pub const USPI_FRAME_BUFFER_SIZE: u32 = 1600;
 pub const MTU: u32 = 1500;
 
+/// The file on the SD card that sets a static IPv4 address; see
+/// `net::config::IpConfig::parse`. Without it, the address comes from DHCP.
+pub const NET_CONFIG_PATH: &str = "/net.cfg";
+
+/// How long to wait for a DHCP lease before using the link-local address.
+pub const DHCP_FALLBACK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The most connections a listening port can hold before they are accepted.
+pub const MAX_LISTEN_BACKLOG: usize = 16;
+
+/// The longest path a system call accepts, in bytes, without its terminator.
+pub const MAX_PATH_LEN: usize = 255;
+
+/// The longest argument `exec` accepts, in bytes, without its terminator.
+pub const MAX_ARG_LEN: usize = 4096;
+
 
 pub const NCORES: usize = 4;
 
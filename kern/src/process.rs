@@ -1,3 +1,4 @@
+mod elf;
 mod process;
 mod scheduler;
 mod stack;
@@ -7,7 +8,10 @@ pub use self::process::{Id, Process};
 pub use self::scheduler::GlobalScheduler;
 pub use self::stack::Stack;
 pub use self::state::State;
-use fat32::vfat::VFatHandle;
+use fat32::vfat::{Attributes, Metadata, Timestamp, VFatHandle};
+use kernel_api::{ExitStatus, SocketType, Stat};
+use smoltcp::socket::SocketHandle;
+use core::time::Duration;
 
 use shim::io::{Read, Write};
 use shim::io;
@@ -31,7 +35,7 @@ impl ProcessFileT for ConsoleFile {
         console.write(buf)
     }
     
-    fn seek(&mut self, pos: usize) -> io::Result<()> {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
         Err(io::Error::new(io::ErrorKind::InvalidInput, "Cannot seek on console"))
     }
 }
@@ -45,7 +49,7 @@ impl<T: VFatHandle> ProcessFileT for fat32::vfat::File<T> {
     fn is_writable(&self) -> bool { true }
 
     fn size(&self) -> Option<usize> {
-        Some(self.metadata.size as usize)
+        Some(fat32::traits::File::size(self) as usize)
     }
 
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
@@ -55,12 +59,42 @@ impl<T: VFatHandle> ProcessFileT for fat32::vfat::File<T> {
 
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         let bytes_written = io::Write::write(self, buf)?;
+        // Keep the sector cache current; `sync` takes it to the disk
+        self.store()?;
         Ok(bytes_written)
     }
 
-    fn seek(&mut self, pos: usize) -> io::Result<()> {
-        io::Seek::seek(self, io::SeekFrom::Start(pos as u64))?;
-        Ok(())
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        io::Seek::seek(self, pos)
+    }
+
+    fn stat(&self) -> io::Result<Stat> {
+        Ok(stat(&self.current_metadata()))
+    }
+
+    fn set_times(&mut self, accessed: Option<u64>, modified: Option<u64>) -> io::Result<()> {
+        let (accessed, modified) = (accessed.map(Timestamp::from_unix), modified.map(Timestamp::from_unix));
+        fat32::vfat::File::set_times(self, None, accessed, modified)
+    }
+
+    fn set_attributes(&mut self, attributes: u8) -> io::Result<()> {
+        fat32::vfat::File::set_attributes(self, Attributes(attributes))
+    }
+
+    fn set_len(&mut self, len: u64) -> io::Result<()> {
+        fat32::vfat::File::set_len(self, len)?;
+        self.store()
+    }
+}
+
+/// Returns the `Stat` of a file or directory described by `metadata`.
+fn stat(metadata: &Metadata) -> Stat {
+    Stat {
+        size: metadata.size as u64,
+        attributes: metadata.attributes.0 as u64,
+        created: metadata.created().to_unix(),
+        accessed: metadata.accessed().to_unix(),
+        modified: metadata.modified().to_unix(),
     }
 }
 
@@ -69,6 +103,7 @@ impl<T: VFatHandle> ProcessFileT for fat32::vfat::File<T> {
 use fat32::traits::Dir;
 use alloc::string::String;
 use fat32::traits::Entry;
+use fat32::traits::Metadata as MetadataTrait;
 impl<T: VFatHandle> ProcessFileT for fat32::vfat::Dir<T> {
     fn is_dir(&self) -> bool {
         true
@@ -88,7 +123,7 @@ impl<T: VFatHandle> ProcessFileT for fat32::vfat::Dir<T> {
         Err(io::Error::new(io::ErrorKind::PermissionDenied, "Cannot write to a directory"))
     }
 
-    fn seek(&mut self, pos: usize) -> io::Result<()> {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
         Err(io::Error::new(io::ErrorKind::InvalidInput, "Cannot seek on a directory"))
     }
 
@@ -103,6 +138,23 @@ impl<T: VFatHandle> ProcessFileT for fat32::vfat::Dir<T> {
         buf[..len].copy_from_slice(&bytes[..len]);
         Ok(len)
     }
+
+    fn stat(&self) -> io::Result<Stat> {
+        Ok(match &self.metadata {
+            Some(metadata) => Stat { size: 0, ..stat(metadata) },
+            // The root directory has no entry to hold its metadata
+            None => Stat { attributes: Attributes::DIRECTORY as u64, ..Stat::default() },
+        })
+    }
+
+    fn set_times(&mut self, accessed: Option<u64>, modified: Option<u64>) -> io::Result<()> {
+        let (accessed, modified) = (accessed.map(Timestamp::from_unix), modified.map(Timestamp::from_unix));
+        fat32::vfat::Dir::set_times(self, None, accessed, modified)
+    }
+
+    fn set_attributes(&mut self, attributes: u8) -> io::Result<()> {
+        fat32::vfat::Dir::set_attributes(self, Attributes(attributes))
+    }
 }
 
 pub trait ProcessFileT: Send + Sync + core::fmt::Debug {
@@ -112,10 +164,29 @@ pub trait ProcessFileT: Send + Sync + core::fmt::Debug {
     fn size(&self) -> Option<usize>;
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize>;
     fn write(&mut self, buf: &[u8]) -> io::Result<usize>;
-    fn seek(&mut self, pos: usize) -> io::Result<()>;
+    /// Moves the file position and returns the new position from the start
+    /// of the file.
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64>;
     fn readdir(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
         Err(io::Error::new(io::ErrorKind::InvalidInput, "Not a directory"))
     }
+    /// Returns the size, attributes and timestamps of the file.
+    fn stat(&self) -> io::Result<Stat> {
+        Err(io::Error::new(io::ErrorKind::InvalidInput, "File has no metadata"))
+    }
+    /// Sets the access and modification times that are `Some`, given as Unix
+    /// times.
+    fn set_times(&mut self, _accessed: Option<u64>, _modified: Option<u64>) -> io::Result<()> {
+        Err(io::Error::new(io::ErrorKind::InvalidInput, "File has no timestamps"))
+    }
+    /// Sets the settable FAT attributes of the file to `attributes`.
+    fn set_attributes(&mut self, _attributes: u8) -> io::Result<()> {
+        Err(io::Error::new(io::ErrorKind::InvalidInput, "File has no attributes"))
+    }
+    /// Truncates or zero-extends the file to `len` bytes.
+    fn set_len(&mut self, _len: u64) -> io::Result<()> {
+        Err(io::Error::new(io::ErrorKind::InvalidInput, "File has no length"))
+    }
 
 }
 
@@ -130,6 +201,25 @@ pub struct ProcessFile {
     pub offset: usize,
 }
 
+/// A socket descriptor: the socket in `ETHERNET`'s socket set, whether it
+/// is a TCP or a UDP socket, and how its calls wait.
+#[derive(Debug, Clone, Copy)]
+pub struct ProcessSocket {
+    pub handle: SocketHandle,
+    pub kind: SocketType,
+    /// Whether calls that would wait fail with `OsError::WouldBlock` instead
+    pub nonblocking: bool,
+    /// How long a call waits before failing with `OsError::IoErrorTimedOut`
+    pub timeout: Option<Duration>,
+}
+
+impl ProcessSocket {
+    /// Returns a blocking descriptor without a timeout for `handle`.
+    pub fn new(handle: SocketHandle, kind: SocketType) -> ProcessSocket {
+        ProcessSocket { handle, kind, nonblocking: false, timeout: None }
+    }
+}
+
 
 impl Clone for ConsoleFile {
     fn clone(&self) -> Self {
@@ -138,11 +228,15 @@ impl Clone for ConsoleFile {
 }
 
 
+/// Exit status recorded for a process that the kernel killed because of a
+/// fault it could not resolve.
+pub const KILLED_BY_FAULT: ExitStatus = ExitStatus { code: -1, killed: true };
+
 #[derive(Clone)]
 pub struct ChildStatus {
     pub done: bool, // Shared flag between parent & child
     pub pid: Option<Id>, // Process ID of the child
-    pub exit_code: Option<i32>, // Exit code of the child
+    pub exit_status: Option<ExitStatus>, // How the child terminated
 }
 
 impl ChildStatus {
@@ -151,7 +245,7 @@ impl ChildStatus {
         Self {
             done: false,
             pid: None,
-            exit_code: None,
+            exit_status: None,
         }
     }
 
@@ -0,0 +1,193 @@
+use core::mem::size_of;
+use core::ptr;
+
+use kernel_api::{OsError, OsResult};
+
+use crate::param::PAGE_SIZE;
+
+/// `e_ident[EI_MAG0..=EI_MAG3]`
+const ELF_MAGIC: [u8; 4] = [0x7F, b'E', b'L', b'F'];
+/// `e_ident[EI_CLASS]` for 64-bit objects
+const ELFCLASS64: u8 = 2;
+/// `e_ident[EI_DATA]` for little-endian objects
+const ELFDATA2LSB: u8 = 1;
+/// `e_ident[EI_VERSION]` and `e_version`
+const EV_CURRENT: u8 = 1;
+/// `e_type` of an executable file
+const ET_EXEC: u16 = 2;
+/// `e_machine` of AArch64
+const EM_AARCH64: u16 = 183;
+/// `p_type` of a loadable segment
+const PT_LOAD: u32 = 1;
+
+/// `p_flags` bits
+pub const PF_X: u32 = 0x1;
+pub const PF_W: u32 = 0x2;
+pub const PF_R: u32 = 0x4;
+
+/// ELF64 file header (ref: System V ABI, Chapter 4).
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct ElfHeader {
+    e_ident: [u8; 16],
+    e_type: u16,
+    e_machine: u16,
+    e_version: u32,
+    e_entry: u64,
+    e_phoff: u64,
+    e_shoff: u64,
+    e_flags: u32,
+    e_ehsize: u16,
+    e_phentsize: u16,
+    e_phnum: u16,
+    e_shentsize: u16,
+    e_shnum: u16,
+    e_shstrndx: u16,
+}
+
+/// ELF64 program header.
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct ProgramHeader {
+    pub p_type: u32,
+    pub p_flags: u32,
+    pub p_offset: u64,
+    pub p_vaddr: u64,
+    pub p_paddr: u64,
+    pub p_filesz: u64,
+    pub p_memsz: u64,
+    pub p_align: u64,
+}
+
+/// A validated ELF64 AArch64 executable borrowed from an in-memory image.
+pub struct Elf<'a> {
+    data: &'a [u8],
+    header: ElfHeader,
+}
+
+/// Reads a `T` at byte offset `offset` of `data`, or returns `None` if it
+/// does not fit.
+fn read_at<T: Copy>(data: &[u8], offset: u64) -> Option<T> {
+    let start = usize::try_from(offset).ok()?;
+    let end = start.checked_add(size_of::<T>())?;
+    if end > data.len() {
+        return None;
+    }
+    Some(unsafe { ptr::read_unaligned(data[start..end].as_ptr() as *const T) })
+}
+
+/// Returns the page-aligned range of virtual addresses `ph` occupies.
+fn pages(ph: &ProgramHeader) -> PageRange {
+    let page_size = PAGE_SIZE as u64;
+    let end = ph.p_vaddr.saturating_add(ph.p_memsz).saturating_add(page_size - 1);
+    PageRange { start: ph.p_vaddr & !(page_size - 1), end: end & !(page_size - 1) }
+}
+
+/// A range `[start, end)` of page-aligned virtual addresses.
+struct PageRange {
+    start: u64,
+    end: u64,
+}
+
+impl PageRange {
+    fn overlaps(&self, other: &PageRange) -> bool {
+        self.start < other.end && other.start < self.end
+    }
+}
+
+impl<'a> Elf<'a> {
+    /// Parses the ELF header of `data`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `OsError::InvalidExecutable` if `data` is not a little-endian
+    /// ELF64 executable for AArch64, or if its program header table or any
+    /// `PT_LOAD` segment lies outside of `data`, if the entry point is not
+    /// inside an executable `PT_LOAD` segment, or if a writable and an
+    /// executable segment share a page.
+    pub fn parse(data: &'a [u8]) -> OsResult<Elf<'a>> {
+        let header: ElfHeader = read_at(data, 0).ok_or(OsError::InvalidExecutable)?;
+
+        let ident = &header.e_ident;
+        if ident[..4] != ELF_MAGIC
+            || ident[4] != ELFCLASS64
+            || ident[5] != ELFDATA2LSB
+            || ident[6] != EV_CURRENT
+        {
+            trace!("[elf] bad e_ident: {:x?}", ident);
+            return Err(OsError::InvalidExecutable);
+        }
+
+        if header.e_type != ET_EXEC || header.e_machine != EM_AARCH64 {
+            trace!("[elf] unsupported type {} / machine {}", header.e_type, header.e_machine);
+            return Err(OsError::InvalidExecutable);
+        }
+
+        if header.e_phentsize as usize != size_of::<ProgramHeader>() || header.e_phnum == 0 {
+            return Err(OsError::InvalidExecutable);
+        }
+
+        let elf = Elf { data, header };
+        for i in 0..header.e_phnum {
+            let ph = elf.program_header(i).ok_or(OsError::InvalidExecutable)?;
+            if ph.p_type != PT_LOAD {
+                continue;
+            }
+            let file_end = ph.p_offset.checked_add(ph.p_filesz);
+            if ph.p_filesz > ph.p_memsz || file_end.map_or(true, |end| end > data.len() as u64) {
+                trace!("[elf] malformed segment: {:x?}", ph);
+                return Err(OsError::InvalidExecutable);
+            }
+        }
+
+        let entry = header.e_entry;
+        let entry_is_code = elf.load_segments().any(|ph| {
+            let end = ph.p_vaddr.saturating_add(ph.p_memsz);
+            ph.p_flags & PF_X != 0 && (ph.p_vaddr..end).contains(&entry)
+        });
+        if !entry_is_code {
+            trace!("[elf] entry point {:#x} is not in an executable segment", entry);
+            return Err(OsError::InvalidExecutable);
+        }
+
+        // A page holding both a writable and an executable segment would have
+        // to be mapped writable and executable
+        for (i, writable) in elf.load_segments().enumerate().filter(|(_, ph)| ph.p_flags & PF_W != 0) {
+            let shares_page = elf
+                .load_segments()
+                .enumerate()
+                .any(|(j, ph)| i != j && ph.p_flags & PF_X != 0 && pages(&ph).overlaps(&pages(&writable)));
+            if shares_page {
+                trace!("[elf] writable segment {:x?} shares a page with code", writable);
+                return Err(OsError::InvalidExecutable);
+            }
+        }
+
+        Ok(elf)
+    }
+
+    /// Returns the virtual address of the program's entry point.
+    pub fn entry(&self) -> u64 {
+        self.header.e_entry
+    }
+
+    fn program_header(&self, idx: u16) -> Option<ProgramHeader> {
+        let offset = (idx as u64)
+            .checked_mul(size_of::<ProgramHeader>() as u64)?
+            .checked_add(self.header.e_phoff)?;
+        read_at(self.data, offset)
+    }
+
+    /// Returns an iterator over the `PT_LOAD` program headers.
+    pub fn load_segments(&self) -> impl Iterator<Item = ProgramHeader> + '_ {
+        (0..self.header.e_phnum)
+            .filter_map(move |i| self.program_header(i))
+            .filter(|ph| ph.p_type == PT_LOAD)
+    }
+
+    /// Returns the file contents of the segment described by `ph`.
+    pub fn segment_data(&self, ph: &ProgramHeader) -> &'a [u8] {
+        let start = ph.p_offset as usize;
+        &self.data[start..start + ph.p_filesz as usize]
+    }
+}
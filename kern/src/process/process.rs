@@ -4,13 +4,12 @@ use alloc::boxed::Box;
 use alloc::vec::Vec;
 use fat32::vfat::VFatHandle;
 use shim::io;
-use shim::path::Path;
+use shim::path::{Path, PathBuf};
 
 use aarch64;
-use smoltcp::socket::SocketHandle;
 
 use crate::console::kprintln;
-use crate::{param::*, FILESYSTEM};
+use crate::{param::*, VFS};
 use crate::process::*;
 use crate::traps::TrapFrame;
 use crate::vm::*;
@@ -40,10 +39,21 @@ pub struct Process {
     pub files: Vec<Option<ProcessFile>>, // Open file table
     pub children: Vec<Arc<Mutex<ChildStatus>>>, // Child processes
     pub parent: Option<Arc<Mutex<ChildStatus>>>, // Parent process
-    pub sockets: Vec<SocketHandle>
+    pub sockets: Vec<ProcessSocket>,
+    /// Start of the user heap, i.e. of its reservation in `vmap`
+    pub heap_base: usize,
+    /// Current end of the user heap (the program break)
+    pub brk: usize,
+    /// Lowest address handed out by `mmap` so far; the next mapping is
+    /// placed right below it
+    pub mmap_base: usize,
+    /// Current working directory, against which relative paths are resolved.
+    /// Always absolute and free of `.` and `..` components.
+    pub cwd: PathBuf,
 }
 use kernel_api::{OsResult, OsError};
-use heap::align_down;
+use heap::{align_down, align_up};
+use crate::process::elf::{Elf, PF_W, PF_X};
 impl Process {
     /// Creates a new process with a zeroed `TrapFrame` (the default), a zeroed
     /// stack of the default size, and a state of `Ready`.
@@ -86,59 +96,93 @@ impl Process {
             files,
             children: Vec::new(),
             parent,
-            sockets: Vec::new()
+            sockets: Vec::new(),
+            heap_base: 0,
+            brk: 0,
+            mmap_base: 0,
+            cwd: PathBuf::from("/"),
         };
 
         Ok(p)
     }
 
 
+    /// Returns a child copy of this process for `fork`. The child shares the
+    /// user pages of `self` copy-on-write; see `UserPageTable::fork()`.
+    pub fn fork(&mut self) -> Process {
+        Process {
+            context: self.context.clone(),
+            stack: self.stack.clone(),
+            vmap: Box::new(self.vmap.fork()),
+            state: State::Ready,
+            files : self.files.clone(),
+            children: Vec::new(),
+            parent: None,
+            sockets: self.sockets.clone(),
+            heap_base: self.heap_base,
+            brk: self.brk,
+            mmap_base: self.mmap_base,
+            cwd: self.cwd.clone(),
+        }
+    }
+
+    /// Returns `path` resolved against the working directory of `self`.
+    pub fn resolve_path<P: AsRef<Path>>(&self, path: P) -> PathBuf {
+        self.cwd.join(path)
+    }
+
     pub fn execve<P: AsRef<Path>>(process: &mut Process, pn: P, args: Vec<String>) -> Result<(), OsError> {
-        use fat32::traits::FileSystem;
-        use shim::io::Read;
-    
         trace!("[execve] Loading program '{}'", pn.as_ref().to_str().unwrap());
     
-        // Load the program file
-        let mut file = FILESYSTEM.open_file(pn).map_err(|_| {
+        // Load the program file; directories have no size
+        let mut file = VFS.open(pn).map_err(|_| {
             trace!("[execve] Error: Could not open file");
             OsError::InvalidFile
-        })?;
-    
-        let mut data = Vec::new();
-        file.read_to_end(&mut data).map_err(|_| {
-            trace!("[execve] Error: Failed to read file");
-            OsError::InvalidFile
-        })?;
-
-         // allocate one page for stack
-        let page = process.vmap.alloc(Process::get_stack_base(), PagePerm::RWX);
-        page.iter_mut().for_each(|x| *x = 0);
-    
-        // Allocate pages for the process image
-        let data_pages = data.chunks(PAGE_SIZE);
-        let page_nums = data_pages.len();
+        })?.open();
+        let size = file.size().ok_or(OsError::InvalidFile)?;
     
-        for (idx, data_page) in data_pages.enumerate() {
-            let va = VirtualAddr::from(Process::get_image_base().as_usize()+PAGE_SIZE*idx);
-            let page = process.vmap.alloc(va, PagePerm::RWX);
-            page[..data_page.len()].copy_from_slice(data_page);
-        }
-    
-        // Allocate user heap pages
-        let user_heap_pages = 16; // 1MB user heap
-        for idx in page_nums..(page_nums + user_heap_pages) {
-            let va = VirtualAddr::from(Process::get_image_base().as_usize() + PAGE_SIZE * idx);
-            let page = process.vmap.alloc(va, PagePerm::RWX);
-            page.iter_mut().for_each(|x| *x = 0);
+        // Read the image in one go so it is loaded with as few disk requests
+        // as its fragmentation allows
+        let mut data = alloc::vec![0u8; size];
+        let mut filled = 0;
+        while filled < size {
+            match file.read(&mut data[filled..]) {
+                Ok(0) | Err(_) => {
+                    trace!("[execve] Error: Failed to read file");
+                    return Err(OsError::InvalidFile);
+                }
+                Ok(read) => filled += read,
+            }
         }
 
+        // Build the new address space on the side so that a bad image leaves
+        // the calling process untouched.
+        let elf = Elf::parse(&data)?;
+        let mut vmap = Box::new(UserPageTable::new());
+        let image_end = Process::load_segments(&mut vmap, &elf)?;
+
+        // The heap starts out empty right after the image and is grown with
+        // `brk`; its pages are populated on first access.
+        vmap.reserve(VirtualAddr::from(image_end), 0, PagePerm::RW)?;
+
+        // allocate one page for stack
+        let stack = vmap.alloc(Process::get_stack_base(), PagePerm::RW);
+        stack.iter_mut().for_each(|x| *x = 0);
+        let (sp, argv_ptr) = Process::push_args(stack, &args)?;
+
         let mut new_tf = Box::new(TrapFrame::default());
         new_tf.ttbr0_el1 = process.context.ttbr0_el1;
-        new_tf.ttbr1_el1 = process.vmap.get_baddr().as_u64();
+        new_tf.ttbr1_el1 = vmap.get_baddr().as_u64();
         new_tf.tpidr = process.context.tpidr;
         process.context = new_tf;
-        use aarch64::PState;
+        // the old image is released here; TTBR1 is reloaded from the new
+        // trap frame before returning to user space
+        process.vmap = vmap;
+        process.heap_base = image_end;
+        process.brk = image_end;
+        // leave an unmapped guard page below the stack
+        process.mmap_base = Process::get_stack_base().as_usize() - PAGE_SIZE;
+
         let mut pstate = PState::new(0);
         pstate.set_value(0b1_u64, PState::F);
         pstate.set_value(0b0_u64, PState::I); // enable interrupt
@@ -147,78 +191,130 @@ impl Process {
         pstate.set_value(0b000_u64, PState::M); // EL0
         process.context.pstate = pstate.get();
     
-        // Set stack pointer
-        process.context.sp = Process::get_stack_top().as_u64();
-    
-        // Set process entry point
-        process.context.pc = Process::get_image_base().as_u64();
+        // Set process entry point and stack pointer
+        process.context.pc = elf.entry();
+        process.context.sp = sp;
+
+        // x0 = argc, x1 = argv_ptr
+        process.context.regs[0] = args.len() as u64;
+        process.context.regs[1] = argv_ptr;
     
-        // --- Set up user stack ---
-        let mut sp = process.context.sp as *mut u8;
+        debug!("[execve] Stack set up: argc = {}, argv_ptr = {:#x}", args.len(), argv_ptr);
 
-        // We'll build the argument block from the bottom up.
-        // We'll push the actual argument strings first (in order), then the argv array, then argc.
+        Ok(())
+    }
+
+    /// Maps every `PT_LOAD` segment of `elf` into `vmap` at its virtual
+    /// address with the segment's permissions, copies in its file contents
+    /// and zeroes the remainder up to `p_memsz` (`.bss`).
+    ///
+    /// Returns the page-aligned end of the highest segment.
+    ///
+    /// # Errors
+    ///
+    /// Returns `OsError::InvalidExecutable` if a segment lies outside of the
+    /// user image region.
+    fn load_segments(vmap: &mut UserPageTable, elf: &Elf) -> OsResult<usize> {
+        let mut image_end = USER_IMG_BASE;
+
+        for ph in elf.load_segments() {
+            let start = ph.p_vaddr as usize;
+            let end = start
+                .checked_add(ph.p_memsz as usize)
+                .ok_or(OsError::InvalidExecutable)?;
+            if start < USER_IMG_BASE || end > Process::get_stack_base().as_usize() {
+                trace!("[execve] segment {:#x}..{:#x} outside of user image", start, end);
+                return Err(OsError::InvalidExecutable);
+            }
+
+            let data = elf.segment_data(&ph);
+            let data_end = start + data.len();
+            for page_va in (align_down(start, PAGE_SIZE)..end).step_by(PAGE_SIZE) {
+                let va = VirtualAddr::from(page_va);
+                let fresh = vmap.is_invalid(va);
+                let page = vmap.alloc(va, Process::segment_perm(ph.p_flags));
+                if fresh {
+                    page.iter_mut().for_each(|x| *x = 0);
+                }
+
+                // copy the part of the file image that falls into this page
+                let copy_start = start.max(page_va);
+                let copy_end = data_end.min(page_va + PAGE_SIZE);
+                if copy_start < copy_end {
+                    page[copy_start - page_va..copy_end - page_va]
+                        .copy_from_slice(&data[copy_start - start..copy_end - start]);
+                }
+            }
+
+            image_end = image_end.max(align_up(end, PAGE_SIZE));
+        }
+
+        Ok(image_end)
+    }
+
+    /// Translates ELF `p_flags` into the permission of the mapped pages.
+    fn segment_perm(flags: u32) -> PagePerm {
+        match (flags & PF_W != 0, flags & PF_X != 0) {
+            (true, true) => PagePerm::RWX,
+            (true, false) => PagePerm::RW,
+            (false, true) => PagePerm::RX,
+            (false, false) => PagePerm::RO,
+        }
+    }
+
+    /// Pushes `args` onto the user stack page `stack`.
+    ///
+    /// The argument strings are copied first (in order), followed by a
+    /// NULL-terminated `argv` array and `argc`. Addresses are computed as seen
+    /// from user space, i.e. relative to `get_stack_base()`.
+    ///
+    /// Returns the 16-byte aligned initial stack pointer and the address of
+    /// `argv`.
+    fn push_args(stack: &mut [u8], args: &[String]) -> OsResult<(u64, u64)> {
+        let base = Process::get_stack_base().as_usize();
+        let mut sp = Process::get_stack_top().as_usize() - base;
 
         // --- Step 1: Push argument strings (in normal order) ---
-        // We want the strings to appear in memory in the same order as in args.
         let mut arg_ptrs = Vec::new();
         for arg in args.iter() {
             trace!("[execve] Pushing argument: {} of size {}", arg, arg.len());
             let len = arg.len() + 1; // +1 for the null terminator
-            sp = unsafe { sp.sub(len) }; // allocate space for the string
-            unsafe {
-                // Copy the string bytes
-                core::ptr::copy_nonoverlapping(arg.as_ptr(), sp, arg.len());
-                // Write null terminator
-                *sp.add(arg.len()) = 0;
-            }
-            // Record this string's address (it will be used for argv)
-            arg_ptrs.push(sp as u64);
+            sp = sp.checked_sub(len).ok_or(OsError::InvalidArgument)?;
+            stack[sp..sp + arg.len()].copy_from_slice(arg.as_bytes());
+            stack[sp + arg.len()] = 0;
+            arg_ptrs.push((base + sp) as u64);
         }
-        // Now, arg_ptrs[0] is the pointer to the first argument string, etc.
-
-        // --- Step 2: Push the argv array (an array of u64 pointers) ---
-        // We want to create an array: [ arg_ptrs[0], arg_ptrs[1], ..., NULL ]
-        // First, push a null pointer as the terminator.
-        sp = unsafe { sp.sub(core::mem::size_of::<u64>()) };
-        unsafe { *(sp as *mut u64) = 0 };
-        // Then push each pointer from arg_ptrs in reverse order,
-        // so that the lowest memory (highest address) gets the first argument.
+
+        // make sure argv, NULL, argc and the final alignment fit in the page
+        sp &= !0x7;
+        let word = core::mem::size_of::<u64>();
+        let needed = (arg_ptrs.len() + 2) * word + 0x10;
+        if sp < needed {
+            return Err(OsError::InvalidArgument);
+        }
+        let mut push = |sp: &mut usize, val: u64| {
+            *sp -= word;
+            stack[*sp..*sp + word].copy_from_slice(&val.to_ne_bytes());
+        };
+
+        // --- Step 2: Push the argv array: [ arg_ptrs[0], ..., NULL ] ---
+        push(&mut sp, 0);
         for ptr_val in arg_ptrs.iter().rev() {
-            sp = unsafe { sp.sub(core::mem::size_of::<u64>()) };
-            unsafe { *(sp as *mut u64) = *ptr_val };
+            push(&mut sp, *ptr_val);
         }
+        let argv_ptr = (base + sp) as u64;
 
         // --- Step 3: Push argc onto the stack ---
-        let argc = args.len() as u64;
-        sp = unsafe { sp.sub(core::mem::size_of::<u64>()) };
-        unsafe { *(sp as *mut u64) = argc };
-    
-        // Now the final stack pointer (sp) points to argc.
-        // According to the AArch64 C ABI, main expects:
-        //   x0 = argc (the value at SP)
-        //   x1 = pointer to argv, which is at (SP + 8)
-        let final_sp = sp;  // final_sp is where argc is stored.
-        let final_argv_ptr = unsafe { final_sp.add(core::mem::size_of::<u64>()) } as u64;
-    
-        // --- Step 4: Align the final stack pointer to a 16-byte boundary ---
-        let sp_aligned = (final_sp as usize & !0xF) as *mut u8;
-        process.context.sp = sp_aligned as u64;
-    
-        // --- Step 5: Update registers: x0 = argc, x1 = argv_ptr ---
-        process.context.regs[0] = argc;
-        process.context.regs[1] = final_argv_ptr;
-    
-        debug!("[execve] Stack set up: argc = {}, argv_ptr = {:#x}", argc, final_argv_ptr);
+        push(&mut sp, args.len() as u64);
 
-        Ok(())
+        // --- Step 4: Align the final stack pointer to a 16-byte boundary ---
+        Ok((((base + sp) & !0xF) as u64, argv_ptr))
     }
-    
 
     /// Loads a program stored in the given path by calling `do_load()` method.
     /// Sets trapframe `context` corresponding to its page table.
     /// `sp` - the address of stack top
-    /// `elr` - the address of image base.
+    /// `elr` - the ELF entry point of the image.
     /// `ttbr0` - the base address of kernel page table
     /// `ttbr1` - the base address of user page table
     /// `spsr` - `F`, `A`, `D` bit should be set.
@@ -228,56 +324,17 @@ impl Process {
         use crate::VMM;
 
         let mut p = Process::do_load(pn, parent)?;
-        
-
-        p.context.sp = Process::get_stack_top().as_u64();
-        p.context.pc = Process::get_image_base().as_u64();
         p.context.ttbr0_el1 = VMM.get_baddr().as_u64();
-        p.context.ttbr1_el1 = p.vmap.get_baddr().as_u64();
-        use aarch64::PState;
-        let mut pstate = PState::new(0);
-        pstate.set_value(0b1_u64, PState::F);
-        pstate.set_value(0b0_u64, PState::I); // enable interrupt
-        pstate.set_value(0b1_u64, PState::A);
-        pstate.set_value(0b1_u64, PState::D);
-        pstate.set_value(0b000_u64, PState::M); // EL0
-        p.context.pstate = pstate.get();
 
         Ok(p)
     }
 
-    /// Creates a process and open a file with given path.
-    /// Allocates one page for stack with read/write permission, and N pages with read/write/execute
-    /// permission to load file's contents.
+    /// Creates a process and loads the ELF executable at `pn` into it with
+    /// `execve()`, which maps each segment and one stack page and reserves
+    /// the user heap.
     fn do_load<P: AsRef<Path>>(pn: P, parent: Option<Arc<Mutex<ChildStatus>>>) -> OsResult<Process> {
-        use fat32::traits::FileSystem;
-        use shim::io::Read;
-        let mut file = FILESYSTEM.open_file(pn)?;
-        let mut p = Process::new(parent).expect("failed to create processs");
-        p.vmap.alloc(Process::get_stack_base(), PagePerm::RWX); // allocate one page for stack
-        
-        use alloc::vec;
-        let mut data = vec![];
-        file.read_to_end(&mut data)?;
-
-        let data_pages = data.chunks(PAGE_SIZE);
-        let page_nums = data_pages.len();
-
-        for (idx, data_page) in data_pages.enumerate() {
-            let va = VirtualAddr::from(Process::get_image_base().as_usize()+PAGE_SIZE*idx);
-            let page = p.vmap.alloc(va, PagePerm::RWX);
-            page[..data_page.len()].copy_from_slice(data_page);
-        }
-
-
-        // alloc some pages for user heap
-        // TODO: add page fault handler to automatically handle this
-        let user_heap_pages = 16; // user can allocate 1 MB heap
-        for idx in (page_nums)..(page_nums+user_heap_pages) {
-            let va = VirtualAddr::from(Process::get_image_base().as_usize()+PAGE_SIZE*idx);
-            p.vmap.alloc(va, PagePerm::RWX);
-        }
-
+        let mut p = Process::new(parent)?;
+        Process::execve(&mut p, pn, Vec::new())?;
         Ok(p)
     }
 
@@ -338,18 +395,3 @@ impl Process {
     }
     
 }
-
-impl Clone for Process {
-    fn clone(&self) -> Self {
-        Process {
-            context: self.context.clone(),
-            stack: self.stack.clone(),
-            vmap: self.vmap.clone(),
-            state: State::Ready,
-            files : self.files.clone(),
-            children: Vec::new(),
-            parent: None,
-            sockets: self.sockets.clone()
-        }
-    }
-}
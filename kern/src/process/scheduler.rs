@@ -1,10 +1,13 @@
 use aarch64::*;
 use alloc::boxed::Box;
 use alloc::collections::vec_deque::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
 use pi::timer;
 use core::arch::asm;
 use core::ffi::c_void;
 use core::fmt;
+use core::mem;
 use core::time::Duration;
 use core::u64;
 use pi::local_interrupt::LocalInterrupt;
@@ -12,12 +15,17 @@ use pi::local_interrupt::LocalInterrupt;
 use crate::mutex::Mutex;
 use crate::net::uspi::TKernelTimerHandle;
 use crate::percore::local_irq;
-use crate::process::{Id, Process, State};
+use crate::process::{ChildStatus, Id, Process, State};
 use crate::traps::irq::IrqHandlerRegistry;
 use crate::traps::TrapFrame;
 use crate::GLOBAL_IRQ;
 use crate::SCHEDULER;
 use crate::{ETHERNET, USB};
+use kernel_api::{ExitStatus, SocketType};
+
+/// Process ID of the first process. It adopts the children of processes that
+/// exit before them.
+pub const INIT_PID: Id = 0;
 
 /// Process scheduler for the entire machine.
 #[derive(Debug)]
@@ -171,6 +179,41 @@ impl GlobalScheduler {
         self.critical(|scheduler| scheduler.kill(tf))
     }
 
+    /// Terminates the process running on `tf` with `status`: wakes up its
+    /// parent, removes it from the scheduler and parks this core in the idle
+    /// thread until the next process is scheduled.
+    pub fn exit(&self, tf: &mut TrapFrame, status: ExitStatus) -> ! {
+        let (parent, children) = self.with_current_process_mut(tf, |process| {
+            (process.parent.clone(), mem::take(&mut process.children))
+        });
+        if let Some(parent) = parent {
+            let mut g = parent.lock();
+            g.complete();
+            g.exit_status = Some(status);
+        }
+        self.adopt_orphans(children);
+
+        let id = self.kill(tf).expect("failed to kill process");
+        assert!(id == tf.tpidr);
+        Self::idle_thread();
+    }
+
+    /// Hands the children of an exiting process that are still running over
+    /// to the init process, which reaps them in its stead. Children that have
+    /// already terminated are dropped along with their parent.
+    fn adopt_orphans(&self, children: Vec<Arc<Mutex<ChildStatus>>>) {
+        let orphans: Vec<_> = children.into_iter().filter(|c| !c.lock().is_done()).collect();
+        if orphans.is_empty() {
+            return;
+        }
+
+        self.critical(|scheduler| {
+            if let Some(init) = scheduler.find_process_by_id(INIT_PID as usize) {
+                init.children.extend(orphans);
+            }
+        });
+    }
+
     /// Starts executing processes in user space using timer interrupt based
     /// preemptive scheduling. This method should not return under normal conditions.
     pub fn start(&'static self) -> ! {
@@ -347,8 +390,6 @@ impl Scheduler {
     fn release_process_resources(&mut self, tf: &mut TrapFrame) {
 
         // NETWORK
-        use core::mem;
-
         let process = self
             .processes
             .iter_mut()
@@ -360,17 +401,26 @@ impl Scheduler {
 
         let sockets = mem::take(&mut process.sockets);
 
-        for handle in sockets {
+        for socket in sockets {
             ETHERNET.critical(|eth| {
-                let port = {
-                    let mut sock = eth.get_socket(handle);
-                    let endpoint = sock.local_endpoint().port;
-                    if sock.is_open() {
-                        sock.close();
+                let handle = socket.handle;
+                let port = match socket.kind {
+                    SocketType::Tcp => {
+                        eth.unlisten(handle);
+                        let mut sock = eth.get_socket(handle);
+                        let endpoint = sock.local_endpoint().port;
+                        if sock.is_open() {
+                            sock.close();
+                        }
+                        endpoint
                     }
-                    endpoint
+                    // UDP sockets hold no connection state to tear down
+                    SocketType::Udp => eth.get_udp_socket(handle).endpoint().port,
                 };
-                eth.erase_port(port);
+                // Accepted connections share the port of their listener
+                if port != 0 && !eth.is_listening_port(port) {
+                    eth.erase_port(port);
+                }
                 eth.release(handle);
             });
         }
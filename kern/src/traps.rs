@@ -3,7 +3,7 @@ mod syndrome;
 mod syscall;
 
 pub mod irq;
-use crate::process::GlobalScheduler;
+use crate::process::{GlobalScheduler, KILLED_BY_FAULT};
 
 pub use self::frame::TrapFrame;
 
@@ -13,9 +13,10 @@ use aarch64::{affinity, current_el, FAR_EL1};
 use pi::interrupt::{Controller, Interrupt};
 use pi::local_interrupt::{LocalController, LocalInterrupt};
 
-use self::syndrome::Syndrome;
+use self::syndrome::{Fault, Syndrome};
 use self::syscall::handle_syscall;
 use crate::percore::{self, local_irq};
+use crate::vm::VirtualAddr;
 use crate::traps::irq::IrqHandlerRegistry;
 
 #[repr(u16)]
@@ -60,15 +61,29 @@ pub extern "C" fn handle_exception(info: Info, esr: u32, tf: &mut TrapFrame) {
                 });
             }
         }
+        Kind::Synchronous
+            if info.source == Source::LowerAArch64 && handle_page_fault(Syndrome::from(esr), tf) =>
+        {
+            // the page is mapped now; `eret` retries the faulting access
+        }
+        Kind::Synchronous if info.source == Source::LowerAArch64 => {
+            // a fault in user space only takes down the offending process
+            let far = unsafe { FAR_EL1.get() };
+            info!(
+                "[core-{}] process {} killed: {:?} at {:#x} (pc: {:#x})",
+                affinity(),
+                tf.tpidr,
+                Syndrome::from(esr),
+                far,
+                tf.pc
+            );
+            SCHEDULER.exit(tf, KILLED_BY_FAULT);
+        }
         Kind::Synchronous => {
             unsafe {
                 debug!("[MAY BE INVALID] Fault addr: {:x}", FAR_EL1.get());
             }
             panic!("[core-{}] {:#?}, {}, {:#?}", affinity(), info, esr, Syndrome::from(esr));
-            // info!("Unhandled exception: {:#?}, {:#?}", info, Syndrome::from(esr));
-            // info!("TrapFrame: {:#x?}", tf);
-            // let _ = SCHEDULER.kill(tf);
-            // GlobalScheduler::switch_to_idle(); // TODO: kill the old proc, set a return code, etc.
         }
         Kind::Irq => {
             let mut handled = false;
@@ -119,3 +134,21 @@ pub extern "C" fn handle_exception(info: Info, esr: u32, tf: &mut TrapFrame) {
     }
 
 }
+
+/// Resolves a user page fault at `FAR_EL1` that is part of normal operation:
+/// a translation fault on memory the current process has reserved but not
+/// touched yet (demand paging), or a permission fault caused by a write to a
+/// copy-on-write page. Returns `true` if the faulting access can be retried.
+fn handle_page_fault(syndrome: Syndrome, tf: &mut TrapFrame) -> bool {
+    let va = VirtualAddr::from(unsafe { FAR_EL1.get() } as usize);
+    match syndrome {
+        Syndrome::DataAbort { kind: Fault::Translation, .. }
+        | Syndrome::InstructionAbort { kind: Fault::Translation, .. } => {
+            SCHEDULER.with_current_process_mut(tf, |process| process.vmap.populate(va, 1, false))
+        }
+        Syndrome::DataAbort { kind: Fault::Permission, .. } => {
+            SCHEDULER.with_current_process_mut(tf, |process| process.vmap.copy_on_write(va))
+        }
+        _ => false,
+    }
+}
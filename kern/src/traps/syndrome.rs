@@ -66,7 +66,7 @@ impl From<u32> for Syndrome {
             0b011000 => MsrMrsSystem,
             0b100000 | 0b100001 => InstructionAbort { kind: Fault::from(esr & 0x1F), level: (esr & 0b11) as u8 },
             0b100010 => PCAlignmentFault,
-            0b100100 | 100101 => DataAbort { kind: Fault::from(esr & 0x1F), level: (esr & 0b11) as u8 },
+            0b100100 | 0b100101 => DataAbort { kind: Fault::from(esr & 0x1F), level: (esr & 0b11) as u8 },
             0b100110 => SpAlignmentFault,
             0b101000 | 0b101100 => TrappedFpu,
             0b101111 => SError,
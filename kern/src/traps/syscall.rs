@@ -1,29 +1,35 @@
 use aarch64::{affinity, current_el};
 use alloc::boxed::{self, Box};
+use alloc::string::String;
+use alloc::vec::Vec;
 use core::hint::spin_loop;
 use core::net::Ipv4Addr;
 use core::time::Duration;
-use fat32::traits::FileSystem;
 
 use smoltcp::wire::{IpAddress, IpEndpoint};
 
 use crate::console::kprint;
-use crate::param::USER_IMG_BASE;
-use crate::process::{GlobalScheduler, State};
+use crate::net::{can_recv_or_closed, can_send_or_closed, EthernetDriver};
+use crate::param::{MAX_ARG_LEN, MAX_LISTEN_BACKLOG, MAX_PATH_LEN, PAGE_SIZE, USER_IMG_BASE, USER_MAX_VM_SIZE};
+use crate::process::{Id, ProcessFileT, ProcessSocket, State};
 use crate::traps::TrapFrame;
-use crate::{ETHERNET, SCHEDULER};
+use crate::vm::{PagePerm, VirtualAddr};
+use crate::{ETHERNET, SCHEDULER, VFS};
 
+use heap::align_up;
 use kernel_api::*;
 use pi::timer;
+use shim::io;
+use shim::path::PathBuf;
 use smoltcp::wire::Ipv4Address;
 pub fn handle_syscall(num: u16, tf: &mut TrapFrame) {
     match num as usize {
         NR_SLEEP => sys_sleep(tf.regs[0] as u32, tf),
         NR_TIME => sys_time(tf),
-        NR_EXIT => sys_exit(tf),
+        NR_EXIT => sys_exit(tf.regs[0] as i32, tf),
         NR_GETPID => sys_getpid(tf),
         NR_WRITE_STR => sys_write_str(tf.regs[0] as usize, tf.regs[1] as usize, tf),
-        NR_OPEN => sys_open(tf.regs[0] as usize, tf),
+        NR_OPEN => sys_open(tf.regs[0] as usize, tf.regs[1], tf),
         NR_CLOSE => sys_close(tf.regs[0] as usize, tf),
         NR_READ => sys_read(
             tf.regs[0] as usize,
@@ -37,7 +43,7 @@ pub fn handle_syscall(num: u16, tf: &mut TrapFrame) {
             tf.regs[2] as usize,
             tf,
         ),
-        NR_SEEK => sys_seek(tf.regs[0] as usize, tf.regs[1] as usize, tf),
+        NR_SEEK => sys_seek(tf.regs[0] as usize, tf.regs[1] as i64, tf.regs[2], tf),
         NR_LEN => sys_len(tf.regs[0] as usize, tf),
         NR_READDIR => sys_readdir(
             tf.regs[0] as usize,
@@ -47,30 +53,50 @@ pub fn handle_syscall(num: u16, tf: &mut TrapFrame) {
         ),
         NR_EXEC => sys_exec(tf.regs[0] as usize, tf),
         NR_FORK => sys_fork(tf),
-        NR_WAITPID => sys_wait(tf, tf.regs[0] as usize),
-        NR_SOCK_CREATE => sys_sock_create(tf),
+        NR_WAITPID => sys_wait(tf, tf.regs[0] as i64, tf.regs[1]),
+        NR_BRK => sys_brk(tf.regs[0] as usize, tf),
+        NR_MMAP => sys_mmap(tf.regs[0] as usize, tf),
+        NR_MKDIR => sys_mkdir(tf.regs[0] as usize, tf),
+        NR_RMDIR => sys_rmdir(tf.regs[0] as usize, tf),
+        NR_RENAME => sys_rename(tf.regs[0] as usize, tf.regs[1] as usize, tf),
+        NR_SYNC => sys_sync(tf),
+        NR_STAT => sys_stat(tf.regs[0] as usize, tf.regs[1] as usize, tf),
+        NR_FSTAT => sys_fstat(tf.regs[0] as usize, tf.regs[1] as usize, tf),
+        NR_SET_TIMES => sys_set_times(tf.regs[0] as usize, tf.regs[1], tf.regs[2], tf),
+        NR_SET_ATTRIBUTES => sys_set_attributes(tf.regs[0] as usize, tf.regs[1], tf),
+        NR_UNLINK => sys_unlink(tf.regs[0] as usize, tf),
+        NR_TRUNCATE => sys_truncate(tf.regs[0] as usize, tf.regs[1], tf),
+        NR_CHDIR => sys_chdir(tf.regs[0] as usize, tf),
+        NR_GETCWD => sys_getcwd(tf.regs[0] as usize, tf.regs[1] as usize, tf),
+        NR_SOCK_CREATE => sys_sock_create(tf.regs[0], tf),
         NR_SOCK_STATUS => sys_sock_status(tf.regs[0] as usize, tf),
-        NR_SOCK_CONNECT => sys_sock_connect(
+        NR_SOCK_CONNECT => sys_sock_connect(tf.regs[0] as usize, ipv4_endpoint(tf.regs[1], tf.regs[2]), tf),
+        NR_SOCK_LISTEN => sys_sock_listen(tf.regs[0] as usize, tf.regs[1] as u16, tf.regs[2] as usize, tf),
+        NR_SOCK_ACCEPT => sys_sock_accept(tf.regs[0] as usize, tf),
+        NR_SOCK_CONFIGURE => sys_sock_configure(tf.regs[0] as usize, tf.regs[1], tf.regs[2], tf),
+        NR_WALL_TIME => sys_wall_time(tf),
+        NR_SET_WALL_TIME => sys_set_wall_time(tf.regs[0], tf.regs[1], tf),
+        NR_SOCK_SEND => sys_sock_send(
             tf.regs[0] as usize,
-            IpEndpoint {
-                addr: IpAddress::Ipv4(Ipv4Address::new(
-                    (tf.regs[1] >> 24) as u8,
-                    (tf.regs[1] >> 16) as u8,
-                    (tf.regs[1] >> 8) as u8,
-                    tf.regs[1] as u8,
-                )),
-                port: tf.regs[2] as u16,
-            },
+            tf.regs[1] as usize,
+            tf.regs[2] as usize,
             tf,
         ),
-        NR_SOCK_LISTEN => sys_sock_listen(tf.regs[0] as usize, tf.regs[1] as u16, tf),
-        NR_SOCK_SEND => sys_sock_send(
+        NR_SOCK_RECV => sys_sock_recv(
             tf.regs[0] as usize,
             tf.regs[1] as usize,
             tf.regs[2] as usize,
             tf,
         ),
-        NR_SOCK_RECV => sys_sock_recv(
+        NR_SOCK_BIND => sys_sock_bind(tf.regs[0] as usize, tf.regs[1] as u16, tf),
+        NR_SOCK_SENDTO => sys_sock_sendto(
+            tf.regs[0] as usize,
+            tf.regs[1] as usize,
+            tf.regs[2] as usize,
+            ipv4_endpoint(tf.regs[3], tf.regs[4]),
+            tf,
+        ),
+        NR_SOCK_RECVFROM => sys_sock_recvfrom(
             tf.regs[0] as usize,
             tf.regs[1] as usize,
             tf.regs[2] as usize,
@@ -131,23 +157,42 @@ pub fn sys_time(tf: &mut TrapFrame) {
     tf.regs[7] = 1;
 }
 
-/// Kills the current process.
+/// Returns the wall-clock time.
 ///
-/// This system call does not take paramer and does not return any value.
-pub fn sys_exit(tf: &mut TrapFrame) {
-    // get parent_semaphore
-    let parent_semaphore = SCHEDULER.with_current_process_mut(tf, |process| process.parent.clone());
-    if let Some(parent_semaphore) = parent_semaphore {
-        // set parent semaphore
-        let mut g = parent_semaphore.lock();
-        g.complete();
-        g.exit_code = Some(0); // TODO: add support for exit codes
-    }
+/// This system call does not take parameter.
+///
+/// In addition to the usual status value, this system call returns two
+/// parameters: the seconds since the Unix epoch and the nanoseconds within
+/// the second.
+pub fn sys_wall_time(tf: &mut TrapFrame) {
+    let now = crate::clock::now();
+    tf.regs[0] = now.as_secs();
+    tf.regs[1] = now.subsec_nanos() as u64;
+    tf.regs[7] = OsError::Ok as u64;
+}
+
+/// Sets the wall-clock time that file timestamps are taken from.
+///
+/// This system call takes the seconds since the Unix epoch and the
+/// nanoseconds within the second. It fails with `OsError::InvalidArgument` if
+/// the nanoseconds are not below one second or the time cannot be kept.
+pub fn sys_set_wall_time(secs: u64, nanos: u64, tf: &mut TrapFrame) {
+    let result = match u32::try_from(nanos) {
+        Ok(nanos) if nanos < 1_000_000_000 => crate::clock::set(Duration::new(secs, nanos)),
+        _ => Err(OsError::InvalidArgument),
+    };
+    tf.regs[7] = match result {
+        Ok(()) => OsError::Ok,
+        Err(e) => e,
+    } as u64;
+}
 
-    // remove from scheduler
-    let id = SCHEDULER.kill(tf).expect("failed to kill process");
-    assert!(id == tf.tpidr);
-    GlobalScheduler::idle_thread();
+/// Kills the current process.
+///
+/// This system call takes the exit code as the only parameter and does not
+/// return. The code is reported to the parent by `wait`.
+pub fn sys_exit(code: i32, tf: &mut TrapFrame) {
+    SCHEDULER.exit(tf, ExitStatus { code, killed: false });
 }
 
 /// Returns the current process's ID.
@@ -161,31 +206,52 @@ pub fn sys_getpid(tf: &mut TrapFrame) {
     tf.regs[7] = 1;
 }
 
-/// Returns a slice from a virtual address and a legnth.
+/// Checks that `[va, va + len)` is in userspace and populates the pages of
+/// the range that are reserved but have not been touched yet, so that the
+/// kernel does not fault on them. If `write` is set, copy-on-write pages of
+/// the range are resolved as well.
+///
+/// Must not be called while the scheduler lock is held.
 ///
 /// # Errors
-/// This functions returns `Err(OsError::BadAddress)` if the slice is not entirely
-/// in userspace.
-unsafe fn to_user_slice<'a>(va: usize, len: usize) -> OsResult<&'a [u8]> {
+/// This functions returns `Err(OsError::BadAddress)` if the range is not
+/// entirely in userspace or covers memory the process cannot access.
+fn populate_user_range(tf: &TrapFrame, va: usize, len: usize, write: bool) -> OsResult<()> {
     let overflow = va.checked_add(len).is_none();
-    if va >= USER_IMG_BASE && !overflow {
-        Ok(core::slice::from_raw_parts(va as *const u8, len))
+    if va < USER_IMG_BASE || overflow {
+        return Err(OsError::BadAddress);
+    }
+    if len == 0 {
+        return Ok(());
+    }
+
+    let populated = SCHEDULER.with_current_process_mut(tf, |process| {
+        process.vmap.populate(VirtualAddr::from(va), len, write)
+    });
+    if populated {
+        Ok(())
     } else {
         Err(OsError::BadAddress)
     }
 }
+
+/// Returns a slice from a virtual address and a legnth.
+///
+/// # Errors
+/// This functions returns `Err(OsError::BadAddress)` if the slice is not entirely
+/// in userspace.
+unsafe fn to_user_slice<'a>(tf: &TrapFrame, va: usize, len: usize) -> OsResult<&'a [u8]> {
+    populate_user_range(tf, va, len, false)?;
+    Ok(core::slice::from_raw_parts(va as *const u8, len))
+}
 /// Returns a mutable slice from a virtual address and a legnth.
 ///
 /// # Errors
 /// This functions returns `Err(OsError::BadAddress)` if the slice is not entirely
 /// in userspace.
-unsafe fn to_user_slice_mut<'a>(va: usize, len: usize) -> OsResult<&'a mut [u8]> {
-    let overflow = va.checked_add(len).is_none();
-    if va >= USER_IMG_BASE && !overflow {
-        Ok(core::slice::from_raw_parts_mut(va as *mut u8, len))
-    } else {
-        Err(OsError::BadAddress)
-    }
+unsafe fn to_user_slice_mut<'a>(tf: &TrapFrame, va: usize, len: usize) -> OsResult<&'a mut [u8]> {
+    populate_user_range(tf, va, len, true)?;
+    Ok(core::slice::from_raw_parts_mut(va as *mut u8, len))
 }
 /// Writes a UTF-8 string to the console.
 ///
@@ -201,7 +267,7 @@ unsafe fn to_user_slice_mut<'a>(va: usize, len: usize) -> OsResult<&'a mut [u8]>
 /// - `OsError::BadAddress`: The address and the length pair does not form a valid userspace slice.
 /// - `OsError::InvalidArgument`: The provided buffer is not UTF-8 encoded.
 pub fn sys_write_str(va: usize, len: usize, tf: &mut TrapFrame) {
-    let result = unsafe { to_user_slice(va, len) }
+    let result = unsafe { to_user_slice(tf, va, len) }
         .and_then(|slice| core::str::from_utf8(slice).map_err(|_| OsError::InvalidArgument));
 
     match result {
@@ -220,50 +286,52 @@ pub fn sys_write_str(va: usize, len: usize, tf: &mut TrapFrame) {
 use crate::mutex::Mutex;
 use crate::process::ChildStatus;
 use alloc::sync::Arc;
-pub fn sys_open(va: usize, tf: &mut TrapFrame) {
-    let path = match unsafe { to_user_slice(va, 256) } {
-        Ok(slice) => {
-            let s = core::str::from_utf8(slice.split(|&c| c == 0).next().unwrap_or(&[]))
-                .unwrap_or("[invalid utf8]");
-            s
-        }
-        Err(_) => {
-            tf.regs[7] = OsError::BadAddress as u64;
+/// Opens a file or directory.
+///
+/// This system call takes the address of a NUL-terminated path and `O_*`
+/// flags. With `O_CREAT`, a missing file is created; with `O_TRUNC`, the file
+/// is truncated to length 0.
+pub fn sys_open(va: usize, flags: u64, tf: &mut TrapFrame) {
+    let path = match user_path(tf, va) {
+        Ok(path) => path,
+        Err(e) => {
+            tf.regs[7] = e as u64;
             return;
         }
     };
 
-    if path.is_empty() {
-        tf.regs[7] = OsError::NoEntry as u64;
-        return;
+    let file = match VFS.open(&path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound && flags & O_CREAT != 0 => VFS.create_file(&path),
+        result => result,
     }
+    .map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => OsError::NoEntry,
+        _ => OsError::from(e),
+    })
+    .and_then(|vnode| {
+        let mut file = vnode.open();
+        if flags & O_TRUNC != 0 {
+            file.set_len(0)?;
+        }
+        Ok(file)
+    });
 
-    match crate::FILESYSTEM.open(path) {
-        Ok(entry) => {
+    match file {
+        Ok(file) => {
             let fd = SCHEDULER.with_current_process_mut(tf, |process| {
                 let fd = process.files.len();
-                match entry {
-                    fat32::vfat::Entry::FileEntry(file) => {
-                        process.files.push(Some(crate::process::ProcessFile {
-                            handle: Arc::new(Mutex::new(Box::new(file))),
-                            offset: 0,
-                        }));
-                    }
-                    fat32::vfat::Entry::DirEntry(dir) => {
-                        process.files.push(Some(crate::process::ProcessFile {
-                            handle: Arc::new(Mutex::new(Box::new(dir))),
-                            offset: 0,
-                        }));
-                    }
-                }
+                process.files.push(Some(crate::process::ProcessFile {
+                    handle: Arc::new(Mutex::new(file)),
+                    offset: 0,
+                }));
                 fd
             });
 
             tf.regs[0] = fd as u64;
             tf.regs[7] = OsError::Ok as u64;
         }
-        Err(_) => {
-            tf.regs[7] = OsError::NoEntry as u64;
+        Err(e) => {
+            tf.regs[7] = e as u64;
         }
     }
 }
@@ -289,7 +357,7 @@ pub fn sys_read(fd: usize, va: usize, len: usize, tf: &mut TrapFrame) {
         Some(y.handle.clone()) // Clone the Arc (increases reference count)
     });
 
-    let buf = match unsafe { to_user_slice_mut(va, len) } {
+    let buf = match unsafe { to_user_slice_mut(tf, va, len) } {
         Ok(slice) => slice,
         Err(_) => {
             tf.regs[7] = OsError::BadAddress as u64;
@@ -324,7 +392,7 @@ pub fn sys_write(fd: usize, va: usize, len: usize, tf: &mut TrapFrame) {
         let y = process.files[fd].as_mut().unwrap();
         Some(y.handle.clone()) // Clone the Arc (increases reference count)
     });
-    let buf = match unsafe { to_user_slice(va, len) } {
+    let buf = match unsafe { to_user_slice(tf, va, len) } {
         Ok(slice) => slice,
         Err(_) => {
             tf.regs[7] = OsError::BadAddress as u64;
@@ -350,22 +418,56 @@ pub fn sys_write(fd: usize, va: usize, len: usize, tf: &mut TrapFrame) {
     }
 }
 
-pub fn sys_seek(fd: usize, offset: usize, tf: &mut TrapFrame) {
-    let result = SCHEDULER.with_current_process_mut(tf, |process| {
-        if fd >= process.files.len() || process.files[fd].is_none() {
-            return OsError::InvalidFile as u64;
+/// Moves the position of an open file.
+///
+/// This system call takes three parameters: the file descriptor, a signed
+/// offset and `whence`, which is one of `SEEK_SET`, `SEEK_CUR` or `SEEK_END`
+/// and says whether the offset is relative to the start of the file, the
+/// current position or the end of the file.
+///
+/// In addition to the usual status value, this system call returns the new
+/// position from the start of the file.
+///
+/// # Errors
+///
+/// - `OsError::InvalidFile`: `fd` is not an open file.
+/// - `OsError::InvalidArgument`: `whence` is not a valid value.
+/// - `OsError::IoErrorInvalidInput`: the new position would be before the
+///   start or past the end of the file, or the file cannot seek.
+pub fn sys_seek(fd: usize, offset: i64, whence: u64, tf: &mut TrapFrame) {
+    let pos = match whence {
+        SEEK_SET if offset >= 0 => io::SeekFrom::Start(offset as u64),
+        SEEK_SET => {
+            tf.regs[7] = OsError::IoErrorInvalidInput as u64;
+            return;
         }
-
-        let y = process.files[fd].as_mut().unwrap();
-        let handle = y.handle.clone();
-        let res = handle.lock().seek(offset);
-        match res {
-            Ok(_) => OsError::Ok as u64,
-            Err(_) => OsError::IoError as u64,
+        SEEK_CUR => io::SeekFrom::Current(offset),
+        SEEK_END => io::SeekFrom::End(offset),
+        _ => {
+            tf.regs[7] = OsError::InvalidArgument as u64;
+            return;
         }
+    };
+
+    let handle = SCHEDULER.with_current_process_mut(tf, |process| {
+        process.files.get(fd).and_then(|file| file.as_ref()).map(|file| file.handle.clone())
     });
+    let handle = match handle {
+        Some(handle) => handle,
+        None => {
+            tf.regs[7] = OsError::InvalidFile as u64;
+            return;
+        }
+    };
 
-    tf.regs[7] = result;
+    let res = handle.lock().seek(pos);
+    match res {
+        Ok(new_pos) => {
+            tf.regs[0] = new_pos;
+            tf.regs[7] = OsError::Ok as u64;
+        }
+        Err(e) => tf.regs[7] = OsError::from(e) as u64,
+    }
 }
 
 pub fn sys_len(fd: usize, tf: &mut TrapFrame) {
@@ -385,6 +487,15 @@ pub fn sys_len(fd: usize, tf: &mut TrapFrame) {
 }
 
 pub fn sys_readdir(fd: usize, user_buf: usize, buf_len: usize, tf: &mut TrapFrame) {
+    // Validate user-space buffer before taking the scheduler lock
+    let user_buffer = match unsafe { to_user_slice_mut(tf, user_buf, buf_len) } {
+        Ok(buf) => buf,
+        Err(_) => {
+            tf.regs[7] = OsError::BadAddress as u64;
+            return;
+        }
+    };
+
     let (result, bytes_read) = SCHEDULER.with_current_process_mut(tf, |process| {
         if fd >= process.files.len() || process.files[fd].is_none() {
             return (OsError::InvalidFile as u64, 0);
@@ -397,14 +508,6 @@ pub fn sys_readdir(fd: usize, user_buf: usize, buf_len: usize, tf: &mut TrapFram
             return (OsError::InvalidDirectory as u64, 0);
         }
 
-        // Validate user-space buffer before writing to it
-        let user_buffer = match unsafe { to_user_slice_mut(user_buf, buf_len) } {
-            Ok(buf) => buf,
-            Err(_) => {
-                return (OsError::BadAddress as u64, 0);
-            }
-        };
-
         // Read directory entries into user buffer
         let y = process.files[fd].as_mut().unwrap();
         let handle = y.handle.clone();
@@ -420,20 +523,268 @@ pub fn sys_readdir(fd: usize, user_buf: usize, buf_len: usize, tf: &mut TrapFram
     tf.regs[7] = result;
 }
 
+/// Reads the NUL-terminated path at user address `va` and resolves it
+/// against the current process's working directory.
+///
+/// # Errors
+///
+/// Returns `OsError::BadAddress` if the path is not in userspace or has no
+/// terminator within `MAX_PATH_LEN` bytes, `OsError::InvalidArgument` if it is
+/// not UTF-8 encoded and `OsError::NoEntry` if it is empty.
+fn user_path(tf: &TrapFrame, va: usize) -> OsResult<PathBuf> {
+    let path = user_str(tf, va, MAX_PATH_LEN)?;
+    if path.is_empty() {
+        return Err(OsError::NoEntry);
+    }
+    Ok(SCHEDULER.with_current_process_mut(tf, |process| process.resolve_path(&path)))
+}
+
+/// Reads the NUL-terminated string at `va`, of at most `max` bytes, from user
+/// memory. The string is read up to a page at a time, so only the pages it
+/// occupies need to be mapped.
+///
+/// # Errors
+///
+/// Returns `OsError::BadAddress` if part of the string is not mapped or it has
+/// no terminator within `max` bytes, and `OsError::InvalidArgument` if it is
+/// not UTF-8.
+fn user_str(tf: &TrapFrame, va: usize, max: usize) -> OsResult<String> {
+    let mut bytes = Vec::new();
+    let mut addr = va;
+    while bytes.len() <= max {
+        let len = (PAGE_SIZE - addr % PAGE_SIZE).min(max + 1 - bytes.len());
+        let chunk = unsafe { to_user_slice(tf, addr, len) }?;
+        if let Some(end) = chunk.iter().position(|&c| c == 0) {
+            bytes.extend_from_slice(&chunk[..end]);
+            return String::from_utf8(bytes).map_err(|_| OsError::InvalidArgument);
+        }
+        bytes.extend_from_slice(chunk);
+        addr += len;
+    }
+    Err(OsError::BadAddress)
+}
+
+/// Changes the working directory of the current process.
+///
+/// This system call takes the address of a NUL-terminated path as its only
+/// parameter. It fails with `OsError::InvalidDirectory` if the entry at the
+/// path is not a directory. Children inherit the working directory on `fork`,
+/// and it is kept across `exec`.
+pub fn sys_chdir(va: usize, tf: &mut TrapFrame) {
+    let result = user_path(tf, va).and_then(|path| {
+        let path = crate::fs::vfs::normalize(&path)?;
+        if !VFS.open(&path)?.is_dir() {
+            return Err(OsError::InvalidDirectory);
+        }
+        SCHEDULER.with_current_process_mut(tf, |process| process.cwd = path);
+        Ok(())
+    });
+    tf.regs[7] = match result {
+        Ok(()) => OsError::Ok,
+        Err(e) => e,
+    } as u64;
+}
+
+/// Returns the working directory of the current process.
+///
+/// This system call takes the address and length of a buffer that it fills
+/// with the absolute path of the working directory, without a NUL
+/// terminator, and returns the length of the path. It fails with
+/// `OsError::InvalidArgument` if the buffer is too short.
+pub fn sys_getcwd(va: usize, len: usize, tf: &mut TrapFrame) {
+    let result = unsafe { to_user_slice_mut(tf, va, len) }.and_then(|buf| {
+        SCHEDULER.with_current_process_mut(tf, |process| {
+            let cwd = process.cwd.to_str().ok_or(OsError::InvalidArgument)?.as_bytes();
+            buf.get_mut(..cwd.len()).ok_or(OsError::InvalidArgument)?.copy_from_slice(cwd);
+            Ok(cwd.len())
+        })
+    });
+    match result {
+        Ok(len) => {
+            tf.regs[0] = len as u64;
+            tf.regs[7] = OsError::Ok as u64;
+        }
+        Err(e) => tf.regs[7] = e as u64,
+    }
+}
+
+/// Creates an empty directory.
+///
+/// This system call takes the address of a NUL-terminated path as its only
+/// parameter. It fails with `OsError::FileExists` if an entry already exists
+/// at the path and with `OsError::NoEntry` if the parent does not exist.
+pub fn sys_mkdir(va: usize, tf: &mut TrapFrame) {
+    let result = user_path(tf, va)
+        .and_then(|path| VFS.create_dir(path).map_err(OsError::from));
+    tf.regs[7] = match result {
+        Ok(_) => OsError::Ok,
+        Err(e) => e,
+    } as u64;
+}
+
+/// Removes a file.
+///
+/// This system call takes the address of a NUL-terminated path as its only
+/// parameter. It fails with `OsError::IoError` if the entry is a directory.
+/// Descriptors that have a file on the tmpfs open keep working until they are
+/// closed. On the FAT file system the file's clusters are freed at once, so
+/// reads and writes through its descriptors fail with `OsError::NoEntry`.
+pub fn sys_unlink(va: usize, tf: &mut TrapFrame) {
+    let result = user_path(tf, va).and_then(|path| VFS.remove_file(path).map_err(OsError::from));
+    tf.regs[7] = match result {
+        Ok(()) => OsError::Ok,
+        Err(e) => e,
+    } as u64;
+}
+
+/// Removes an empty directory.
+///
+/// This system call takes the address of a NUL-terminated path as its only
+/// parameter. It fails with `OsError::IoError` if the entry is not a
+/// directory or is not empty.
+pub fn sys_rmdir(va: usize, tf: &mut TrapFrame) {
+    let result = user_path(tf, va)
+        .and_then(|path| VFS.remove_dir(path).map_err(OsError::from));
+    tf.regs[7] = match result {
+        Ok(()) => OsError::Ok,
+        Err(e) => e,
+    } as u64;
+}
+
+/// Renames or moves a file or directory.
+///
+/// This system call takes the addresses of two NUL-terminated paths: the
+/// entry to move and its new path. It fails with `OsError::FileExists` if an
+/// entry already exists at the new path and with `OsError::IoErrorInvalidInput`
+/// if the paths are on different file systems.
+pub fn sys_rename(from_va: usize, to_va: usize, tf: &mut TrapFrame) {
+    let result = user_path(tf, from_va).and_then(|from| {
+        let to = user_path(tf, to_va)?;
+        VFS.rename(from, to).map_err(OsError::from)
+    });
+    tf.regs[7] = match result {
+        Ok(()) => OsError::Ok,
+        Err(e) => e,
+    } as u64;
+}
+
+/// Writes every modified sector of the mounted file systems back to their
+/// disks.
+///
+/// This system call takes no parameters. Writes to files are kept in the
+/// kernel's sector cache until they are synced or evicted.
+pub fn sys_sync(tf: &mut TrapFrame) {
+    tf.regs[7] = match VFS.sync() {
+        Ok(()) => OsError::Ok,
+        Err(e) => OsError::from(e),
+    } as u64;
+}
+
+/// Copies `stat` to the `Stat` at user address `va`.
+fn write_user_stat(tf: &TrapFrame, va: usize, stat: Stat) -> OsResult<()> {
+    let buf = unsafe { to_user_slice_mut(tf, va, core::mem::size_of::<Stat>()) }?;
+    unsafe { (buf.as_mut_ptr() as *mut Stat).write_unaligned(stat) };
+    Ok(())
+}
+
+/// Returns the handle of the open file `fd` of the current process.
+fn file_handle(tf: &TrapFrame, fd: usize) -> OsResult<Arc<Mutex<Box<dyn ProcessFileT>>>> {
+    SCHEDULER
+        .with_current_process_mut(tf, |process| {
+            process.files.get(fd).and_then(|file| file.as_ref()).map(|file| file.handle.clone())
+        })
+        .ok_or(OsError::InvalidFile)
+}
+
+/// Returns the metadata of a file or directory.
+///
+/// This system call takes the address of a NUL-terminated path and the
+/// address of a `Stat` that it fills in with the size, attributes and
+/// timestamps of the entry at the path.
+pub fn sys_stat(path_va: usize, stat_va: usize, tf: &mut TrapFrame) {
+    let result = user_path(tf, path_va).and_then(|path| {
+        let stat = VFS.open(path)?.stat()?;
+        write_user_stat(tf, stat_va, stat)
+    });
+    tf.regs[7] = match result {
+        Ok(()) => OsError::Ok,
+        Err(e) => e,
+    } as u64;
+}
+
+/// Returns the metadata of an open file or directory.
+///
+/// This system call takes a file descriptor and the address of a `Stat` that
+/// it fills in. It fails with `OsError::IoErrorInvalidInput` if the file, like
+/// the console, has no metadata.
+pub fn sys_fstat(fd: usize, stat_va: usize, tf: &mut TrapFrame) {
+    let result = file_handle(tf, fd).and_then(|handle| {
+        let stat = handle.lock().stat()?;
+        write_user_stat(tf, stat_va, stat)
+    });
+    tf.regs[7] = match result {
+        Ok(()) => OsError::Ok,
+        Err(e) => e,
+    } as u64;
+}
+
+/// Sets the access and modification times of an open file or directory.
+///
+/// This system call takes a file descriptor and the new access and
+/// modification times as Unix times; `TIME_OMIT` leaves a time unchanged.
+/// The changes reach the disk on the next `sync`.
+pub fn sys_set_times(fd: usize, accessed: u64, modified: u64, tf: &mut TrapFrame) {
+    let time = |secs| Some(secs).filter(|&secs| secs != TIME_OMIT);
+    let result = file_handle(tf, fd).and_then(|handle| {
+        handle.lock().set_times(time(accessed), time(modified)).map_err(OsError::from)
+    });
+    tf.regs[7] = match result {
+        Ok(()) => OsError::Ok,
+        Err(e) => e,
+    } as u64;
+}
+
+/// Sets the attributes of an open file or directory.
+///
+/// This system call takes a file descriptor and the new `ATTR_READ_ONLY`,
+/// `ATTR_HIDDEN`, `ATTR_SYSTEM` and `ATTR_ARCHIVE` bits. It fails with
+/// `OsError::IoErrorInvalidInput` if any other bit is set.
+pub fn sys_set_attributes(fd: usize, attributes: u64, tf: &mut TrapFrame) {
+    let result = file_handle(tf, fd).and_then(|handle| {
+        let attributes = u8::try_from(attributes).map_err(|_| OsError::IoErrorInvalidInput)?;
+        handle.lock().set_attributes(attributes).map_err(OsError::from)
+    });
+    tf.regs[7] = match result {
+        Ok(()) => OsError::Ok,
+        Err(e) => e,
+    } as u64;
+}
+
+/// Truncates or zero-extends an open file.
+///
+/// This system call takes a file descriptor and the new length in bytes. It
+/// fails with `OsError::IoErrorInvalidInput` if the descriptor is not a
+/// regular file.
+pub fn sys_truncate(fd: usize, len: u64, tf: &mut TrapFrame) {
+    let result = file_handle(tf, fd).and_then(|handle| handle.lock().set_len(len).map_err(OsError::from));
+    tf.regs[7] = match result {
+        Ok(()) => OsError::Ok,
+        Err(e) => e,
+    } as u64;
+}
+
 use crate::process::Process;
-use shim::path::Path;
 pub fn sys_exec(va: usize, tf: &mut TrapFrame) {
     trace!("[sys_exec] Received request to exec at VA: {:#x}", va);
 
     // Read the path string
-    let path = match unsafe { to_user_slice(va, 256) } {
-        Ok(slice) => core::str::from_utf8(slice).unwrap_or(""),
-        Err(_) => {
-            tf.regs[7] = OsError::BadAddress as u64;
+    let path = match user_path(tf, va) {
+        Ok(path) => path,
+        Err(e) => {
+            tf.regs[7] = e as u64;
             return;
         }
     };
-    let clean_path = path.trim_end_matches('\0');
 
     // Instead of reading argv from the stack, get it from tf.regs[1]
     let argv_ptr = tf.regs[1] as usize;
@@ -441,7 +792,7 @@ pub fn sys_exec(va: usize, tf: &mut TrapFrame) {
 
     if argv_ptr != 0 {
         // For example, assume the argv buffer is 256 bytes long.
-        let argv_slice = match unsafe { to_user_slice(argv_ptr, 256) } {
+        let argv_slice = match unsafe { to_user_slice(tf, argv_ptr, 256) } {
             Ok(slice) => slice,
             Err(_) => {
                 tf.regs[7] = OsError::BadAddress as u64;
@@ -459,49 +810,40 @@ pub fn sys_exec(va: usize, tf: &mut TrapFrame) {
                     break;
                 }
                 let ptr_bytes: [u8; 8] = argv_slice[start..end].try_into().unwrap();
-                let arg_ptr = u64::from_ne_bytes(ptr_bytes) as *const u8;
-                if arg_ptr.is_null() {
+                let arg_ptr = u64::from_ne_bytes(ptr_bytes) as usize;
+                if arg_ptr == 0 {
                     break;
                 }
-                // Read the null-terminated string from user memory.
-                let arg_str = unsafe {
-                    let mut len = 0;
-                    while core::ptr::read(arg_ptr.add(len)) != 0 {
-                        len += 1;
+                match user_str(tf, arg_ptr, MAX_ARG_LEN) {
+                    Ok(arg) => args.push(arg),
+                    Err(e) => {
+                        tf.regs[7] = e as u64;
+                        return;
                     }
-                    core::str::from_utf8(core::slice::from_raw_parts(arg_ptr, len))
-                        .unwrap_or("[Invalid UTF-8]")
-                };
-                // move to heap then push
-                use crate::alloc::string::ToString;
-                let arg_str = arg_str.to_string();
-                args.push(arg_str);
+                }
             }
         }
     }
 
-    debug!("[sys_exec] Executing: '{}'", clean_path);
+    debug!("[sys_exec] Executing: '{}'", path.display());
     debug!("[sys_exec] Args: {:?}", args);
     debug!("core {} is running execve", affinity());
 
     // Run execve() and update process.context, etc.
     let new_tf = SCHEDULER.with_current_process_mut(tf, |process| {
-        match Process::execve(process, Path::new(clean_path), args) {
-            Ok(_) => Some(*process.context),
-            Err(_) => None,
-        }
+        Process::execve(process, &path, args).map(|_| *process.context)
     });
 
     trace!("[sys_exec] tf: {:#x?}", new_tf);
     match new_tf {
-        Some(context) => {
+        Ok(context) => {
             debug!("[sys_exec] Switching to user mode at {:#x}", context.pc);
             *tf = context; // Update the trap frame
                            // TLB flush happens before eret
         }
-        None => {
-            trace!("[sys_exec] ERROR: execve() failed!");
-            tf.regs[7] = OsError::InvalidFile as u64;
+        Err(e) => {
+            trace!("[sys_exec] ERROR: execve() failed: {:?}", e);
+            tf.regs[7] = e as u64;
         }
     }
 }
@@ -513,7 +855,7 @@ pub fn sys_fork(tf: &mut TrapFrame) {
     let mut new_proc = SCHEDULER.with_current_process_mut(tf, |parent| {
         // Create a new process
         parent.children.push(child_fut.clone());
-        parent.clone()
+        parent.fork()
     });
 
     new_proc.state = State::Ready;
@@ -535,42 +877,195 @@ pub fn sys_fork(tf: &mut TrapFrame) {
     tf.regs[0] = id as u64;
 }
 
-pub fn sys_wait(tf: &mut TrapFrame, pid: usize) {
+/// Waits for a child process to terminate and reaps it.
+///
+/// This system call takes two parameters: the process ID of the child to wait
+/// for, or `-1` for any child, and flags. If the flags contain `WNOHANG`, the
+/// call returns immediately instead of blocking when no selected child has
+/// terminated yet.
+///
+/// In addition to the usual status value, this system call returns three
+/// parameters: the reaped child's process ID (`0` if none has terminated yet
+/// under `WNOHANG`), its exit code and `1` if the kernel killed the child
+/// because of a fault (`0` if it exited normally).
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::InvalidFile`: No child of the current process matches `pid`.
+pub fn sys_wait(tf: &mut TrapFrame, pid: i64, flags: u64) {
+    if flags & WNOHANG != 0 {
+        let result = SCHEDULER.with_current_process_mut(tf, |process| reap_child(process, pid));
+        set_wait_result(tf, result);
+        return;
+    }
+
     let boxed_fnmut = Box::new(move |process: &mut crate::process::Process| {
-        let mut child = None;
-        let mut child_done: bool = false;
-        for c in process.children.iter() {
-            let g = c.lock();
-            if g.pid == Some(pid as u64) {
-                child = Some(c.clone());
-                child_done = g.done;
-                break;
+        match reap_child(process, pid) {
+            Ok(None) => false,
+            result => {
+                set_wait_result(&mut process.context, result);
+                true
             }
         }
+    });
+
+    SCHEDULER.block(State::Waiting(Some(boxed_fnmut)), tf);
+}
+
+/// Finds a terminated child of `process` matching `pid` (`-1` matches any
+/// child) and drops its `ChildStatus` from `process.children`.
+///
+/// Returns `Ok(None)` if matching children exist but none has terminated yet.
+///
+/// # Errors
+/// Returns `OsError::InvalidFile` if no child matches `pid`.
+fn reap_child(process: &mut Process, pid: i64) -> OsResult<Option<(Id, ExitStatus)>> {
+    let mut matched = false;
+    let mut reaped = None;
+    for (i, child) in process.children.iter().enumerate() {
+        let g = child.lock();
+        if pid >= 0 && g.pid != Some(pid as Id) {
+            continue;
+        }
+        matched = true;
+        if let (true, Some(child_pid), Some(status)) = (g.done, g.pid, g.exit_status) {
+            reaped = Some((i, child_pid, status));
+            break;
+        }
+    }
+
+    match reaped {
+        Some((i, child_pid, status)) => {
+            process.children.remove(i);
+            Ok(Some((child_pid, status)))
+        }
+        None if matched => Ok(None),
+        None => Err(OsError::InvalidFile),
+    }
+}
 
-        if child.is_none() {
-            process.context.regs[7] = OsError::InvalidFile as u64;
-            return true;
+/// Stores the result of `reap_child()` in the return registers of `tf`.
+fn set_wait_result(tf: &mut TrapFrame, result: OsResult<Option<(Id, ExitStatus)>>) {
+    match result {
+        Ok(Some((pid, status))) => {
+            tf.regs[0] = pid;
+            tf.regs[1] = status.code as u64;
+            tf.regs[2] = status.killed as u64;
+            tf.regs[7] = OsError::Ok as u64;
+        }
+        Ok(None) => {
+            tf.regs[0] = 0;
+            tf.regs[7] = OsError::Ok as u64;
+        }
+        Err(e) => {
+            tf.regs[7] = e as u64;
+        }
+    }
+}
+
+/// Moves the end of the current process's heap (the program break) to `addr`.
+///
+/// This system call takes the requested break as the only parameter. A
+/// requested break of `0` leaves the heap unchanged. Pages of a grown heap are
+/// allocated lazily, when they are first accessed; pages of a shrunk heap are
+/// released.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the resulting program break.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::InvalidArgument`: `addr` is below the start of the heap.
+/// - `OsError::NoVmSpace`: The heap would run into other mapped memory.
+pub fn sys_brk(addr: usize, tf: &mut TrapFrame) {
+    let result = SCHEDULER.with_current_process_mut(tf, |process| {
+        if addr == 0 {
+            return Ok(process.brk);
         }
-        if child_done {
-            process.context.regs[0] = pid as u64;
-            // process.context.regs[1] = child.exit_code.unwrap() as u64;
-            process.context.regs[7] = OsError::Ok as u64;
+        if addr < process.heap_base {
+            return Err(OsError::InvalidArgument);
         }
 
-        child_done
+        let heap_base = VirtualAddr::from(process.heap_base);
+        process.vmap.resize(heap_base, addr - process.heap_base)?;
+        process.brk = addr;
+        Ok(addr)
     });
 
-    SCHEDULER.block(State::Waiting(Some(boxed_fnmut)), tf);
+    match result {
+        Ok(brk) => {
+            tf.regs[0] = brk as u64;
+            tf.regs[7] = OsError::Ok as u64;
+        }
+        Err(e) => {
+            tf.regs[7] = e as u64;
+        }
+    }
+}
+
+/// Maps `len` bytes of anonymous, zero-filled, read-write memory into the
+/// current process.
+///
+/// This system call takes the length of the mapping as the only parameter.
+/// Mappings are placed top-down below the user stack and their pages are
+/// allocated lazily, when they are first accessed.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the page-aligned start address of the mapping.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::InvalidArgument`: `len` is zero.
+/// - `OsError::NoVmSpace`: There is no room left for the mapping.
+pub fn sys_mmap(len: usize, tf: &mut TrapFrame) {
+    let result = SCHEDULER.with_current_process_mut(tf, |process| {
+        if len == 0 {
+            return Err(OsError::InvalidArgument);
+        }
+        if len > USER_MAX_VM_SIZE {
+            return Err(OsError::NoVmSpace);
+        }
+
+        let size = align_up(len, PAGE_SIZE);
+        let start = process.mmap_base.checked_sub(size).ok_or(OsError::NoVmSpace)?;
+        process.vmap.reserve(VirtualAddr::from(start), size, PagePerm::RW)?;
+        process.mmap_base = start;
+        Ok(start)
+    });
+
+    match result {
+        Ok(start) => {
+            tf.regs[0] = start as u64;
+            tf.regs[7] = OsError::Ok as u64;
+        }
+        Err(e) => {
+            tf.regs[7] = e as u64;
+        }
+    }
 }
 
 /// socket list.
 ///
-pub fn sys_sock_create(tf: &mut TrapFrame) {
-    let handle = ETHERNET.add_socket();
-    SCHEDULER.with_current_process_mut(tf, |process| {
-        process.sockets.push(handle);
-        process.context.regs[0] = process.sockets.len() as u64 - 1;
+/// This system call takes the `SocketType` of the new socket as its only
+/// parameter. It fails with `OsError::InvalidArgument` for an unknown type.
+pub fn sys_sock_create(kind: u64, tf: &mut TrapFrame) {
+    let kind = match SocketType::from_raw(kind) {
+        Some(kind) => kind,
+        None => {
+            tf.regs[7] = OsError::InvalidArgument as u64;
+            return;
+        }
+    };
+    let handle = match kind {
+        SocketType::Tcp => ETHERNET.add_socket(),
+        SocketType::Udp => ETHERNET.add_udp_socket(),
+    };
+    tf.regs[0] = SCHEDULER.with_current_process_mut(tf, |process| {
+        process.sockets.push(ProcessSocket::new(handle, kind));
+        process.sockets.len() as u64 - 1
     });
     tf.regs[7] = OsError::Ok as u64;
     trace!("Socket created: {}", tf.regs[0]);
@@ -578,6 +1073,82 @@ pub fn sys_sock_create(tf: &mut TrapFrame) {
 
 use smoltcp::socket::SocketHandle;
 
+/// Returns the socket `sock_idx` of the current process.
+///
+/// # Errors
+///
+/// Returns `OsError::InvalidSocket` if there is no such socket and
+/// `OsError::IllegalSocketOperation` if it is not of type `kind`, when given.
+fn process_socket(tf: &TrapFrame, sock_idx: usize, kind: Option<SocketType>) -> OsResult<ProcessSocket> {
+    let socket = SCHEDULER
+        .with_current_process_mut(tf, |process| process.sockets.get(sock_idx).copied())
+        .ok_or(OsError::InvalidSocket)?;
+    match kind {
+        Some(kind) if kind != socket.kind => Err(OsError::IllegalSocketOperation),
+        _ => Ok(socket),
+    }
+}
+
+/// Returns the handle of the socket `sock_idx` of the current process. See
+/// `process_socket()` for the errors.
+fn socket_handle(tf: &TrapFrame, sock_idx: usize, kind: Option<SocketType>) -> OsResult<SocketHandle> {
+    process_socket(tf, sock_idx, kind).map(|socket| socket.handle)
+}
+
+/// Blocks the current process until `ready` holds for `socket`, unless it
+/// already does or the socket is nonblocking. Returns whether the caller can
+/// go on with the system call now.
+///
+/// Once `ready` holds, the process returns to its `svc` instruction and runs
+/// the system call again, so the caller must not have changed `tf` yet. If
+/// the socket's timeout passes first, the system call fails with
+/// `OsError::IoErrorTimedOut`.
+fn wait_for_socket(
+    socket: ProcessSocket,
+    ready: fn(&mut EthernetDriver, SocketHandle) -> bool,
+    tf: &mut TrapFrame,
+) -> bool {
+    let handle = socket.handle;
+    if socket.nonblocking || ETHERNET.critical(|eth| ready(eth, handle)) {
+        return true;
+    }
+
+    let deadline = socket.timeout.map(|timeout| timer::current_time() + timeout);
+    let boxed_fnmut = Box::new(move |process: &mut crate::process::Process| {
+        if ETHERNET.critical(|eth| ready(eth, handle)) {
+            process.context.pc -= 4;
+            true
+        } else if deadline.is_some_and(|deadline| timer::current_time() >= deadline) {
+            process.context.regs[7] = OsError::IoErrorTimedOut as u64;
+            true
+        } else {
+            false
+        }
+    });
+
+    SCHEDULER.block(State::Waiting(Some(boxed_fnmut)), tf);
+    false
+}
+
+/// Returns the `OsError` that a smoltcp socket error is reported as.
+fn socket_error(e: smoltcp::Error) -> OsError {
+    match e {
+        smoltcp::Error::Illegal => OsError::IllegalSocketOperation,
+        smoltcp::Error::Unaddressable => OsError::BadAddress,
+        smoltcp::Error::Exhausted => OsError::WouldBlock,
+        _ => OsError::Unknown,
+    }
+}
+
+/// Returns the IPv4 endpoint of the big-endian address `ip` and `port`, as
+/// passed in registers.
+fn ipv4_endpoint(ip: u64, port: u64) -> IpEndpoint {
+    IpEndpoint {
+        addr: IpAddress::Ipv4(Ipv4Address::from_bytes(&(ip as u32).to_be_bytes())),
+        port: port as u16,
+    }
+}
+
 /// Returns the status of a socket.
 ///
 /// This system call takes a socket descriptor as the first parameter.
@@ -594,26 +1165,24 @@ use smoltcp::socket::SocketHandle;
 /// This function returns `OsError::InvalidSocket` if a socket that corresponds
 /// to the provided descriptor is not found.
 pub fn sys_sock_status(sock_idx: usize, tf: &mut TrapFrame) {
-    let socket_handle: Option<SocketHandle> = SCHEDULER.with_current_process_mut(tf, |process| {
-        if sock_idx >= process.sockets.len() {
-            return None;
+    let socket = match SCHEDULER.with_current_process_mut(tf, |process| process.sockets.get(sock_idx).copied()) {
+        Some(socket) => socket,
+        None => {
+            tf.regs[7] = OsError::InvalidSocket as u64;
+            return;
         }
-        Some(process.sockets[sock_idx])
-    });
-
-    if socket_handle.is_none() {
-        tf.regs[7] = OsError::InvalidSocket as u64;
-        return;
-    }
-
-    let socket = socket_handle.unwrap();
-    let status = ETHERNET.with_socket(socket, |s| {
-        let is_active = s.is_active();
-        let is_listening = s.is_listening();
-        let can_send = s.can_send();
-        let can_recv = s.can_recv();
-        (is_active, is_listening, can_send, can_recv)
-    });
+    };
+    let status = match socket.kind {
+        SocketType::Tcp => ETHERNET.with_socket(socket.handle, |s| {
+            let is_active = s.is_active();
+            let is_listening = s.is_listening();
+            let can_send = s.can_send();
+            let can_recv = s.can_recv();
+            (is_active, is_listening, can_send, can_recv)
+        }),
+        // A UDP socket is active once it is bound and never listens
+        SocketType::Udp => ETHERNET.with_udp_socket(socket.handle, |s| (s.is_open(), false, s.can_send(), s.can_recv())),
+    };
     tf.regs[0] = status.0 as u64;
     tf.regs[1] = status.1 as u64;
     tf.regs[2] = status.2 as u64;
@@ -646,19 +1215,13 @@ pub fn sys_sock_connect(
     tf: &mut TrapFrame,
 ) {
     
-    let socket_handle: Option<SocketHandle> = SCHEDULER.with_current_process_mut(tf, |process| {
-        if sock_idx >= process.sockets.len() {
-            return None;
+    let socket = match socket_handle(tf, sock_idx, Some(SocketType::Tcp)) {
+        Ok(handle) => handle,
+        Err(e) => {
+            tf.regs[7] = e as u64;
+            return;
         }
-        Some(process.sockets[sock_idx])
-    });
-
-    if socket_handle.is_none() {
-        tf.regs[7] = OsError::InvalidSocket as u64;
-        return;
-    }
-
-    let socket = socket_handle.unwrap();
+    };
 
     let local_port = ETHERNET.get_ephemeral_port();
     if local_port.is_none() {
@@ -666,13 +1229,10 @@ pub fn sys_sock_connect(
         return;
     }
     let local_port = local_port.unwrap();
-    let local_endpoint = IpEndpoint {
-        addr: IpAddress::from(Ipv4Address::new(169, 254, 32, 10)),
-        port: local_port,
-    };
-    
+
+    // smoltcp picks the local address by the route to the remote endpoint
     let result = ETHERNET.with_socket(socket, |s| {
-        s.connect(local_endpoint, remote_endpoint)
+        s.connect(remote_endpoint, local_port)
     });
 
     match result {
@@ -681,17 +1241,21 @@ pub fn sys_sock_connect(
             ETHERNET.mark_port(local_port);
         }
         Err(e) => {
-            tf.regs[7] = e as u64;
+            tf.regs[7] = socket_error(e) as u64;
         }
     }
     trace!("Socket connected: {}", tf.regs[0]);
 }
 
 
-/// Listens on a local port for an inbound connection.
+/// Listens on a local port for inbound connections.
 ///
-/// This system call takes a socket descriptor as the first parameter and the
-/// local ports to listen on as the second parameter.
+/// This system call takes a socket descriptor as the first parameter, the
+/// local port to listen on as the second parameter, and the backlog, the
+/// number of connections that can be established before they are accepted,
+/// as the third parameter. The backlog is clamped to `1..=MAX_LISTEN_BACKLOG`.
+///
+/// Without `sys_sock_accept`, the socket itself becomes the first connection.
 ///
 /// It only returns the usual status value.
 ///
@@ -699,25 +1263,20 @@ pub fn sys_sock_connect(
 /// This function can return following errors:
 ///
 /// - `OsError::InvalidSocket`: Cannot find a socket that corresponds to the provided descriptor.
-/// - `OsError::IllegalSocketOperation`: `listen()` returned `smoltcp::Error::Illegal`.
+/// - `OsError::IllegalSocketOperation`: `listen()` returned `smoltcp::Error::Illegal`, or another socket listens on the port.
 /// - `OsError::BadAddress`: `listen()` returned `smoltcp::Error::Unaddressable`.
 /// - `OsError::Unknown`: All the other errors from calling `listen()`.
-pub fn sys_sock_listen(sock_idx: usize, local_port: u16, tf: &mut TrapFrame) {
-    let socket_handle: Option<SocketHandle> = SCHEDULER.with_current_process_mut(tf, |process| {
-        if sock_idx >= process.sockets.len() {
-            return None;
+pub fn sys_sock_listen(sock_idx: usize, local_port: u16, backlog: usize, tf: &mut TrapFrame) {
+    let socket = match socket_handle(tf, sock_idx, Some(SocketType::Tcp)) {
+        Ok(handle) => handle,
+        Err(e) => {
+            tf.regs[7] = e as u64;
+            return;
         }
-        Some(process.sockets[sock_idx])
-    });
-
-    if socket_handle.is_none() {
-        tf.regs[7] = OsError::InvalidSocket as u64;
-        return;
-    }
-
-    let socket = socket_handle.unwrap();
+    };
 
-    let result = ETHERNET.with_socket(socket, |s| s.listen(local_port));
+    let backlog = backlog.clamp(1, MAX_LISTEN_BACKLOG);
+    let result = ETHERNET.critical(|eth| eth.listen(socket, local_port, backlog));
 
     match result {
         Ok(_) => {
@@ -725,12 +1284,57 @@ pub fn sys_sock_listen(sock_idx: usize, local_port: u16, tf: &mut TrapFrame) {
             ETHERNET.mark_port(local_port);
         }
         Err(e) => {
-            tf.regs[7] = e as u64;
+            tf.regs[7] = socket_error(e) as u64;
         }
     }
     trace!("Socket listening: {}", tf.regs[0]);
 }
 
+/// Accepts a connection established on a listening socket.
+///
+/// This system call takes the descriptor of a socket passed to
+/// `sys_sock_listen` as its only parameter. The socket keeps listening.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the descriptor of a new socket for the connection.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::InvalidSocket`: Cannot find a socket that corresponds to the provided descriptor.
+/// - `OsError::IllegalSocketOperation`: The socket is not listening.
+/// - `OsError::WouldBlock`: The socket is nonblocking and no connection has been established.
+/// - `OsError::IoErrorTimedOut`: No connection was established within the socket's timeout.
+pub fn sys_sock_accept(sock_idx: usize, tf: &mut TrapFrame) {
+    let socket = match process_socket(tf, sock_idx, Some(SocketType::Tcp)) {
+        Ok(socket) => socket,
+        Err(e) => {
+            tf.regs[7] = e as u64;
+            return;
+        }
+    };
+    if !wait_for_socket(socket, |eth, handle| eth.can_accept(handle), tf) {
+        return;
+    }
+    let mut socket = socket.handle;
+
+    let connection = match ETHERNET.critical(|eth| eth.accept(&mut socket)) {
+        Ok(connection) => connection,
+        Err(e) => {
+            tf.regs[7] = socket_error(e) as u64;
+            return;
+        }
+    };
+    tf.regs[0] = SCHEDULER.with_current_process_mut(tf, |process| {
+        // The listening socket may have moved to a fresh socket
+        process.sockets[sock_idx].handle = socket;
+        process.sockets.push(ProcessSocket::new(connection, SocketType::Tcp));
+        process.sockets.len() as u64 - 1
+    });
+    tf.regs[7] = OsError::Ok as u64;
+    trace!("Socket accepted: {}", tf.regs[0]);
+}
+
 
 /// Sends data with a connected socket.
 ///
@@ -738,6 +1342,9 @@ pub fn sys_sock_listen(sock_idx: usize, local_port: u16, tf: &mut TrapFrame) {
 /// address of the buffer as the second parameter, and the length of the buffer
 /// as the third parameter.
 ///
+/// Unless the socket is nonblocking, the process sleeps until there is room
+/// in the send buffer or the connection closes.
+///
 /// In addition to the usual status value, this system call returns one
 /// parameter: the number of bytes sent.
 ///
@@ -747,25 +1354,24 @@ pub fn sys_sock_listen(sock_idx: usize, local_port: u16, tf: &mut TrapFrame) {
 /// - `OsError::InvalidSocket`: Cannot find a socket that corresponds to the provided descriptor.
 /// - `OsError::BadAddress`: The address and the length pair does not form a valid userspace slice.
 /// - `OsError::IllegalSocketOperation`: `send_slice()` returned `smoltcp::Error::Illegal`.
+/// - `OsError::IoErrorTimedOut`: Nothing could be sent within the socket's timeout.
 /// - `OsError::Unknown`: All the other errors from smoltcp.
 pub fn sys_sock_send(sock_idx: usize, va: usize, len: usize, tf: &mut TrapFrame) {
     
-    let socket_handle: Option<SocketHandle> = SCHEDULER.with_current_process_mut(tf, |process| {
-        if sock_idx >= process.sockets.len() {
-            return None;
+    let socket = match process_socket(tf, sock_idx, Some(SocketType::Tcp)) {
+        Ok(socket) => socket,
+        Err(e) => {
+            tf.regs[7] = e as u64;
+            return;
         }
-        Some(process.sockets[sock_idx])
-    });
-
-    if socket_handle.is_none() {
-        tf.regs[7] = OsError::InvalidSocket as u64;
+    };
+    if len > 0 && !wait_for_socket(socket, |eth, handle| can_send_or_closed(&eth.get_socket(handle)), tf) {
         return;
     }
-
-    let socket = socket_handle.unwrap();
+    let socket = socket.handle;
 
     // use to_user_slice(va, len) for the buffer
-    let buf = match unsafe { to_user_slice(va, len) } {
+    let buf = match unsafe { to_user_slice(tf, va, len) } {
         Ok(slice) => slice,
         Err(_) => {
             tf.regs[7] = OsError::BadAddress as u64;
@@ -780,7 +1386,7 @@ pub fn sys_sock_send(sock_idx: usize, va: usize, len: usize, tf: &mut TrapFrame)
             tf.regs[7] = OsError::Ok as u64;
         }
         Err(e) => {
-            tf.regs[7] = e as u64;
+            tf.regs[7] = socket_error(e) as u64;
         }
     }
     trace!("Socket sent: {}", tf.regs[0]);
@@ -793,6 +1399,9 @@ pub fn sys_sock_send(sock_idx: usize, va: usize, len: usize, tf: &mut TrapFrame)
 /// address of the buffer as the second parameter, and the length of the buffer
 /// as the third parameter.
 ///
+/// Unless the socket is nonblocking, the process sleeps until data arrives or
+/// the connection closes.
+///
 /// In addition to the usual status value, this system call returns one
 /// parameter: the number of bytes read.
 ///
@@ -802,24 +1411,23 @@ pub fn sys_sock_send(sock_idx: usize, va: usize, len: usize, tf: &mut TrapFrame)
 /// - `OsError::InvalidSocket`: Cannot find a socket that corresponds to the provided descriptor.
 /// - `OsError::BadAddress`: The address and the length pair does not form a valid userspace slice.
 /// - `OsError::IllegalSocketOperation`: `recv_slice()` returned `smoltcp::Error::Illegal`.
+/// - `OsError::IoErrorTimedOut`: Nothing arrived within the socket's timeout.
 /// - `OsError::Unknown`: All the other errors from smoltcp.
 pub fn sys_sock_recv(sock_idx: usize, va: usize, len: usize, tf: &mut TrapFrame) {
-    let socket_handle: Option<SocketHandle> = SCHEDULER.with_current_process_mut(tf, |process| {
-        if sock_idx >= process.sockets.len() {
-            return None;
+    let socket = match process_socket(tf, sock_idx, Some(SocketType::Tcp)) {
+        Ok(socket) => socket,
+        Err(e) => {
+            tf.regs[7] = e as u64;
+            return;
         }
-        Some(process.sockets[sock_idx])
-    });
-
-    if socket_handle.is_none() {
-        tf.regs[7] = OsError::InvalidSocket as u64;
+    };
+    if len > 0 && !wait_for_socket(socket, |eth, handle| can_recv_or_closed(&eth.get_socket(handle)), tf) {
         return;
     }
-
-    let socket = socket_handle.unwrap();
+    let socket = socket.handle;
 
     // use to_user_slice(va, len) for the buffer
-    let buf = match unsafe { to_user_slice_mut(va, len) } {
+    let buf = match unsafe { to_user_slice_mut(tf, va, len) } {
         Ok(slice) => slice,
         Err(_) => {
             tf.regs[7] = OsError::BadAddress as u64;
@@ -834,10 +1442,178 @@ pub fn sys_sock_recv(sock_idx: usize, va: usize, len: usize, tf: &mut TrapFrame)
             tf.regs[7] = OsError::Ok as u64;
         }
         Err(e) => {
-            tf.regs[7] = e as u64;
+            tf.regs[7] = socket_error(e) as u64;
         }
     }
     trace!("Socket received: {}", tf.regs[0]);
 }
 
+/// Binds a UDP socket to a local port on every local address.
+///
+/// This system call takes a socket descriptor as the first parameter and the
+/// local port as the second parameter. Port 0 picks a free ephemeral port.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::InvalidSocket`: Cannot find a socket that corresponds to the provided descriptor.
+/// - `OsError::IllegalSocketOperation`: The socket is not a UDP socket or is already bound.
+/// - `OsError::PortInUse`: Another socket uses the port.
+/// - `OsError::NoEntry`: Fails to allocate an ephemeral port.
+pub fn sys_sock_bind(sock_idx: usize, local_port: u16, tf: &mut TrapFrame) {
+    let result = socket_handle(tf, sock_idx, Some(SocketType::Udp)).and_then(|socket| bind_udp(socket, local_port));
+    tf.regs[7] = match result {
+        Ok(_) => OsError::Ok,
+        Err(e) => e,
+    } as u64;
+    trace!("Socket bound: {}", sock_idx);
+}
+
+/// Binds the UDP socket `socket` to `local_port`, or to an ephemeral port if
+/// it is 0, and returns the port.
+fn bind_udp(socket: SocketHandle, local_port: u16) -> OsResult<u16> {
+    ETHERNET.critical(|eth| {
+        if eth.get_udp_socket(socket).is_open() {
+            return Err(OsError::IllegalSocketOperation);
+        }
+        let port = match local_port {
+            0 => eth.get_ephemeral_port().ok_or(OsError::NoEntry)?,
+            port => port,
+        };
+        eth.mark_port(port).ok_or(OsError::PortInUse)?;
+        eth.get_udp_socket(socket).bind(port).map_err(socket_error)?;
+        Ok(port)
+    })
+}
+
+/// Sends a datagram with a UDP socket.
+///
+/// This system call takes a socket descriptor, the address and length of the
+/// buffer, and the IP of the remote endpoint in big endian and its port. An
+/// unbound socket is first bound to an ephemeral port.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the number of bytes sent.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::InvalidSocket`: Cannot find a socket that corresponds to the provided descriptor.
+/// - `OsError::IllegalSocketOperation`: The socket is not a UDP socket.
+/// - `OsError::BadAddress`: The buffer is not in userspace or the remote endpoint is unspecified.
+/// - `OsError::WouldBlock`: The socket is nonblocking and its send buffer is full.
+/// - `OsError::IoErrorTimedOut`: The send buffer stayed full for the socket's timeout.
+pub fn sys_sock_sendto(sock_idx: usize, va: usize, len: usize, remote_endpoint: IpEndpoint, tf: &mut TrapFrame) {
+    let socket = match process_socket(tf, sock_idx, Some(SocketType::Udp)) {
+        Ok(socket) => socket,
+        Err(e) => {
+            tf.regs[7] = e as u64;
+            return;
+        }
+    };
+    if !wait_for_socket(socket, |eth, handle| eth.get_udp_socket(handle).can_send(), tf) {
+        return;
+    }
+    let result = unsafe { to_user_slice(tf, va, len) }
+        .and_then(|buf| send_datagram(socket.handle, buf, remote_endpoint));
+    match result {
+        Ok(bytes) => {
+            tf.regs[0] = bytes as u64;
+            tf.regs[7] = OsError::Ok as u64;
+        }
+        Err(e) => tf.regs[7] = e as u64,
+    }
+    trace!("Socket sent datagram: {}", tf.regs[0]);
+}
+
+/// Queues `buf` as a datagram to `remote_endpoint` on the UDP socket
+/// `socket`, binding the socket to an ephemeral port first if it is unbound.
+fn send_datagram(socket: SocketHandle, buf: &[u8], remote_endpoint: IpEndpoint) -> OsResult<usize> {
+    if !ETHERNET.with_udp_socket(socket, |s| s.is_open()) {
+        bind_udp(socket, 0)?;
+    }
+    ETHERNET
+        .with_udp_socket(socket, |s| s.send_slice(buf, remote_endpoint))
+        .map_err(socket_error)?;
+    Ok(buf.len())
+}
+
+/// Receives a datagram from a UDP socket.
+///
+/// This system call takes a socket descriptor and the address and length of
+/// the buffer. The part of a datagram that does not fit in the buffer is
+/// dropped. Unless the socket is nonblocking, the process sleeps until a
+/// datagram arrives.
+///
+/// In addition to the usual status value, this system call returns three
+/// parameters: the number of bytes read, and the IP in big endian and port of
+/// the sender.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::InvalidSocket`: Cannot find a socket that corresponds to the provided descriptor.
+/// - `OsError::IllegalSocketOperation`: The socket is not a UDP socket.
+/// - `OsError::BadAddress`: The buffer is not in userspace.
+/// - `OsError::WouldBlock`: The socket is nonblocking and no datagram has been received.
+/// - `OsError::IoErrorTimedOut`: No datagram arrived within the socket's timeout.
+pub fn sys_sock_recvfrom(sock_idx: usize, va: usize, len: usize, tf: &mut TrapFrame) {
+    let socket = match process_socket(tf, sock_idx, Some(SocketType::Udp)) {
+        Ok(socket) => socket,
+        Err(e) => {
+            tf.regs[7] = e as u64;
+            return;
+        }
+    };
+    if !wait_for_socket(socket, |eth, handle| eth.get_udp_socket(handle).can_recv(), tf) {
+        return;
+    }
+    let result = unsafe { to_user_slice_mut(tf, va, len) }
+        .and_then(|buf| ETHERNET.with_udp_socket(socket.handle, |s| s.recv_slice(buf)).map_err(socket_error));
+    match result {
+        Ok((bytes, endpoint)) => {
+            let ip = match endpoint.addr {
+                IpAddress::Ipv4(addr) => u32::from_be_bytes(addr.0),
+                _ => 0,
+            };
+            tf.regs[0] = bytes as u64;
+            tf.regs[1] = ip as u64;
+            tf.regs[2] = endpoint.port as u64;
+            tf.regs[7] = OsError::Ok as u64;
+        }
+        Err(e) => tf.regs[7] = e as u64,
+    }
+    trace!("Socket received datagram: {}", tf.regs[0]);
+}
 
+/// Sets how the calls on a socket wait.
+///
+/// This system call takes a socket descriptor, the `SOCK_*` flags, and the
+/// timeout of calls that wait in milliseconds, where 0 means no timeout.
+///
+/// It only returns the usual status value.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::InvalidSocket`: Cannot find a socket that corresponds to the provided descriptor.
+/// - `OsError::InvalidArgument`: The flags contain an unknown flag.
+pub fn sys_sock_configure(sock_idx: usize, flags: u64, timeout_ms: u64, tf: &mut TrapFrame) {
+    if flags & !SOCK_NONBLOCK != 0 {
+        tf.regs[7] = OsError::InvalidArgument as u64;
+        return;
+    }
+    let result = SCHEDULER.with_current_process_mut(tf, |process| {
+        let socket = process.sockets.get_mut(sock_idx).ok_or(OsError::InvalidSocket)?;
+        socket.nonblocking = flags & SOCK_NONBLOCK != 0;
+        socket.timeout = match timeout_ms {
+            0 => None,
+            ms => Some(Duration::from_millis(ms)),
+        };
+        Ok(())
+    });
+    tf.regs[7] = match result {
+        Ok(()) => OsError::Ok,
+        Err(e) => e,
+    } as u64;
+}
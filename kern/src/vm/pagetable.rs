@@ -3,16 +3,20 @@ use core::ops::{Deref, DerefMut, SubAssign};
 use core::slice::Iter;
 
 use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
 use alloc::fmt;
+use alloc::vec::Vec;
 use core::alloc::{GlobalAlloc, Layout};
 
 use crate::allocator::{self, memory_map };
 use crate::console::{kprint, kprintln};
+use crate::mutex::Mutex;
 use crate::param::*;
 use crate::vm::{PhysicalAddr, VirtualAddr};
 use crate::ALLOCATOR;
 
 use aarch64::vmsa::*;
+use kernel_api::{OsError, OsResult};
 use shim::const_assert_size;
 
 #[repr(C)]
@@ -225,8 +229,6 @@ impl KernPageTable {
         let start: usize = 0x0;
         let (_, end): (usize, usize) = memory_map().expect("failed to load memory map");
 
-        use alloc::vec::Vec;
-
         let mut entries: Vec<&mut L3Entry> = kpt
             .l3
             .iter_mut()
@@ -266,33 +268,110 @@ impl KernPageTable {
     }
 }
 
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum PagePerm {
     RW,
     RO,
+    RX,
     RWX,
 }
 
+impl PagePerm {
+    fn is_writable(self) -> bool {
+        matches!(self, PagePerm::RW | PagePerm::RWX)
+    }
+
+    fn is_executable(self) -> bool {
+        matches!(self, PagePerm::RX | PagePerm::RWX)
+    }
+
+    fn from_flags(writable: bool, executable: bool) -> PagePerm {
+        match (writable, executable) {
+            (true, true) => PagePerm::RWX,
+            (true, false) => PagePerm::RW,
+            (false, true) => PagePerm::RX,
+            (false, false) => PagePerm::RO,
+        }
+    }
+
+    /// Returns the permission of the user page described by `entry`. A
+    /// copy-on-write page counts as writable even though it is mapped
+    /// read-only until its first write.
+    fn of(entry: &RawL3Entry) -> PagePerm {
+        PagePerm::from_flags(
+            entry.get_value(RawL3Entry::AP) == EntryPerm::USER_RW || is_cow(entry),
+            entry.get_value(RawL3Entry::UXN) == 0,
+        )
+    }
+
+    /// Returns the smallest permission that grants both `self` and `other`.
+    fn union(self, other: PagePerm) -> PagePerm {
+        PagePerm::from_flags(
+            self.is_writable() || other.is_writable(),
+            self.is_executable() || other.is_executable(),
+        )
+    }
+
+    /// Writes the access permission and execute-never bits of `self` into a
+    /// user L3 entry. User pages are never executable at EL1.
+    fn apply(self, entry: &mut RawL3Entry) {
+        let ap = if self.is_writable() { EntryPerm::USER_RW } else { EntryPerm::USER_RO };
+        entry.set_value(ap, RawL3Entry::AP);
+        entry.set_value(!self.is_executable() as u64, RawL3Entry::UXN);
+        entry.set_value(0b1_u64, RawL3Entry::PXN);
+    }
+}
+
+/// A page-aligned range `[start, end)` of user virtual addresses whose pages
+/// are allocated lazily, on first access, with permission `perm`.
+#[derive(Debug, Copy, Clone)]
+struct Region {
+    start: usize,
+    end: usize,
+    perm: PagePerm,
+}
+
+impl Region {
+    fn contains(&self, va: usize) -> bool {
+        self.start <= va && va < self.end
+    }
+
+    fn overlaps(&self, start: usize, end: usize) -> bool {
+        self.start < end && start < self.end
+    }
+}
+
 #[derive(Debug)]
-pub struct UserPageTable(Box<PageTable>);
+pub struct UserPageTable {
+    table: Box<PageTable>,
+    regions: Vec<Region>,
+}
 
 impl UserPageTable {
     /// Returns a new `UserPageTable` containing a `PageTable` created with
     /// `USER_RW` permission.
     pub fn new() -> UserPageTable {
-        UserPageTable(PageTable::new(EntryPerm::USER_RW))
+        UserPageTable {
+            table: PageTable::new(EntryPerm::USER_RW),
+            regions: Vec::new(),
+        }
     }
 
     /// Allocates a page and set an L3 entry translates given virtual address to the
-    /// physical address of the allocated page. Returns the allocated page.
+    /// physical address of the allocated page with permission `perm`. Returns
+    /// the allocated page.
+    ///
+    /// If the virtual address has already been allocated, the existing page
+    /// is returned and its permission is widened to also grant `perm`.
     ///
     /// # Panics
     /// Panics if the virtual address is lower than `USER_IMG_BASE`.
-    /// Panics if the virtual address has already been allocated.
     /// Panics if allocator fails to allocate a page.
+    /// Panics if widening would make the page both writable and executable
+    /// although neither its old permission nor `perm` is `PagePerm::RWX`.
     ///
     /// TODO. use Result<T> and make it failurable
-    /// TODO. use perm properly
-    pub fn alloc(&mut self, va: VirtualAddr, _perm: PagePerm) -> &mut [u8] {
+    pub fn alloc(&mut self, va: VirtualAddr, perm: PagePerm) -> &mut [u8] {
         if va.as_usize() < USER_IMG_BASE {
             panic!("virtual address {} is lower than `USER_IMG_BASE`", va.as_usize());
         }
@@ -301,8 +380,17 @@ impl UserPageTable {
         let adj_va = va.sub(VirtualAddr::from(USER_IMG_BASE)); // normalize
 
         if self.is_valid(adj_va) {
+            self.copy_on_write(va);
+            let (l2_idx, l3_idx) = PageTable::locate(adj_va);
+            let entry = &mut self.l3[l2_idx].entries[l3_idx].0;
+            let old = PagePerm::of(entry);
+            let widened = old.union(perm);
+            if widened == PagePerm::RWX && old != PagePerm::RWX && perm != PagePerm::RWX {
+                panic!("page {:#x} would become writable and executable", va.as_usize());
+            }
+            widened.apply(entry);
+            invalidate_page(va);
             return self.get_page(va).unwrap();
-            // panic!("virtual address has already been allocated");
         }
 
         use core::slice;
@@ -321,7 +409,7 @@ impl UserPageTable {
         entry.set_value(EntryValid::Valid, RawL3Entry::VALID);
         entry.set_value(PageType::Page, RawL3Entry::TYPE);
         entry.set_value(EntryAttr::Mem, RawL3Entry::ATTR);
-        entry.set_value(EntryPerm::USER_RW, RawL3Entry::AP); // use _perm ?
+        perm.apply(&mut entry);
         entry.set_value(EntrySh::ISh, RawL3Entry::SH);
         entry.set_value(0b1_u64, RawL3Entry::AF);
         entry.set_masked(page as u64, RawL3Entry::ADDR);
@@ -330,6 +418,242 @@ impl UserPageTable {
 
         page_slice
     }
+
+    /// Reserves `len` bytes of user virtual memory starting at `start` with
+    /// permission `perm`. No page is allocated until it is first accessed;
+    /// see `populate()`. A zero-length reservation is allowed and can later
+    /// be grown with `resize()`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `OsError::InvalidArgument` if `start` is not page aligned.
+    /// Returns `OsError::NoVmSpace` if the range leaves the user address
+    /// space or overlaps an existing reservation or mapped page.
+    pub fn reserve(&mut self, start: VirtualAddr, len: usize, perm: PagePerm) -> OsResult<()> {
+        let start = start.as_usize();
+        if start % PAGE_SIZE != 0 {
+            return Err(OsError::InvalidArgument);
+        }
+        let end = UserPageTable::range_end(start, len)?;
+        self.check_free(start, end)?;
+
+        self.regions.push(Region { start, end, perm });
+        Ok(())
+    }
+
+    /// Changes the length of the reservation starting at `start` to `len`
+    /// bytes. Pages that fall outside of a shrunk reservation are released.
+    ///
+    /// # Errors
+    ///
+    /// Returns `OsError::InvalidArgument` if no reservation starts at `start`.
+    /// Returns `OsError::NoVmSpace` if the grown range leaves the user
+    /// address space or overlaps another reservation or mapped page.
+    pub fn resize(&mut self, start: VirtualAddr, len: usize) -> OsResult<()> {
+        let start = start.as_usize();
+        let idx = self
+            .regions
+            .iter()
+            .position(|r| r.start == start)
+            .ok_or(OsError::InvalidArgument)?;
+
+        let old_end = self.regions[idx].end;
+        let new_end = UserPageTable::range_end(start, len)?;
+        if new_end > old_end {
+            self.check_free(old_end, new_end)?;
+        }
+        for va in (new_end..old_end).step_by(PAGE_SIZE) {
+            self.unmap(VirtualAddr::from(va));
+        }
+
+        self.regions[idx].end = new_end;
+        Ok(())
+    }
+
+    /// Returns the page-aligned end of `[start, start + len)`.
+    fn range_end(start: usize, len: usize) -> OsResult<usize> {
+        start
+            .checked_add(len)
+            .and_then(|end| end.checked_add(PAGE_SIZE - 1))
+            .map(|end| end & PAGE_MASK)
+            .ok_or(OsError::NoVmSpace)
+    }
+
+    /// Returns `Ok` if `[start, end)` lies in the user address space and none
+    /// of its pages is reserved or mapped.
+    fn check_free(&self, start: usize, end: usize) -> OsResult<()> {
+        let taken = start < USER_IMG_BASE
+            || self.regions.iter().any(|r| r.overlaps(start, end))
+            || (start..end).step_by(PAGE_SIZE).any(|va| self.is_valid(VirtualAddr::from(va)));
+        if taken {
+            return Err(OsError::NoVmSpace);
+        }
+        Ok(())
+    }
+
+    /// Makes sure every page of `[va, va + len)` is mapped, allocating zeroed
+    /// pages for the parts that are reserved but not yet populated. If
+    /// `write` is set, copy-on-write pages of the range are also resolved.
+    ///
+    /// Returns `false` if some page in the range is neither mapped nor
+    /// reserved, or is not writable although `write` is set, i.e. an access
+    /// to it is a genuine fault.
+    pub fn populate(&mut self, va: VirtualAddr, len: usize, write: bool) -> bool {
+        let start = va.as_usize() & PAGE_MASK;
+        let end = match va.as_usize().checked_add(len.max(1)) {
+            Some(end) => end,
+            None => return false,
+        };
+        if start < USER_IMG_BASE {
+            return false;
+        }
+
+        for page_va in (start..end).step_by(PAGE_SIZE) {
+            let page_va = VirtualAddr::from(page_va);
+            if self.is_valid(page_va) {
+                if write {
+                    let (l2_idx, l3_idx) = PageTable::locate(page_va);
+                    if !PagePerm::of(&self.l3[l2_idx].entries[l3_idx].0).is_writable() {
+                        return false;
+                    }
+                    self.copy_on_write(page_va);
+                }
+                continue;
+            }
+            if write && !self.regions.iter().any(|r| r.contains(page_va.as_usize()) && r.perm.is_writable()) {
+                return false;
+            }
+            match self.regions.iter().find(|r| r.contains(page_va.as_usize())) {
+                Some(region) => {
+                    let perm = region.perm;
+                    self.alloc(page_va, perm).iter_mut().for_each(|x| *x = 0);
+                }
+                None => return false,
+            }
+        }
+        true
+    }
+
+    /// Releases the page mapped at `va`, if any.
+    fn unmap(&mut self, va: VirtualAddr) {
+        let (l2_idx, l3_idx) = PageTable::locate(va);
+        let entry = &mut self.l3[l2_idx].entries[l3_idx];
+        if let Some(addr) = entry.get_page_addr() {
+            release_page(addr.as_usize());
+            *entry = L3Entry::new();
+            invalidate_page(va);
+        }
+    }
+
+    /// Returns a copy of this address space for a forked child. Every mapped
+    /// page is shared with the child instead of being copied; writable pages
+    /// become read-only copy-on-write pages in both page tables, and the
+    /// first write to one of them in either process is resolved by
+    /// `copy_on_write()`.
+    pub fn fork(&mut self) -> UserPageTable {
+        let mut child = UserPageTable::new();
+        child.regions = self.regions.clone();
+
+        for (l3, child_l3) in self.l3.iter_mut().zip(child.l3.iter_mut()) {
+            for (entry, child_entry) in l3.entries.iter_mut().zip(child_l3.entries.iter_mut()) {
+                if let Some(addr) = entry.get_page_addr() {
+                    if PagePerm::of(&entry.0).is_writable() {
+                        entry.0.set_value(EntryPerm::USER_RO, RawL3Entry::AP);
+                        entry.0.set_value(0b1_u64, RawL3Entry::COW);
+                    }
+                    share_page(addr.as_usize());
+                    *child_entry = *entry;
+                }
+            }
+        }
+
+        child
+    }
+
+    /// Resolves a write to the copy-on-write page at `va`. The page is copied
+    /// if other page tables still share it; otherwise it is simply made
+    /// writable again.
+    ///
+    /// Returns `false` if `va` is not mapped to a copy-on-write page.
+    pub fn copy_on_write(&mut self, va: VirtualAddr) -> bool {
+        let (l2_idx, l3_idx) = PageTable::locate(va);
+        let entry = &mut self.l3[l2_idx].entries[l3_idx];
+        let addr = match entry.get_page_addr() {
+            Some(addr) if is_cow(&entry.0) => addr.as_usize(),
+            _ => return false,
+        };
+
+        if is_shared(addr) {
+            let page = unsafe { ALLOCATOR.alloc(Page::layout()) };
+            if page == core::ptr::null_mut() {
+                panic!("allocator failed to allocate a page")
+            };
+            unsafe { core::ptr::copy_nonoverlapping(addr as *const u8, page, Page::SIZE) };
+            entry.0.set_masked(page as u64, RawL3Entry::ADDR);
+            release_page(addr);
+        }
+
+        entry.0.set_value(0b0_u64, RawL3Entry::COW);
+        entry.0.set_value(EntryPerm::USER_RW, RawL3Entry::AP);
+        invalidate_page(va);
+        true
+    }
+}
+
+/// Drops every core's cached translation of the user page at `va`. Called
+/// after a valid entry changes, since the kernel may access the page through
+/// the user mapping before the TLB is flushed on the way back to user space.
+fn invalidate_page(va: VirtualAddr) {
+    // TLBI takes VA[55:12] in its low 44 bits
+    let page = (va.as_usize() as u64 >> 12) & ((1 << 44) - 1);
+    unsafe {
+        core::arch::asm!(
+            "dsb ishst",
+            "tlbi vae1is, {page}",
+            "dsb ish",
+            "isb",
+            page = in(reg) page,
+            options(nostack),
+        );
+    }
+}
+
+/// Number of user page tables mapping each physical page shared by `fork`.
+/// Pages mapped by a single page table have no entry.
+static SHARED_PAGES: Mutex<BTreeMap<usize, usize>> = Mutex::new(BTreeMap::new());
+
+/// Returns `true` if `entry` maps a copy-on-write page.
+fn is_cow(entry: &RawL3Entry) -> bool {
+    entry.get_value(RawL3Entry::COW) == 0b1
+}
+
+/// Returns `true` if more than one page table maps the page at `addr`.
+fn is_shared(addr: usize) -> bool {
+    SHARED_PAGES.lock().contains_key(&addr)
+}
+
+/// Records one more page table mapping the page at `addr`.
+fn share_page(addr: usize) {
+    *SHARED_PAGES.lock().entry(addr).or_insert(1) += 1;
+}
+
+/// Drops a page table's reference to the page at `addr` and frees the page
+/// once no page table maps it anymore.
+fn release_page(addr: usize) {
+    {
+        let mut shared = SHARED_PAGES.lock();
+        if let Some(count) = shared.get_mut(&addr) {
+            *count -= 1;
+            if *count == 1 {
+                shared.remove(&addr);
+            }
+            return;
+        }
+    }
+
+    unsafe {
+        ALLOCATOR.dealloc(addr as *mut u8, Page::layout());
+    }
 }
 
 impl Deref for KernPageTable {
@@ -344,7 +668,7 @@ impl Deref for UserPageTable {
     type Target = PageTable;
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.table
     }
 }
 
@@ -356,18 +680,15 @@ impl DerefMut for KernPageTable {
 
 impl DerefMut for UserPageTable {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
+        &mut self.table
     }
 }
 
 impl Drop for UserPageTable {
     fn drop(&mut self) {
         for entry in self.into_iter() {
-            if entry.is_valid() {
-                let ptr = entry.0.get_masked(RawL3Entry::ADDR) as *mut u8;
-                unsafe {
-                    ALLOCATOR.dealloc(ptr, Page::layout());
-                }
+            if let Some(addr) = entry.get_page_addr() {
+                release_page(addr.as_usize());
             }
         }
     }
@@ -397,46 +718,3 @@ impl fmt::Debug for PageTable {
             .finish()
     }
 }
-
-
-
-impl UserPageTable {
-    fn get_page_slice(&self, va: VirtualAddr) -> &[u8] {
-        let (l2_idx, l3_idx) = PageTable::locate(va);
-        let entry = &self.l3[l2_idx].entries[l3_idx];
-        if entry.is_valid() {
-            let addr = entry.0.get_masked(RawL3Entry::ADDR) as *const u8;
-            unsafe { core::slice::from_raw_parts(addr, Page::SIZE) }
-        } else {
-            panic!("Invalid page entry");
-        }
-    }
-}
-
-impl Clone for UserPageTable {
-    fn clone(&self) -> Self {
-        let mut new_page_table = UserPageTable::new();
-
-        for (l2_idx, l3_table) in self.l3.iter().enumerate() {
-            for (l3_idx, entry) in l3_table.entries.iter().enumerate() {
-                if entry.is_valid() {
-                    let old_phys_addr = entry.0.get_masked(RawL3Entry::ADDR);
-                    let virt_addr = VirtualAddr::from(USER_IMG_BASE+ (l2_idx << 29) | (l3_idx << 16));
-
-                    trace!("Cloning page at {:x} to {:x}", old_phys_addr, virt_addr.as_usize());
-
-                    // Allocate a new page
-                    let new_page_slice = new_page_table.alloc(virt_addr, PagePerm::RWX);
-
-                    // Get the old page slice
-                    let old_page_slice = self.get_page_slice(virt_addr);
-
-                    // Copy the data
-                    new_page_slice.copy_from_slice(old_page_slice);
-                }
-            }
-        }
-
-        new_page_table
-    }
-}
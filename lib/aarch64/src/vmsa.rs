@@ -83,6 +83,9 @@ defbit!(RawL2Entry,
 defbit!(
     RawL3Entry,
     [
+        COW[55 - 55], // software-defined: shared copy-on-write page
+        UXN[54 - 54],
+        PXN[53 - 53],
         ADDR[47 - 16],
         AF[10 - 10],
         SH[09 - 08],
@@ -224,6 +227,23 @@ impl fmt::Debug for RawL3Entry {
             }
         )?;
 
+        write!(
+            f,
+            "|{}{}{}",
+            match self.get_value(RawL3Entry::PXN) {
+                0 => "K",
+                _ => "-",
+            },
+            match self.get_value(RawL3Entry::UXN) {
+                0 => "X",
+                _ => "-",
+            },
+            match self.get_value(RawL3Entry::COW) {
+                0 => "-",
+                _ => "C",
+            }
+        )?;
+
         // NS    [05-05],
 
         write!(
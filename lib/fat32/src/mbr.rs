@@ -21,6 +21,16 @@ impl Default for CHS {
 
 const_assert_size!(CHS, 3);
 
+impl CHS {
+    /// The CHS address of partitions that can only be addressed with LBA.
+    fn lba_only() -> CHS {
+        CHS {
+            head: 0xFE,
+            sector_and_cylinder: [0xFF, 0xFF],
+        }
+    }
+}
+
 #[repr(C, packed)]
 #[derive(Debug, Clone, Copy)]
 pub struct PartitionEntry {
@@ -47,6 +57,21 @@ impl Default for PartitionEntry {
 
 const_assert_size!(PartitionEntry, 16);
 
+impl PartitionEntry {
+    /// Returns the entry of a non-bootable partition of type `partition_type`
+    /// spanning `total_sectors` sectors from sector `relative_sector`.
+    pub fn new(partition_type: u8, relative_sector: u32, total_sectors: u32) -> PartitionEntry {
+        PartitionEntry {
+            boot_indicator: 0,
+            start_chs: CHS::lba_only(),
+            partition_type,
+            end_chs: CHS::lba_only(),
+            relative_sector,
+            total_sectors,
+        }
+    }
+}
+
 /// The master boot record (MBR).
 #[repr(C, packed)]
 #[derive(Debug)]
@@ -85,6 +110,16 @@ const VALID_INDICATORS: [u8; 2] = [0x80, 0x00];
 
 use core::slice;
 impl MasterBootRecord {
+    /// Returns an MBR with partition table `partition_table` and no
+    /// bootstrap code.
+    pub fn new(partition_table: [PartitionEntry; 4]) -> MasterBootRecord {
+        MasterBootRecord {
+            partition_table,
+            signature: VALID_SIGNATURE,
+            ..MasterBootRecord::default()
+        }
+    }
+
     /// Reads and returns the master boot record (MBR) from `device`.
     ///
     /// # Errors
@@ -475,3 +475,1222 @@ fn shuffle_test() {
     let hash = hash_files_recursive_from(vfat, "/");
     assert_hash_eq!("mock 1 file hashes", hash, hash_for!("files-1"));
 }
+
+/// An in-memory disk whose contents survive the `VFat` mounted on it, so a
+/// test can remount the image and check what reached the "disk".
+#[derive(Clone)]
+struct MemDisk(Arc<Mutex<Vec<u8>>>);
+
+impl MemDisk {
+    fn sectors(&self, start: u64, count: u64) -> Vec<u8> {
+        let data = self.0.lock().unwrap();
+        data[(start * 512) as usize..((start + count) * 512) as usize].to_vec()
+    }
+}
+
+impl BlockDevice for MemDisk {
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let data = self.0.lock().unwrap();
+        let start = (n * 512) as usize;
+        let len = buf.len().min(512);
+        buf[..len].copy_from_slice(&data[start..start + len]);
+        Ok(len)
+    }
+
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        let mut data = self.0.lock().unwrap();
+        let start = (n * 512) as usize;
+        let len = buf.len().min(512);
+        data[start..start + len].copy_from_slice(&buf[..len]);
+        Ok(len)
+    }
+}
+
+/// A `MemDisk` that records every request made to it.
+#[derive(Clone)]
+struct LoggedDisk {
+    disk: MemDisk,
+    writes: Arc<Mutex<Vec<u64>>>,        // sector of every write
+    reads: Arc<Mutex<Vec<(u64, u64)>>>, // first sector and sector count of every read
+}
+
+impl LoggedDisk {
+    fn new(disk: MemDisk) -> LoggedDisk {
+        LoggedDisk { disk, writes: Arc::default(), reads: Arc::default() }
+    }
+
+    fn writes(&self) -> Vec<u64> {
+        core::mem::take(&mut *self.writes.lock().unwrap())
+    }
+
+    fn reads(&self) -> Vec<(u64, u64)> {
+        core::mem::take(&mut *self.reads.lock().unwrap())
+    }
+}
+
+impl BlockDevice for LoggedDisk {
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        self.reads.lock().unwrap().push((n, 1));
+        self.disk.read_sector(n, buf)
+    }
+
+    fn read_sectors(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        self.reads.lock().unwrap().push((n, buf.len() as u64 / 512));
+        let data = self.disk.0.lock().unwrap();
+        buf.copy_from_slice(&data[(n * 512) as usize..(n * 512) as usize + buf.len()]);
+        Ok(buf.len())
+    }
+
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        self.writes.lock().unwrap().push(n);
+        self.disk.write_sector(n, buf)
+    }
+}
+
+const IMG_PART_START: u64 = 1;
+const IMG_PART_SECTORS: u64 = 4096;
+const IMG_RESERVED: u64 = 32;
+const IMG_SECTORS_PER_FAT: u64 = 32;
+const IMG_FSINFO: u64 = 1;
+const IMG_DATA_CLUSTERS: u32 = (IMG_PART_SECTORS - IMG_RESERVED - 2 * IMG_SECTORS_PER_FAT) as u32;
+
+/// Formats a disk holding an empty FAT32 file system with 512-byte sectors
+/// and clusters, two FATs and an FSInfo sector.
+fn fat32_image() -> MemDisk {
+    let sectors = IMG_PART_START + IMG_PART_SECTORS;
+    let disk = MemDisk(Arc::new(Mutex::new(vec![0u8; (sectors * 512) as usize])));
+    vfat::format(disk.clone(), sectors).expect("format");
+    disk
+}
+
+fn fat_copies(disk: &MemDisk) -> (Vec<u8>, Vec<u8>) {
+    let first = IMG_PART_START + IMG_RESERVED;
+    (
+        disk.sectors(first, IMG_SECTORS_PER_FAT),
+        disk.sectors(first + IMG_SECTORS_PER_FAT, IMG_SECTORS_PER_FAT),
+    )
+}
+
+fn fsinfo_free_count(disk: &MemDisk) -> u32 {
+    let sector = disk.sectors(IMG_PART_START + IMG_FSINFO, 1);
+    u32::from_le_bytes([sector[488], sector[489], sector[490], sector[491]])
+}
+
+fn mount(disk: &MemDisk) -> StdVFatHandle {
+    VFat::<StdVFatHandle>::from(disk.clone()).expect("failed to initialize VFAT from image")
+}
+
+fn sync(vfat: &StdVFatHandle) {
+    vfat.lock(|vfat| vfat.sync()).expect("sync");
+}
+
+fn read_file(vfat: &StdVFatHandle, path: &str) -> Vec<u8> {
+    let mut file = vfat.open_file(path).expect("file exists");
+    let mut data = Vec::new();
+    file.read_to_end(&mut data).expect("read file");
+    assert_eq!(data.len() as u64, file.size());
+    data
+}
+
+fn root_names(vfat: &StdVFatHandle) -> Vec<String> {
+    let mut names: Vec<String> = vfat
+        .open_dir("/")
+        .expect("root directory")
+        .entries()
+        .expect("entries interator")
+        .map(|entry| entry.name().to_string())
+        .collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn test_create_write_and_remount() {
+    let disk = fat32_image();
+    let vfat = mount(&disk);
+
+    let root = vfat.open_dir("/").expect("root directory");
+    let mut file = root.create_file("HELLO.TXT").expect("create file");
+    file.write_all(b"hello, world\n").expect("write");
+    file.sync().expect("sync");
+    assert_eq!(file.size(), 13);
+
+    let vfat = mount(&disk);
+    assert_eq!(root_names(&vfat), vec!["HELLO.TXT"]);
+    assert_eq!(read_file(&vfat, "/hello.txt"), b"hello, world\n");
+}
+
+#[test]
+fn test_create_long_names() {
+    let disk = fat32_image();
+    let vfat = mount(&disk);
+
+    let names = [
+        "a long file name.text",
+        "lower.txt",
+        "Ünïcødé ñame",
+        "a name that needs more than one LFN entry to be stored.bin",
+    ];
+    let root = vfat.open_dir("/").expect("root directory");
+    for name in names.iter() {
+        let mut file = root.create_file(name).expect("create file");
+        file.write_all(name.as_bytes()).expect("write");
+        file.sync().expect("sync");
+    }
+
+    let vfat = mount(&disk);
+    let mut expected: Vec<String> = names.iter().map(|s| s.to_string()).collect();
+    expected.sort();
+    assert_eq!(root_names(&vfat), expected);
+    for name in names.iter() {
+        assert_eq!(read_file(&vfat, &format!("/{}", name)), name.as_bytes());
+    }
+}
+
+#[test]
+fn test_create_short_name_has_no_lfn() {
+    let disk = fat32_image();
+    let vfat = mount(&disk);
+
+    let root = vfat.open_dir("/").expect("root directory");
+    let file = root.create_file("README.MD").expect("create file");
+    let slots = file.slots.expect("file has slots");
+    assert_eq!(slots.first, slots.regular);
+
+    let file = root.create_file("readme.md.bak").expect("create file");
+    let slots = file.slots.expect("file has slots");
+    assert_eq!(slots.regular - slots.first, 1);
+}
+
+#[test]
+fn test_create_errors() {
+    let disk = fat32_image();
+    let vfat = mount(&disk);
+
+    let root = vfat.open_dir("/").expect("root directory");
+    root.create_file("file").expect("create file");
+
+    let e = root.create_file("FILE").unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::AlreadyExists);
+
+    for name in ["", ".", "..", "a/b", "what?", "trailing.", &"x".repeat(256)].iter() {
+        let e = root.create_file(name).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput, "name {:?}", name);
+    }
+}
+
+#[test]
+fn test_extend_mirrors_fat_and_fsinfo() {
+    let disk = fat32_image();
+    let vfat = mount(&disk);
+    assert_eq!(vfat.lock(|v| v.free_clusters()), Some(IMG_DATA_CLUSTERS - 1));
+
+    let data: Vec<u8> = (0..20 * 512 + 100).map(|i| (i % 251) as u8).collect();
+    let root = vfat.open_dir("/").expect("root directory");
+    let mut file = root.create_file("big.bin").expect("create file");
+    file.write_all(&data[..1000]).expect("write");
+    file.sync().expect("sync");
+    file.write_all(&data[1000..]).expect("append");
+    file.sync().expect("sync");
+
+    assert_eq!(fsinfo_free_count(&disk), IMG_DATA_CLUSTERS - 1 - 21);
+    let (fat1, fat2) = fat_copies(&disk);
+    assert!(fat1 == fat2, "FAT copies differ");
+
+    let vfat = mount(&disk);
+    assert_eq!(read_file(&vfat, "/big.bin"), data);
+
+    // Appending after seeking to the end of a reopened file
+    let mut file = vfat.open_file("/big.bin").expect("file exists");
+    file.seek(io::SeekFrom::End(0)).expect("seek to end");
+    file.write_all(b"tail").expect("append");
+    file.sync().expect("sync");
+
+    let vfat = mount(&disk);
+    let read = read_file(&vfat, "/big.bin");
+    assert_eq!(&read[..data.len()], &data[..]);
+    assert_eq!(&read[data.len()..], b"tail");
+}
+
+#[test]
+fn test_set_len_beyond_free_space() {
+    let disk = fat32_image();
+    let vfat = mount(&disk);
+    write_new_file(&vfat, "/", "file", b"data");
+
+    let mut file = vfat.open_file("/file").expect("open file");
+    let free = vfat.lock(|v| v.free_clusters()).expect("free count") as u64;
+    let e = file.set_len(u32::MAX as u64).unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::Other);
+    let e = file.set_len(512 * (free + 2)).unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::Other);
+    assert_eq!(file.size(), 4);
+
+    // Every free cluster can still be used
+    file.set_len(512 * (free + 1)).expect("set_len");
+    file.sync().expect("sync");
+    assert_eq!(fsinfo_free_count(&disk), 0);
+}
+
+#[test]
+fn test_truncate_frees_clusters() {
+    let disk = fat32_image();
+    let vfat = mount(&disk);
+
+    let root = vfat.open_dir("/").expect("root directory");
+    let mut file = root.create_file("shrink.bin").expect("create file");
+    file.write_all(&[0xAB; 8 * 512]).expect("write");
+    file.sync().expect("sync");
+    assert_eq!(fsinfo_free_count(&disk), IMG_DATA_CLUSTERS - 1 - 8);
+
+    file.set_len(700).expect("truncate");
+    file.sync().expect("sync");
+    assert_eq!(fsinfo_free_count(&disk), IMG_DATA_CLUSTERS - 1 - 2);
+
+    let vfat = mount(&disk);
+    assert_eq!(read_file(&vfat, "/shrink.bin"), vec![0xAB; 700]);
+
+    let mut file = vfat.open_file("/shrink.bin").expect("file exists");
+    file.set_len(0).expect("truncate");
+    file.sync().expect("sync");
+    assert_eq!(fsinfo_free_count(&disk), IMG_DATA_CLUSTERS - 1);
+
+    let vfat = mount(&disk);
+    assert_eq!(read_file(&vfat, "/shrink.bin"), Vec::<u8>::new());
+    let (fat1, fat2) = fat_copies(&disk);
+    assert!(fat1 == fat2, "FAT copies differ");
+}
+
+#[test]
+fn test_remove_frees_chain() {
+    let disk = fat32_image();
+    let vfat = mount(&disk);
+
+    let root = vfat.open_dir("/").expect("root directory");
+    for name in ["keep.txt", "a file to remove.txt"].iter() {
+        let mut file = root.create_file(name).expect("create file");
+        file.write_all(&[0x55; 3 * 512]).expect("write");
+        file.sync().expect("sync");
+    }
+    assert_eq!(fsinfo_free_count(&disk), IMG_DATA_CLUSTERS - 1 - 6);
+
+    root.remove("A FILE TO REMOVE.TXT").expect("remove file");
+    sync(&vfat);
+    assert_eq!(fsinfo_free_count(&disk), IMG_DATA_CLUSTERS - 1 - 3);
+
+    let vfat = mount(&disk);
+    assert_eq!(root_names(&vfat), vec!["keep.txt"]);
+    let e = vfat.open("/a file to remove.txt").unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::NotFound);
+
+    let root = vfat.open_dir("/").expect("root directory");
+    let e = root.remove("a file to remove.txt").unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::NotFound);
+
+    // The freed slots and clusters are reused
+    let mut file = root.create_file("new.txt").expect("create file");
+    file.write_all(b"new").expect("write");
+    file.sync().expect("sync");
+    assert_eq!(fsinfo_free_count(&disk), IMG_DATA_CLUSTERS - 1 - 4);
+    let vfat = mount(&disk);
+    assert_eq!(root_names(&vfat), vec!["keep.txt", "new.txt"]);
+}
+
+#[test]
+fn test_store_after_remove() {
+    let disk = fat32_image();
+    let vfat = mount(&disk);
+    let root = vfat.open_dir("/").expect("root directory");
+    vfat.create_dir("/dir").expect("create dir");
+    write_new_file(&vfat, "/", "gone.txt", &[0x55; 2 * 512]);
+    write_new_file(&vfat, "/", "empty.txt", b"");
+
+    // `stale` is in use when its file is removed, `stale_empty` is not
+    let mut stale = vfat.open_file("/gone.txt").expect("open file");
+    stale.read(&mut [0; 1]).expect("read");
+    let mut stale_empty = vfat.open_file("/empty.txt").expect("open file");
+    root.remove("gone.txt").expect("remove file");
+    root.remove("empty.txt").expect("remove file");
+    // The new file takes the clusters that `gone.txt` had
+    write_new_file(&vfat, "/dir", "other.txt", &[0xAA; 2 * 512]);
+    sync(&vfat);
+    let free = fsinfo_free_count(&disk);
+
+    assert_eq!(stale.write_all(&[0x11; 3 * 512]).unwrap_err().kind(), io::ErrorKind::NotFound);
+    assert_eq!(stale.read(&mut [0; 512]).unwrap_err().kind(), io::ErrorKind::NotFound);
+    assert_eq!(stale.store().unwrap_err().kind(), io::ErrorKind::NotFound);
+    assert_eq!(stale_empty.write_all(b"data").unwrap_err().kind(), io::ErrorKind::NotFound);
+
+    sync(&vfat);
+    assert_eq!(fsinfo_free_count(&disk), free);
+    let vfat = mount(&disk);
+    assert_eq!(root_names(&vfat), vec!["dir"]);
+    assert_eq!(read_file(&vfat, "/dir/other.txt"), vec![0xAA; 2 * 512]);
+}
+
+#[test]
+fn test_two_handles_share_the_file() {
+    let disk = fat32_image();
+    let vfat = mount(&disk);
+    write_new_file(&vfat, "/", "shared.txt", b"short");
+
+    let mut a = vfat.open_file("/shared.txt").expect("open file");
+    let mut b = vfat.open_file("/shared.txt").expect("open file");
+    // `c` is not used until the other handles are gone
+    let mut c = vfat.open_file("/shared.txt").expect("open file");
+    a.write_all(b"S").expect("write");
+    b.seek(io::SeekFrom::End(0)).expect("seek");
+    b.write_all(&[0x22; 3 * 512]).expect("write");
+    b.store().expect("store");
+    // `a` sees what `b` wrote and does not shrink the file back
+    a.store().expect("store");
+    assert_eq!(a.size(), 5 + 3 * 512);
+    let mut data = Vec::new();
+    a.seek(io::SeekFrom::Start(5)).expect("seek");
+    a.read_to_end(&mut data).expect("read");
+    assert!(data == [0x22; 3 * 512], "file data differs");
+    drop((a, b));
+
+    c.write_all(b"sh").expect("write");
+    c.sync().expect("sync");
+    assert_eq!(check(&vfat), vec![]);
+    let vfat = mount(&disk);
+    let mut expected = b"short".to_vec();
+    expected.extend_from_slice(&[0x22; 3 * 512]);
+    assert_eq!(read_file(&vfat, "/shared.txt"), expected);
+}
+
+#[test]
+fn test_create_grows_directory() {
+    let disk = fat32_image();
+    let vfat = mount(&disk);
+
+    // 40 files with one LFN entry each need 80 slots: 5 clusters of 16 slots
+    let root = vfat.open_dir("/").expect("root directory");
+    let mut expected = Vec::new();
+    for i in 0..40 {
+        let name = format!("file {:02}.dat", i);
+        let mut file = root.create_file(&name).expect("create file");
+        file.write_all(name.as_bytes()).expect("write");
+        file.sync().expect("sync");
+        expected.push(name);
+    }
+
+    let vfat = mount(&disk);
+    assert_eq!(root_names(&vfat), expected);
+    assert_eq!(read_file(&vfat, "/file 39.dat"), b"file 39.dat");
+    assert_eq!(fsinfo_free_count(&disk), IMG_DATA_CLUSTERS - 1 - 4 - 40);
+}
+
+fn dir_names(vfat: &StdVFatHandle, path: &str) -> Vec<String> {
+    let mut names: Vec<String> = vfat
+        .open_dir(path)
+        .expect("directory exists")
+        .entries()
+        .expect("entries interator")
+        .map(|entry| entry.name().to_string())
+        .collect();
+    names.sort();
+    names
+}
+
+fn write_new_file(vfat: &StdVFatHandle, dir: &str, name: &str, data: &[u8]) {
+    let mut file = vfat
+        .open_dir(dir)
+        .expect("directory exists")
+        .create_file(name)
+        .expect("create file");
+    file.write_all(data).expect("write");
+    file.sync().expect("sync");
+}
+
+#[test]
+fn test_create_dir() {
+    let disk = fat32_image();
+    let vfat = mount(&disk);
+
+    vfat.create_dir("/sub").expect("create dir");
+    vfat.create_dir("/sub/a nested directory").expect("create nested dir");
+    write_new_file(&vfat, "/sub/a nested directory", "file.txt", b"nested");
+    assert_eq!(fsinfo_free_count(&disk), IMG_DATA_CLUSTERS - 1 - 3);
+
+    let vfat = mount(&disk);
+    assert_eq!(root_names(&vfat), vec!["sub"]);
+    assert_eq!(dir_names(&vfat, "/sub"), vec![".", "..", "a nested directory"]);
+    assert_eq!(dir_names(&vfat, "/sub/a nested directory"), vec![".", "..", "file.txt"]);
+
+    // `.` and `..` lead back to the right directories
+    assert_eq!(dir_names(&vfat, "/sub/."), dir_names(&vfat, "/sub"));
+    assert_eq!(dir_names(&vfat, "/sub/.."), vec!["sub"]);
+    assert_eq!(dir_names(&vfat, "/sub/a nested directory/.."), dir_names(&vfat, "/sub"));
+    assert_eq!(read_file(&vfat, "/sub/a nested directory/../a nested directory/file.txt"), b"nested");
+
+    let e = vfat.create_dir("/SUB").unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::AlreadyExists);
+    let e = vfat.create_dir("/missing/sub").unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::NotFound);
+    let e = vfat.create_dir("/").unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn test_remove_dir() {
+    let disk = fat32_image();
+    let vfat = mount(&disk);
+
+    vfat.create_dir("/full").expect("create dir");
+    vfat.create_dir("/empty directory").expect("create dir");
+    write_new_file(&vfat, "/full", "file", b"data");
+    write_new_file(&vfat, "/", "file", b"data");
+
+    let e = vfat.remove_dir("/full").unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::Other);
+    let e = vfat.remove_dir("/file").unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::Other);
+    let e = vfat.remove_dir("/full/..").unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+    let e = vfat.remove_dir("/nope").unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::NotFound);
+
+    vfat.remove_dir("/empty directory").expect("remove empty dir");
+    vfat.open_dir("/full").unwrap().remove("file").expect("remove file");
+    vfat.remove_dir("/full").expect("remove emptied dir");
+    sync(&vfat);
+    assert_eq!(fsinfo_free_count(&disk), IMG_DATA_CLUSTERS - 1 - 1);
+
+    let vfat = mount(&disk);
+    assert_eq!(root_names(&vfat), vec!["file"]);
+}
+
+#[test]
+fn test_rename_in_place() {
+    let disk = fat32_image();
+    let vfat = mount(&disk);
+
+    write_new_file(&vfat, "/", "short.txt", b"contents");
+    vfat.rename("/short.txt", "/a much longer name.txt").expect("rename");
+    vfat.rename("/a much longer name.txt", "/A Much Longer Name.TXT").expect("rename case");
+    vfat.rename("/A Much Longer Name.TXT", "/FINAL.TXT").expect("rename to 8.3");
+    sync(&vfat);
+
+    let vfat = mount(&disk);
+    assert_eq!(root_names(&vfat), vec!["FINAL.TXT"]);
+    assert_eq!(read_file(&vfat, "/final.txt"), b"contents");
+    // The root directory is the first (single-sector) data cluster
+    let root = disk.sectors(IMG_PART_START + IMG_RESERVED + 2 * IMG_SECTORS_PER_FAT, 1);
+    let used = root.chunks(32).filter(|slot| slot[0] != 0x00 && slot[0] != 0xE5).count();
+    assert_eq!(used, 1, "old LFN and regular slots were not all freed");
+}
+
+#[test]
+fn test_rename_across_directories() {
+    let disk = fat32_image();
+    let vfat = mount(&disk);
+
+    vfat.create_dir("/from").expect("create dir");
+    vfat.create_dir("/to").expect("create dir");
+    vfat.create_dir("/from/moved directory").expect("create dir");
+    write_new_file(&vfat, "/from/moved directory", "inner", b"inner data");
+    write_new_file(&vfat, "/from", "moved file.txt", b"file data");
+    let free = fsinfo_free_count(&disk);
+
+    vfat.rename("/from/moved file.txt", "/to/file.txt").expect("move file");
+    vfat.rename("/from/moved directory", "/to/dir").expect("move dir");
+    sync(&vfat);
+    assert_eq!(fsinfo_free_count(&disk), free);
+
+    let vfat = mount(&disk);
+    assert_eq!(dir_names(&vfat, "/from"), vec![".", ".."]);
+    assert_eq!(dir_names(&vfat, "/to"), vec![".", "..", "dir", "file.txt"]);
+    assert_eq!(read_file(&vfat, "/to/file.txt"), b"file data");
+    assert_eq!(read_file(&vfat, "/to/dir/inner"), b"inner data");
+    assert_eq!(dir_names(&vfat, "/to/dir/.."), dir_names(&vfat, "/to"));
+
+    // A directory moved to the root points `..` at cluster 0
+    vfat.rename("/to/dir", "/top").expect("move dir to root");
+    sync(&vfat);
+    let vfat = mount(&disk);
+    assert_eq!(dir_names(&vfat, "/top/.."), vec!["from", "to", "top"]);
+}
+
+#[test]
+fn test_rename_open_file() {
+    let disk = fat32_image();
+    let vfat = mount(&disk);
+    vfat.create_dir("/dir").expect("create dir");
+    write_new_file(&vfat, "/", "a.txt", b"before");
+
+    let mut file = vfat.open_file("/a.txt").expect("open file");
+    file.write_all(b"B").expect("write");
+    vfat.rename("/a.txt", "/dir/b.txt").expect("rename");
+    file.seek(io::SeekFrom::End(0)).expect("seek");
+    file.write_all(b" and after").expect("write");
+    file.sync().expect("sync");
+
+    let vfat = mount(&disk);
+    assert_eq!(root_names(&vfat), vec!["dir"]);
+    assert_eq!(read_file(&vfat, "/dir/b.txt"), b"Before and after");
+    assert_eq!(check(&vfat), vec![]);
+}
+
+#[test]
+fn test_rename_errors() {
+    let disk = fat32_image();
+    let vfat = mount(&disk);
+
+    vfat.create_dir("/dir").expect("create dir");
+    vfat.create_dir("/dir/child").expect("create dir");
+    write_new_file(&vfat, "/", "a", b"a");
+    write_new_file(&vfat, "/", "b", b"b");
+
+    let e = vfat.rename("/a", "/B").unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::AlreadyExists);
+    let e = vfat.rename("/missing", "/c").unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::NotFound);
+    let e = vfat.rename("/a", "/missing/a").unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::NotFound);
+    let e = vfat.rename("/dir", "/dir/child/dir").unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+    let e = vfat.rename("/dir", "/dir/dir").unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+    // `..` in `/dir` is the root directory, which has no entry
+    let root = vfat.open_dir("/").expect("root directory");
+    let dir = vfat.open_dir("/dir").expect("open dir");
+    let e = dir.rename("..", &root, "c").unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+    let e = dir.rename(".", &root, "c").unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+    assert!(dir.remove_dir("..").is_err());
+
+    let vfat = mount(&disk);
+    assert_eq!(root_names(&vfat), vec!["a", "b", "dir"]);
+    assert_eq!(read_file(&vfat, "/a"), b"a");
+}
+
+fn cached_partition(capacity: usize) -> (LoggedDisk, vfat::CachedPartition) {
+    let disk = LoggedDisk::new(fat32_image());
+    let partition = vfat::Partition {
+        start: IMG_PART_START,
+        num_sectors: IMG_PART_SECTORS,
+        sector_size: 512,
+    };
+    (disk.clone(), vfat::CachedPartition::with_capacity(disk, partition, capacity))
+}
+
+#[test]
+fn test_cache_evicts_least_recently_used() {
+    let (disk, mut cache) = cached_partition(4);
+
+    for sector in 100..110 {
+        cache.get(sector).expect("read sector");
+    }
+    assert_eq!(cache.cached_sectors(), 4);
+
+    cache.get_mut(200).expect("write sector").copy_from_slice(&[0xAB; 512]);
+    for sector in 201..204 {
+        cache.get(sector).expect("read sector");
+    }
+    cache.get(200).expect("read sector");
+    assert_eq!(cache.cached_sectors(), 4);
+    assert!(disk.writes().is_empty(), "a recently used sector was evicted");
+
+    // 201 is now the least recently used sector, then the dirty 200
+    cache.get(204).expect("read sector");
+    assert!(disk.writes().is_empty(), "clean sectors are not written back");
+    cache.get(205).expect("read sector");
+    cache.get(206).expect("read sector");
+    cache.get(207).expect("read sector");
+    assert_eq!(disk.writes(), vec![IMG_PART_START + 200]);
+    assert_eq!(disk.disk.sectors(IMG_PART_START + 200, 1), vec![0xAB; 512]);
+
+    // An evicted sector is read back from the disk
+    assert_eq!(cache.get(200).expect("read sector"), &[0xAB; 512][..]);
+}
+
+#[test]
+fn test_cache_sync_writes_dirty_sectors_in_order() {
+    let (disk, mut cache) = cached_partition(16);
+
+    for &sector in [300u64, 3, 170, 42].iter() {
+        cache.write_sector(sector, &[sector as u8; 512]).expect("write sector");
+    }
+    cache.get(50).expect("read sector");
+    assert!(disk.writes().is_empty(), "writes reached the disk before sync");
+
+    cache.sync().expect("sync");
+    let expected: Vec<u64> = [3, 42, 170, 300].iter().map(|n| IMG_PART_START + n).collect();
+    assert_eq!(disk.writes(), expected);
+    assert_eq!(disk.disk.sectors(IMG_PART_START + 170, 1), vec![170; 512]);
+
+    cache.sync().expect("sync");
+    assert!(disk.writes().is_empty(), "clean sectors were written again");
+}
+
+#[test]
+fn test_changes_reach_disk_on_sync() {
+    let disk = fat32_image();
+    let vfat = mount(&disk);
+
+    vfat.create_dir("/pending").expect("create dir");
+    assert!(root_names(&mount(&disk)).is_empty());
+
+    sync(&vfat);
+    assert_eq!(root_names(&mount(&disk)), vec!["pending"]);
+}
+
+/// The first sector of the data region of `fat32_image()`.
+const IMG_DATA_START: u64 = IMG_PART_START + IMG_RESERVED + 2 * IMG_SECTORS_PER_FAT;
+
+/// Returns the number of requests and the number of sectors in `reads` that
+/// fall in the data region.
+fn data_reads(reads: &[(u64, u64)]) -> (usize, u64) {
+    let data: Vec<_> = reads.iter().filter(|(sector, _)| *sector >= IMG_DATA_START).collect();
+    (data.len(), data.iter().map(|(_, count)| count).sum())
+}
+
+fn big_file_image(clusters: usize) -> (Vec<u8>, LoggedDisk) {
+    let contents: Vec<u8> = (0..clusters * 512).map(|i| (i % 251) as u8).collect();
+    let disk = fat32_image();
+    write_new_file(&mount(&disk), "/", "big file.bin", &contents);
+    (contents, LoggedDisk::new(disk))
+}
+
+#[test]
+fn test_sequential_read_coalesces_sectors() {
+    const CLUSTERS: usize = 200;
+    let (contents, disk) = big_file_image(CLUSTERS);
+    let vfat = VFat::<StdVFatHandle>::from(disk.clone()).expect("mount");
+
+    let mut file = vfat.open_file("/big file.bin").expect("open file");
+    // The first access reads the file's directory entry
+    file.read(&mut []).expect("read");
+    disk.reads();
+    let mut data = Vec::new();
+    let mut buf = [0u8; 512];
+    loop {
+        let n = file.read(&mut buf).expect("read");
+        if n == 0 {
+            break;
+        }
+        data.extend_from_slice(&buf[..n]);
+    }
+    assert!(data == contents, "file data differs");
+
+    // Reading a sector at a time would take one request per cluster
+    let (requests, sectors) = data_reads(&disk.reads());
+    assert_eq!(sectors, CLUSTERS as u64, "sectors were read more than once");
+    assert!(requests <= 12, "{} requests to read {} sectors", requests, sectors);
+}
+
+#[test]
+fn test_whole_file_read_is_one_request() {
+    let (contents, disk) = big_file_image(100);
+    let vfat = VFat::<StdVFatHandle>::from(disk.clone()).expect("mount");
+
+    let mut file = vfat.open_file("/big file.bin").expect("open file");
+    // The first access reads the file's directory entry
+    file.read(&mut []).expect("read");
+    disk.reads();
+    let mut data = vec![0u8; file.size() as usize];
+    file.read_exact(&mut data).expect("read");
+    assert!(data == contents, "file data differs");
+    assert_eq!(data_reads(&disk.reads()), (1, 100));
+}
+
+#[test]
+fn test_fragmented_read() {
+    let disk = fat32_image();
+    let vfat = mount(&disk);
+    let root = vfat.open_dir("/").expect("root directory");
+    let (mut a, mut b) = (root.create_file("a").unwrap(), root.create_file("b").unwrap());
+    for i in 0..6u8 {
+        a.write_all(&[i; 2 * 512]).expect("write");
+        a.sync().expect("sync");
+        b.write_all(&[0xF0 | i; 512]).expect("write");
+        b.sync().expect("sync");
+    }
+
+    let disk = LoggedDisk::new(disk);
+    let vfat = VFat::<StdVFatHandle>::from(disk.clone()).expect("mount");
+    let mut file = vfat.open_file("/a").expect("open file");
+    // The first access reads the file's directory entry
+    file.read(&mut []).expect("read");
+    disk.reads();
+    let mut data = Vec::new();
+    file.read_to_end(&mut data).expect("read");
+    let expected: Vec<u8> = (0..6u8).flat_map(|i| vec![i; 2 * 512]).collect();
+    assert!(data == expected, "file data differs");
+
+    // `a` is stored in six runs of two clusters
+    let reads = disk.reads();
+    assert_eq!(data_reads(&reads).1, 12);
+    assert!(reads.iter().all(|&(_, count)| count <= 2), "read past a fragment: {:?}", reads);
+}
+
+#[test]
+fn test_listing_does_not_read_file_data() {
+    let (_, disk) = big_file_image(100);
+    let vfat = VFat::<StdVFatHandle>::from(disk.clone()).expect("mount");
+    disk.reads();
+
+    assert_eq!(root_names(&vfat), vec!["big file.bin"]);
+    let (_, sectors) = data_reads(&disk.reads());
+    assert_eq!(sectors, 1, "only the root directory should be read");
+}
+
+#[test]
+fn test_store_writes_only_changed_clusters() {
+    let (mut contents, disk) = big_file_image(100);
+    let vfat = VFat::<StdVFatHandle>::from(disk.clone()).expect("mount");
+    let mut file = vfat.open_file("/big file.bin").expect("open file");
+
+    file.seek(io::SeekFrom::Start(50 * 512 + 10)).expect("seek");
+    file.write_all(b"patched").expect("write");
+    file.sync().expect("sync");
+    // The root directory takes the first data sector; the file follows it
+    let data_writes = |writes: Vec<u64>| writes.into_iter().filter(|&s| s > IMG_DATA_START).count();
+    assert_eq!(data_writes(disk.writes()), 1);
+    contents[50 * 512 + 10..50 * 512 + 17].copy_from_slice(b"patched");
+
+    file.seek(io::SeekFrom::End(0)).expect("seek");
+    for i in 0..4u8 {
+        file.write_all(&[i; 256]).expect("write");
+        file.sync().expect("sync");
+        contents.extend_from_slice(&[i; 256]);
+    }
+    // Two appended clusters, each written by two syncs
+    assert_eq!(data_writes(disk.writes()), 4);
+
+    let vfat = VFat::<StdVFatHandle>::from(disk.clone()).expect("mount");
+    assert!(read_file(&vfat, "/big file.bin") == contents, "file data differs");
+}
+
+#[test]
+fn test_seek() {
+    let (contents, disk) = big_file_image(20);
+    let vfat = VFat::<StdVFatHandle>::from(disk.clone()).expect("mount");
+    let mut file = vfat.open_file("/big file.bin").expect("open file");
+    // The first access reads the file's directory entry
+    file.read(&mut []).expect("read");
+    let len = contents.len() as u64;
+
+    assert_eq!(file.seek(io::SeekFrom::End(0)).unwrap(), len);
+    assert_eq!(file.seek(io::SeekFrom::End(-100)).unwrap(), len - 100);
+    assert_eq!(file.seek(io::SeekFrom::Current(-1000)).unwrap(), len - 1100);
+    assert_eq!(file.seek(io::SeekFrom::Current(600)).unwrap(), len - 500);
+    assert_eq!(file.seek(io::SeekFrom::Start(len)).unwrap(), len);
+
+    for pos in [
+        io::SeekFrom::Start(len + 1),
+        io::SeekFrom::End(1),
+        io::SeekFrom::End(-(len as i64) - 1),
+        io::SeekFrom::Current(i64::MIN),
+        io::SeekFrom::Current(1),
+    ]
+    .iter()
+    {
+        let e = file.seek(*pos).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput, "{:?}", pos);
+    }
+    assert_eq!(file.stream_position().unwrap(), len, "a failed seek moved the position");
+
+    // Seeking back and forth reads the right data without reading it twice
+    disk.reads();
+    let mut buf = [0u8; 700];
+    file.seek(io::SeekFrom::Start(3000)).unwrap();
+    file.read_exact(&mut buf).unwrap();
+    assert!(buf[..] == contents[3000..3700]);
+    file.seek(io::SeekFrom::Current(-1200)).unwrap();
+    file.read_exact(&mut buf).unwrap();
+    assert!(buf[..] == contents[2500..3200]);
+    assert_eq!(data_reads(&disk.reads()).1, 8);
+    assert_eq!(file.read(&mut buf).unwrap(), 700);
+    assert_eq!(file.seek(io::SeekFrom::End(-10)).unwrap(), len - 10);
+    assert_eq!(file.read(&mut buf).unwrap(), 10);
+    assert!(buf[..10] == contents[contents.len() - 10..]);
+    assert_eq!(file.read(&mut buf).unwrap(), 0);
+}
+
+/// The byte offset on the disk of the data of `cluster`.
+fn cluster_offset(cluster: u32) -> usize {
+    let data_start = IMG_PART_START + IMG_RESERVED + 2 * IMG_SECTORS_PER_FAT;
+    ((data_start + cluster as u64 - 2) * 512) as usize
+}
+
+fn poke(disk: &MemDisk, offset: usize, bytes: &[u8]) {
+    disk.0.lock().unwrap()[offset..offset + bytes.len()].copy_from_slice(bytes);
+}
+
+/// Sets the FAT entry of `cluster` in the FAT copies `copies` on the disk.
+fn poke_fat(disk: &MemDisk, copies: &[u64], cluster: u32, value: u32) {
+    for &copy in copies {
+        let fat_start = (IMG_PART_START + IMG_RESERVED + copy * IMG_SECTORS_PER_FAT) * 512;
+        poke(disk, fat_start as usize + 4 * cluster as usize, &value.to_le_bytes());
+    }
+}
+
+fn check(vfat: &StdVFatHandle) -> Vec<vfat::Problem> {
+    let findings = vfat.lock(|vfat| vfat.check()).expect("check");
+    assert!(findings.iter().all(|finding| !finding.repaired));
+    findings.into_iter().map(|finding| finding.problem).collect()
+}
+
+fn repair(vfat: &StdVFatHandle) -> Vec<(vfat::Problem, bool)> {
+    let findings = vfat.lock(|vfat| vfat.repair()).expect("repair");
+    findings.into_iter().map(|finding| (finding.problem, finding.repaired)).collect()
+}
+
+#[test]
+fn test_check_clean_volume() {
+    let disk = fat32_image();
+    let vfat = mount(&disk);
+    assert!(!vfat.lock(|v| v.is_dirty()));
+
+    write_new_file(&vfat, "/", "hello.txt", b"hello");
+    vfat.create_dir("/sub").expect("create dir");
+    write_new_file(&vfat, "/sub", "BIG.BIN", &[7; 3 * 512 + 1]);
+    assert_eq!(check(&vfat), vec![]);
+
+    // A change that was not synced leaves the volume marked as in use
+    vfat.create_dir("/unsynced").expect("create dir");
+    assert!(vfat.lock(|v| v.is_dirty()));
+    let vfat = mount(&disk);
+    assert!(vfat.lock(|v| v.is_dirty()));
+    assert_eq!(repair(&vfat), vec![]);
+    sync(&vfat);
+    assert!(!mount(&disk).lock(|v| v.is_dirty()));
+}
+
+#[test]
+fn test_check_lost_chain() {
+    use vfat::Problem::*;
+
+    let disk = fat32_image();
+    poke_fat(&disk, &[0, 1], 100, 101);
+    poke_fat(&disk, &[0, 1], 101, 0x0FFF_FFFF);
+    let vfat = mount(&disk);
+
+    let free = IMG_DATA_CLUSTERS - 1;
+    assert_eq!(
+        check(&vfat),
+        vec![
+            LostChain { first: 100.into(), clusters: 2 },
+            FreeCountMismatch { recorded: free, actual: free - 2 },
+        ]
+    );
+    assert_eq!(repair(&vfat), vec![(LostChain { first: 100.into(), clusters: 2 }, true)]);
+    sync(&vfat);
+
+    let vfat = mount(&disk);
+    assert_eq!(check(&vfat), vec![]);
+    assert_eq!(fsinfo_free_count(&disk), free);
+}
+
+#[test]
+fn test_check_cross_linked_chains() {
+    use vfat::Problem::*;
+
+    let disk = fat32_image();
+    let vfat = mount(&disk);
+    write_new_file(&vfat, "/", "a.bin", &[0xAA; 2 * 512]);
+    write_new_file(&vfat, "/", "b.bin", &[0xBB; 512]);
+
+    // a.bin's clusters are 3 and 4, b.bin's is 5
+    poke_fat(&disk, &[0, 1], 4, 5);
+    let vfat = mount(&disk);
+    assert_eq!(
+        check(&vfat),
+        vec![
+            SizeMismatch { path: "/a.bin".into(), size: 1024, clusters: 3 },
+            CrossLinked { path: "/b.bin".into(), other: "/a.bin".into(), cluster: 5.into() },
+            SizeMismatch { path: "/b.bin".into(), size: 512, clusters: 0 },
+        ]
+    );
+
+    // Cutting a.bin's chain gives cluster 5 back to b.bin
+    assert_eq!(
+        repair(&vfat),
+        vec![(SizeMismatch { path: "/a.bin".into(), size: 1024, clusters: 3 }, true)]
+    );
+    sync(&vfat);
+    let vfat = mount(&disk);
+    assert_eq!(check(&vfat), vec![]);
+    assert_eq!(read_file(&vfat, "/a.bin"), vec![0xAA; 2 * 512]);
+    assert_eq!(read_file(&vfat, "/b.bin"), vec![0xBB; 512]);
+}
+
+#[test]
+fn test_check_broken_chain() {
+    use vfat::Problem::*;
+
+    let disk = fat32_image();
+    let vfat = mount(&disk);
+    write_new_file(&vfat, "/", "C.BIN", &[0xCC; 3 * 512]);
+
+    // C.BIN's clusters are 3, 4 and 5
+    poke_fat(&disk, &[0, 1], 4, 0);
+    let vfat = mount(&disk);
+    let free = IMG_DATA_CLUSTERS - 1 - 3;
+    assert_eq!(
+        check(&vfat),
+        vec![
+            BadChain { path: "/C.BIN".into(), cluster: 4.into() },
+            SizeMismatch { path: "/C.BIN".into(), size: 3 * 512, clusters: 1 },
+            LostChain { first: 5.into(), clusters: 1 },
+            FreeCountMismatch { recorded: free, actual: free + 1 },
+        ]
+    );
+    assert_eq!(
+        repair(&vfat),
+        vec![
+            (BadChain { path: "/C.BIN".into(), cluster: 4.into() }, true),
+            (SizeMismatch { path: "/C.BIN".into(), size: 3 * 512, clusters: 1 }, true),
+            (LostChain { first: 5.into(), clusters: 1 }, true),
+            (FreeCountMismatch { recorded: free, actual: free + 2 }, true),
+        ]
+    );
+    sync(&vfat);
+
+    let vfat = mount(&disk);
+    assert_eq!(check(&vfat), vec![]);
+    assert_eq!(read_file(&vfat, "/C.BIN"), vec![0xCC; 512]);
+}
+
+#[test]
+fn test_check_bad_lfn() {
+    let disk = fat32_image();
+    let vfat = mount(&disk);
+    write_new_file(&vfat, "/", "long name.txt", b"data");
+
+    // Slot 0 of the root directory is the LFN entry; its checksum is byte 13
+    let checksum = cluster_offset(2) + 13;
+    let byte = disk.0.lock().unwrap()[checksum];
+    poke(&disk, checksum, &[byte.wrapping_add(1)]);
+    let vfat = mount(&disk);
+    let problem = vfat::Problem::BadLfn { dir: "/".into(), slot: 0 };
+    assert_eq!(check(&vfat), vec![problem.clone()]);
+    assert_eq!(repair(&vfat), vec![(problem, true)]);
+    sync(&vfat);
+
+    let vfat = mount(&disk);
+    assert_eq!(check(&vfat), vec![]);
+    assert_eq!(root_names(&vfat), vec!["LONGNA~1.TXT"]);
+    assert_eq!(read_file(&vfat, "/LONGNA~1.TXT"), b"data");
+}
+
+#[test]
+fn test_check_dot_entries() {
+    use vfat::Problem::*;
+
+    let disk = fat32_image();
+    let vfat = mount(&disk);
+    vfat.create_dir("/sub").expect("create dir");
+    write_new_file(&vfat, "/sub", "F.TXT", b"f");
+
+    // /sub starts at cluster 3: delete `.` and point `..` to /sub itself
+    poke(&disk, cluster_offset(3), &[0xE5]);
+    poke(&disk, cluster_offset(3) + 32 + 26, &3u16.to_le_bytes());
+    let vfat = mount(&disk);
+    assert_eq!(
+        check(&vfat),
+        vec![
+            BadDotEntry { dir: "/sub".into(), name: "." },
+            BadDotEntry { dir: "/sub".into(), name: ".." },
+        ]
+    );
+    assert_eq!(repair(&vfat).iter().filter(|(_, repaired)| *repaired).count(), 2);
+    sync(&vfat);
+
+    let vfat = mount(&disk);
+    assert_eq!(check(&vfat), vec![]);
+    assert_eq!(dir_names(&vfat, "/sub"), vec![".", "..", "F.TXT"]);
+    assert_eq!(dir_names(&vfat, "/sub/.."), vec!["sub"]);
+}
+
+#[test]
+fn test_check_fat_copies() {
+    let disk = fat32_image();
+    poke_fat(&disk, &[1], 50, 0x0FFF_FFFF);
+    let vfat = mount(&disk);
+
+    let problem = vfat::Problem::FatCopyMismatch { copy: 1, sectors: 1 };
+    assert_eq!(check(&vfat), vec![problem.clone()]);
+    assert_eq!(repair(&vfat), vec![(problem, true)]);
+    sync(&vfat);
+
+    let (fat1, fat2) = fat_copies(&disk);
+    assert!(fat1 == fat2, "FAT copies differ");
+    assert_eq!(check(&mount(&disk)), vec![]);
+}
+
+#[test]
+fn test_format_layout() {
+    let disk = fat32_image();
+    let mbr = MasterBootRecord::from(disk.clone()).expect("mbr");
+    let entry = mbr.get_fat32_partition().expect("FAT32 partition");
+    assert_eq!({ entry.relative_sector } as u64, IMG_PART_START);
+    assert_eq!({ entry.total_sectors } as u64, IMG_PART_SECTORS);
+
+    let ebpb = BiosParameterBlock::from(disk.clone(), IMG_PART_START).expect("ebpb");
+    assert_eq!({ ebpb.num_reserved_sectors } as u64, IMG_RESERVED);
+    assert_eq!({ ebpb.sectors_per_fat } as u64, IMG_SECTORS_PER_FAT);
+    assert_eq!(disk.sectors(IMG_PART_START, 1), disk.sectors(IMG_PART_START + 6, 1));
+    assert_eq!(disk.sectors(IMG_PART_START + 1, 1), disk.sectors(IMG_PART_START + 7, 1));
+
+    let vfat = mount(&disk);
+    assert!(!vfat.lock(|v| v.is_dirty()));
+    assert_eq!(vfat.lock(|v| v.free_clusters()), Some(IMG_DATA_CLUSTERS - 1));
+    assert!(root_names(&vfat).is_empty());
+    assert_eq!(check(&vfat), vec![]);
+}
+
+#[test]
+fn test_format_round_trip() {
+    // Large enough for a 1 MiB-aligned partition, and filled with garbage
+    // that the formatter must not leave behind.
+    let sectors = 64 << 11;
+    let disk = MemDisk(Arc::new(Mutex::new(vec![0xFF; sectors * 512])));
+    vfat::format(disk.clone(), sectors as u64).expect("format");
+
+    let mbr = MasterBootRecord::from(disk.clone()).expect("mbr");
+    let entry = mbr.get_fat32_partition().expect("FAT32 partition");
+    assert_eq!({ entry.relative_sector }, 2048);
+
+    let vfat = mount(&disk);
+    assert_eq!(check(&vfat), vec![]);
+    write_new_file(&vfat, "/", "hello.txt", b"hello, world");
+    vfat.create_dir("/sub").expect("create dir");
+    sync(&vfat);
+
+    let vfat = mount(&disk);
+    assert_eq!(root_names(&vfat), vec!["hello.txt", "sub"]);
+    assert_eq!(read_file(&vfat, "/hello.txt"), b"hello, world");
+    assert_eq!(dir_names(&vfat, "/sub"), vec![".", ".."]);
+    assert_eq!(check(&vfat), vec![]);
+}
+
+#[test]
+fn test_format_too_small() {
+    let disk = MemDisk(Arc::new(Mutex::new(vec![0u8; 32 * 512])));
+    let err = vfat::format(disk, 32).expect_err("device is too small");
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn test_timestamp_unix_conversion() {
+    use vfat::Timestamp as Ts;
+
+    let ts = Ts::from_unix(1_709_210_097); // 2024-02-29 12:34:57
+    assert_eq!((ts.year(), ts.month(), ts.day()), (2024, 2, 29));
+    assert_eq!((ts.hour(), ts.minute(), ts.second()), (12, 34, 56));
+    assert_eq!(ts.to_unix(), 1_709_210_096);
+
+    let ts = Ts::from_unix(1_246_694_400); // 2009-07-04 08:00:00
+    assert_eq!((ts.year(), ts.month(), ts.day(), ts.hour()), (2009, 7, 4, 8));
+    assert_eq!(ts.to_unix(), 1_246_694_400);
+
+    // Clamped to what FAT32 can represent
+    let ts = Ts::from_unix(0);
+    assert_eq!((ts.year(), ts.month(), ts.day(), ts.hour()), (1980, 1, 1, 0));
+    let ts = Ts::from_unix(u64::MAX);
+    assert_eq!((ts.year(), ts.month(), ts.day()), (2107, 12, 31));
+    assert_eq!((ts.hour(), ts.minute(), ts.second()), (23, 59, 58));
+
+    assert_eq!(Ts::default().to_unix(), 0);
+}
+
+fn clock_2009() -> vfat::Timestamp {
+    vfat::Timestamp::from_unix(1_246_694_400) // 2009-07-04 08:00:00
+}
+
+fn clock_2024() -> vfat::Timestamp {
+    vfat::Timestamp::from_unix(1_709_210_096) // 2024-02-29 12:34:56
+}
+
+fn metadata(vfat: &StdVFatHandle, path: &str) -> vfat::Metadata {
+    vfat.open(path).expect("entry exists").metadata().clone()
+}
+
+#[test]
+fn test_clock_stamps_entries() {
+    let disk = fat32_image();
+    let vfat = mount(&disk);
+
+    // Without a clock, timestamps stay zeroed
+    write_new_file(&vfat, "/", "old.txt", b"old");
+    assert_eq!(metadata(&vfat, "/old.txt").modified().to_unix(), 0);
+
+    vfat.lock(|v| v.set_clock(clock_2009));
+    write_new_file(&vfat, "/", "file.txt", b"first");
+    vfat.create_dir("/dir").expect("create dir");
+    sync(&vfat);
+
+    let vfat = mount(&disk);
+    let file = metadata(&vfat, "/file.txt");
+    assert_eq!(file.created(), clock_2009());
+    assert_eq!(file.modified(), clock_2009());
+    assert_eq!(file.accessed().date, clock_2009().date);
+    assert_eq!(metadata(&vfat, "/dir").created(), clock_2009());
+    assert_eq!(metadata(&vfat, "/dir/.").created(), clock_2009());
+
+    // Writing updates the modification and access times only
+    vfat.lock(|v| v.set_clock(clock_2024));
+    let mut file = vfat.open_file("/file.txt").expect("file exists");
+    file.seek(io::SeekFrom::End(0)).expect("seek");
+    file.write_all(b", second").expect("write");
+    file.sync().expect("sync");
+    assert_eq!(file.metadata.modified(), clock_2024());
+
+    // Storing a file that was not written to keeps its times
+    vfat.lock(|v| v.set_clock(clock_2009));
+    vfat.open_file("/file.txt").expect("file exists").sync().expect("sync");
+
+    let vfat = mount(&disk);
+    let file = metadata(&vfat, "/file.txt");
+    assert_eq!(file.created(), clock_2009());
+    assert_eq!(file.modified(), clock_2024());
+    assert_eq!(file.accessed().date, clock_2024().date);
+    assert_eq!(read_file(&vfat, "/file.txt"), b"first, second");
+    assert_eq!(check(&vfat), vec![]);
+}
+
+#[test]
+fn test_set_times_and_attributes() {
+    use vfat::Attributes;
+
+    let disk = fat32_image();
+    let vfat = mount(&disk);
+    write_new_file(&vfat, "/", "file.txt", b"data");
+    vfat.create_dir("/dir").expect("create dir");
+
+    let mut file = vfat.open_file("/file.txt").expect("file exists");
+    file.set_times(Some(clock_2009()), None, Some(clock_2024())).expect("set times");
+    file.set_attributes(Attributes(Attributes::READ_ONLY | Attributes::HIDDEN))
+        .expect("set attributes");
+    let err = file.set_attributes(Attributes(Attributes::DIRECTORY)).expect_err("kind bit");
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+    let mut dir = vfat.open_dir("/dir").expect("dir exists");
+    dir.set_times(None, Some(clock_2024()), Some(clock_2009())).expect("set times");
+    dir.set_attributes(Attributes(Attributes::SYSTEM)).expect("set attributes");
+
+    let mut root = vfat.open_dir("/").expect("root");
+    let err = root.set_times(Some(clock_2009()), None, None).expect_err("root has no entry");
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    sync(&vfat);
+
+    let vfat = mount(&disk);
+    let file = metadata(&vfat, "/file.txt");
+    assert_eq!(file.created(), clock_2009());
+    assert_eq!(file.modified(), clock_2024());
+    assert!(file.read_only() && file.hidden());
+    assert_eq!(file.attributes, Attributes(Attributes::READ_ONLY | Attributes::HIDDEN));
+    assert_eq!(file.size, 4);
+
+    let dir = metadata(&vfat, "/dir");
+    assert_eq!(dir.accessed().date, clock_2024().date);
+    assert_eq!(dir.modified(), clock_2009());
+    assert_eq!(dir.attributes, Attributes(Attributes::DIRECTORY | Attributes::SYSTEM));
+    assert!(vfat.open("/dir").expect("dir exists").is_dir());
+    assert_eq!(check(&vfat), vec![]);
+}
@@ -44,6 +44,32 @@ pub trait BlockDevice: Send {
         Ok(read)
     }
 
+    /// Reads consecutive sectors starting at sector `n` into `buf`, whose
+    /// length must be a multiple of `self.sector_size()`. The number of bytes
+    /// read is returned.
+    ///
+    /// Devices that can transfer several sectors in one request should
+    /// override this method; by default the sectors are read one at a time.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if the length of `buf` is not a
+    /// multiple of the sector size, or if seeking or reading from `self`
+    /// fails.
+    fn read_sectors(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let sector_size = self.sector_size() as usize;
+        if !buf.len().is_multiple_of(sector_size) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "buffer is not a multiple of the sector size",
+            ));
+        }
+        for (i, chunk) in buf.chunks_mut(sector_size).enumerate() {
+            self.read_sector(n + i as u64, chunk)?;
+        }
+        Ok(buf.len())
+    }
+
     /// Overwrites sector `n` with the contents of `buf`.
     ///
     /// `self.sector_size()` or `buf.len()` bytes, whichever is less, are written
@@ -58,10 +84,18 @@ pub trait BlockDevice: Send {
 }
 
 impl<'a, T: BlockDevice> BlockDevice for &'a mut T {
+    fn sector_size(&self) -> u64 {
+        (**self).sector_size()
+    }
+
     fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
         (*self).read_sector(n, buf)
     }
 
+    fn read_sectors(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        (*self).read_sectors(n, buf)
+    }
+
     fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
         (*self).write_sector(n, buf)
     }
@@ -113,4 +113,36 @@ pub trait FileSystem: Sized {
             .into_dir()
             .ok_or(io::Error::new(io::ErrorKind::Other, "not a directory"))
     }
+
+    /// Creates an empty directory at `path`. `path` must be absolute.
+    ///
+    /// # Errors
+    ///
+    /// If the parent of `path` cannot be opened as a directory, the errors of
+    /// `open_dir()` are returned.
+    ///
+    /// If an entry already exists at `path`, an error kind of `AlreadyExists`
+    /// is returned. If the last component of `path` is not a valid name, an
+    /// error kind of `InvalidInput` is returned.
+    fn create_dir<P: AsRef<Path>>(self, path: P) -> io::Result<Self::Dir>;
+
+    /// Removes the empty directory at `path`. `path` must be absolute.
+    ///
+    /// # Errors
+    ///
+    /// In addition to the error conditions for `open()`, this method returns
+    /// an error kind of `Other` if the entry at `path` is not a directory or
+    /// is not empty.
+    fn remove_dir<P: AsRef<Path>>(self, path: P) -> io::Result<()>;
+
+    /// Moves the entry at `from` to `to`, which may be in another directory.
+    /// Both paths must be absolute.
+    ///
+    /// # Errors
+    ///
+    /// In addition to the error conditions for `open()` on `from` and on the
+    /// parent of `to`, this method returns an error kind of `AlreadyExists`
+    /// if an entry already exists at `to`, and an error kind of
+    /// `InvalidInput` if a directory would be moved into itself.
+    fn rename<P: AsRef<Path>, Q: AsRef<Path>>(self, from: P, to: Q) -> io::Result<()>;
 }
@@ -6,10 +6,14 @@ use shim::io;
 
 use crate::traits::BlockDevice;
 
+/// The number of sectors a `CachedPartition` keeps in memory by default.
+pub const DEFAULT_CAPACITY: usize = 256;
+
 #[derive(Debug)]
 struct CacheEntry {
     data: Vec<u8>,
     dirty: bool,
+    last_use: u64, // value of the partition's use counter at the last access
 }
 
 pub struct Partition {
@@ -24,6 +28,8 @@ pub struct Partition {
 pub struct CachedPartition {
     device: Box<dyn BlockDevice>,
     cache: HashMap<u64, CacheEntry>,
+    capacity: usize,
+    uses: u64,
     partition: Partition,
 }
 
@@ -33,6 +39,9 @@ impl CachedPartition {
     /// `partition`. All reads and writes from `CacheDevice` are performed on
     /// in-memory caches.
     ///
+    /// At most `DEFAULT_CAPACITY` sectors are cached at once; see
+    /// `with_capacity()`.
+    ///
     /// The `partition` parameter determines the size of a logical sector and
     /// where logical sectors begin. An access to a sector `0` will be
     /// translated to physical sector `partition.start`. Virtual sectors of
@@ -45,18 +54,42 @@ impl CachedPartition {
     ///
     /// Panics if the partition's sector size is < the device's sector size.
     pub fn new<T>(device: T, partition: Partition) -> CachedPartition
+    where
+        T: BlockDevice + 'static,
+    {
+        CachedPartition::with_capacity(device, partition, DEFAULT_CAPACITY)
+    }
+
+    /// Creates a new `CachedPartition` like `new()` that caches at most
+    /// `capacity` sectors. When the cache is full, the least recently used
+    /// sector is evicted to make room, and written back first if it is dirty.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the partition's sector size is < the device's sector size or
+    /// if `capacity` is `0`.
+    pub fn with_capacity<T>(device: T, partition: Partition, capacity: usize) -> CachedPartition
     where
         T: BlockDevice + 'static,
     {
         assert!(partition.sector_size >= device.sector_size());
+        assert!(capacity > 0);
 
         CachedPartition {
             device: Box::new(device),
-            cache: HashMap::new(),
+            cache: HashMap::with_capacity(capacity),
+            capacity,
+            uses: 0,
             partition,
         }
     }
 
+    /// Returns the number of sectors currently cached.
+    #[cfg(test)]
+    pub fn cached_sectors(&self) -> usize {
+        self.cache.len()
+    }
+
     /// Returns the number of physical sectors that corresponds to
     /// one logical sector.
     fn factor(&self) -> u64 {
@@ -87,21 +120,9 @@ impl CachedPartition {
     ///
     /// Returns an error if there is an error reading the sector from the disk.
     pub fn get_mut(&mut self, sector: u64) -> io::Result<&mut [u8]> {
-        match self.cache.get_mut(&sector) {
-            Some(x) => {
-                (*x).dirty = true;
-            }
-            None => {
-                let mut data = vec![0 as u8, self.partition.sector_size as u8];
-                self.device.read_sector(
-                    self.virtual_to_physical(sector).expect("bad caller"),
-                    &mut data,
-                )?;
-                self.cache.insert(sector, CacheEntry { data, dirty: true });
-            }
-        }
-
-        Ok(&mut self.cache.get_mut(&sector).expect("key should exist").data)
+        let entry = self.load(sector)?;
+        entry.dirty = true;
+        Ok(&mut entry.data)
     }
 
     /// Returns a reference to the cached sector `sector`. If the sector is not
@@ -111,23 +132,81 @@ impl CachedPartition {
     ///
     /// Returns an error if there is an error reading the sector from the disk.
     pub fn get(&mut self, sector: u64) -> io::Result<&[u8]> {
-        match self.cache.get_mut(&sector) {
-            Some(_) => {}
-            None => {
-                let mut data = vec![0 as u8; self.partition.sector_size as usize];
-                for i in 0..self.factor() {
-                    let start: usize = (i * self.device.sector_size()) as usize;
-                    let end: usize = ((i + 1) * self.device.sector_size()) as usize;
-                    self.device.read_sector(
-                        self.virtual_to_physical(sector).expect("bad caller") + i,
-                        &mut data[start..end],
-                    )?;
-                }
-                self.cache.insert(sector, CacheEntry { data, dirty: false });
+        Ok(&self.load(sector)?.data)
+    }
+
+    /// Writes every dirty cached sector back to the disk, in ascending sector
+    /// order. The sectors stay cached.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there is an error writing a sector to the disk. The
+    /// sectors that could not be written stay dirty.
+    pub fn sync(&mut self) -> io::Result<()> {
+        let mut dirty: Vec<u64> = self
+            .cache
+            .iter()
+            .filter(|(_, entry)| entry.dirty)
+            .map(|(&sector, _)| sector)
+            .collect();
+        dirty.sort_unstable();
+        for sector in dirty {
+            self.write_back(sector)?;
+        }
+        Ok(())
+    }
+
+    /// Writes cached sector `sector` to the disk and marks it clean.
+    fn write_back(&mut self, sector: u64) -> io::Result<()> {
+        let physical = self.partition.start + sector * self.factor();
+        let device_sector_size = self.device.sector_size() as usize;
+        let entry = self.cache.get_mut(&sector).expect("sector should be cached");
+        for (i, chunk) in entry.data.chunks(device_sector_size).enumerate() {
+            self.device.write_sector(physical + i as u64, chunk)?;
+        }
+        entry.dirty = false;
+        Ok(())
+    }
+
+    /// Removes the least recently used sector from the cache, writing it to
+    /// the disk first if it is dirty.
+    fn evict(&mut self) -> io::Result<()> {
+        let victim = self
+            .cache
+            .iter()
+            .min_by_key(|(_, entry)| entry.last_use)
+            .map(|(&sector, entry)| (sector, entry.dirty));
+        if let Some((sector, dirty)) = victim {
+            if dirty {
+                self.write_back(sector)?;
+            }
+            self.cache.remove(&sector);
+        }
+        Ok(())
+    }
+
+    /// Returns the cache entry of sector `sector`, reading it from the disk if
+    /// it is not already cached.
+    fn load(&mut self, sector: u64) -> io::Result<&mut CacheEntry> {
+        if !self.cache.contains_key(&sector) {
+            let physical = self.virtual_to_physical(sector).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, "sector out of range")
+            })?;
+            let device_sector_size = self.device.sector_size() as usize;
+            let mut data = vec![0u8; self.partition.sector_size as usize];
+            for (i, chunk) in data.chunks_mut(device_sector_size).enumerate() {
+                self.device.read_sector(physical + i as u64, chunk)?;
+            }
+            if self.cache.len() >= self.capacity {
+                self.evict()?;
             }
+            self.cache.insert(sector, CacheEntry { data, dirty: false, last_use: 0 });
         }
 
-        Ok(&self.cache.get(&sector).expect("key should exist").data)
+        self.uses += 1;
+        let entry = self.cache.get_mut(&sector).expect("key should exist");
+        entry.last_use = self.uses;
+        Ok(entry)
     }
 }
 
@@ -143,16 +222,53 @@ impl BlockDevice for CachedPartition {
     fn write_sector(&mut self, sector: u64, buf: &[u8]) -> io::Result<usize> {
         let bytes_to_write = (self.partition.sector_size as usize).min(buf.len());
         let sector = self.get_mut(sector)?;
-        sector.copy_from_slice(&buf[..bytes_to_write]);
+        sector[..bytes_to_write].copy_from_slice(&buf[..bytes_to_write]);
         Ok(bytes_to_write)
     }
 
     fn read_sector(&mut self, sector: u64, buf: &mut [u8]) -> io::Result<usize> {
         let bytes_to_read = (self.partition.sector_size as usize).min(buf.len());
         let sector = self.get(sector)?;
-        buf.copy_from_slice(&(sector[..bytes_to_read]));
+        buf[..bytes_to_read].copy_from_slice(&sector[..bytes_to_read]);
         Ok(bytes_to_read)
     }
+
+    /// Reads consecutive sectors into `buf`. Cached sectors are copied from
+    /// the cache, and every run of uncached sectors is read from the device
+    /// with a single request. Those sectors are not cached, so streaming a
+    /// large file does not evict the file system's metadata.
+    fn read_sectors(&mut self, sector: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let sector_size = self.partition.sector_size as usize;
+        if !buf.len().is_multiple_of(sector_size) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "buffer is not a multiple of the sector size",
+            ));
+        }
+        let count = (buf.len() / sector_size) as u64;
+        if sector.checked_add(count).is_none_or(|end| end > self.partition.num_sectors) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "sector out of range"));
+        }
+
+        let mut i = 0;
+        while i < count {
+            let chunk = &mut buf[i as usize * sector_size..];
+            if let Some(entry) = self.cache.get(&(sector + i)) {
+                chunk[..sector_size].copy_from_slice(&entry.data);
+                i += 1;
+                continue;
+            }
+
+            let run = (i..count)
+                .find(|&j| self.cache.contains_key(&(sector + j)))
+                .unwrap_or(count)
+                - i;
+            let physical = self.partition.start + (sector + i) * self.factor();
+            self.device.read_sectors(physical, &mut chunk[..run as usize * sector_size])?;
+            i += run;
+        }
+        Ok(buf.len())
+    }
 }
 
 // why not derive debug?
@@ -161,6 +277,7 @@ impl fmt::Debug for CachedPartition {
         f.debug_struct("CachedPartition")
             .field("device", &"<block device>")
             .field("cache", &self.cache)
+            .field("capacity", &self.capacity)
             .finish()
     }
 }
@@ -0,0 +1,483 @@
+use core::fmt;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use hashbrown::{HashMap, HashSet};
+use shim::io;
+
+use crate::util::VecExt;
+use crate::vfat::dir::{self, VFatDirEntry, VFatRegularDirEntry, DOT_DOT_NAME, DOT_NAME};
+use crate::vfat::vfat::EOC;
+use crate::vfat::{Cluster, Status, VFat, VFatHandle};
+
+/// A problem found by a consistency check of a volume.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Problem {
+    /// Copy `copy` of the FAT differs from the first copy in `sectors`
+    /// sectors.
+    FatCopyMismatch { copy: u8, sectors: u32 },
+    /// The chain of the entry at `path` reaches `cluster`, which is free,
+    /// bad, reserved, out of range or already part of the chain.
+    BadChain { path: String, cluster: Cluster },
+    /// The chain of the entry at `path` reaches `cluster`, which is part of
+    /// the chain of the entry at `other`.
+    CrossLinked { path: String, other: String, cluster: Cluster },
+    /// The chain of `clusters` clusters starting at `first` is in use but no
+    /// directory entry refers to it.
+    LostChain { first: Cluster, clusters: u32 },
+    /// The file at `path` is `size` bytes long but its chain holds `clusters`
+    /// clusters.
+    SizeMismatch { path: String, size: u32, clusters: u32 },
+    /// The LFN entries starting at slot `slot` of the directory at `dir`
+    /// don't belong to the regular entry that follows them, if there is one.
+    BadLfn { dir: String, slot: usize },
+    /// The `name` entry of the directory at `dir`, `.` or `..`, is missing or
+    /// refers to the wrong cluster.
+    BadDotEntry { dir: String, name: &'static str },
+    /// The FSInfo sector records `recorded` free clusters but `actual`
+    /// clusters are free.
+    FreeCountMismatch { recorded: u32, actual: u32 },
+}
+
+/// A problem found by `VFat::check()` or `VFat::repair()`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+    pub problem: Problem,
+    pub repaired: bool, // always false for `check()`
+}
+
+impl<HANDLE: VFatHandle> VFat<HANDLE> {
+    /// Checks the consistency of the volume without modifying it and returns
+    /// every problem found, in the order they were found.
+    pub fn check(&mut self) -> io::Result<Vec<Finding>> {
+        Checker::new(self, false).run()
+    }
+
+    /// Checks the volume like `check()` and fixes the problems found:
+    ///
+    ///   * copies of the FAT are overwritten with the first copy,
+    ///   * broken and cross-linked chains are cut before the offending
+    ///     cluster; directories left without any cluster are removed,
+    ///   * files are truncated to their chain, or their chain to their size,
+    ///   * broken LFN entries are deleted, which leaves their entry with its
+    ///     8.3 name,
+    ///   * `.` and `..` entries are rewritten, unless another entry took their
+    ///     slot,
+    ///   * lost chains are freed and the FSInfo free cluster count is updated.
+    ///
+    /// The fixes are kept in the sector cache like any other change; `sync()`
+    /// writes them to the device.
+    pub fn repair(&mut self) -> io::Result<Vec<Finding>> {
+        Checker::new(self, true).run()
+    }
+}
+
+/// The state of a single check of a volume.
+struct Checker<'a, HANDLE: VFatHandle> {
+    vfat: &'a mut VFat<HANDLE>,
+    repair: bool,
+    findings: Vec<Finding>,
+    owners: Vec<u32>,   // for every cluster, 1 + the index in `paths` of its entry, or 0
+    paths: Vec<String>, // paths of the entries whose chains were walked
+}
+
+/// A directory whose entries are yet to be checked.
+struct PendingDir {
+    path: String,
+    first: Cluster,
+    parent: Option<Cluster>, // None for the root directory
+    chain: Vec<Cluster>,
+}
+
+impl<'a, HANDLE: VFatHandle> Checker<'a, HANDLE> {
+    fn new(vfat: &'a mut VFat<HANDLE>, repair: bool) -> Checker<'a, HANDLE> {
+        let owners = vec![0; vfat.max_cluster() as usize + 1];
+        Checker { vfat, repair, findings: Vec::new(), owners, paths: Vec::new() }
+    }
+
+    fn run(mut self) -> io::Result<Vec<Finding>> {
+        // Fixed first, so that later repairs update every copy alike
+        for (copy, sectors) in self.vfat.fat_copy_mismatches()? {
+            self.report(Problem::FatCopyMismatch { copy, sectors }, self.repair);
+            if self.repair {
+                self.vfat.restore_fat_copy(copy)?;
+            }
+        }
+
+        let root = self.vfat.root_cluster();
+        let chain = self.claim_chain(root, "/", false)?;
+        let mut pending = vec![PendingDir { path: String::from("/"), first: root, parent: None, chain }];
+        while let Some(dir) = pending.pop() {
+            self.check_dir(dir, &mut pending)?;
+        }
+
+        self.check_lost_chains()?;
+        self.check_free_count()?;
+        Ok(self.findings)
+    }
+
+    fn report(&mut self, problem: Problem, repaired: bool) {
+        self.findings.push(Finding { problem, repaired });
+    }
+
+    /// Whether `cluster` is a data cluster of the volume.
+    fn in_range(&self, cluster: Cluster) -> bool {
+        (2..=self.vfat.max_cluster()).contains(&cluster.num())
+    }
+
+    /// Walks the chain starting at `first` on behalf of the entry at `path`
+    /// and returns its clusters, up to the first one that is not valid or
+    /// belongs to another chain. When repairing, the chain is cut there.
+    ///
+    /// If not even `first` is valid, the caller is left to drop its reference
+    /// to the chain, which it can only do if it is `removable`.
+    fn claim_chain(&mut self, first: Cluster, path: &str, removable: bool) -> io::Result<Vec<Cluster>> {
+        self.paths.push(String::from(path));
+        let owner = self.paths.len() as u32;
+
+        let bad_chain = |cluster| Problem::BadChain { path: String::from(path), cluster };
+        let mut chain = Vec::new();
+        let mut curr = first;
+        let problem = loop {
+            if !self.in_range(curr) {
+                break bad_chain(curr);
+            }
+            match self.owners[curr.num() as usize] {
+                0 => {}
+                other if other == owner => break bad_chain(curr),
+                other => {
+                    break Problem::CrossLinked {
+                        path: String::from(path),
+                        other: self.paths[other as usize - 1].clone(),
+                        cluster: curr,
+                    }
+                }
+            }
+            let next = match self.vfat.fat_status(curr)? {
+                Status::Data(next) => Some(next),
+                Status::Eoc(_) => None,
+                _ => break bad_chain(curr),
+            };
+
+            self.owners[curr.num() as usize] = owner;
+            chain.push(curr);
+            match next {
+                Some(next) => curr = next,
+                None => return Ok(chain),
+            }
+        };
+
+        let repaired = self.repair && (!chain.is_empty() || removable);
+        self.report(problem, repaired);
+        match chain.last() {
+            Some(&last) if self.repair => self.vfat.set_fat_entry(last, EOC)?,
+            _ => {}
+        }
+        Ok(chain)
+    }
+
+    /// Checks the entries of `dir`, walking the chains of its files and
+    /// adding its subdirectories to `pending`.
+    fn check_dir(&mut self, dir: PendingDir, pending: &mut Vec<PendingDir>) -> io::Result<()> {
+        let mut data = Vec::new();
+        for &cluster in &dir.chain {
+            self.vfat.read_cluster(cluster, &mut data)?;
+        }
+        let mut entries: Vec<VFatDirEntry> = unsafe { data.cast() };
+        let mut modified = match dir.parent {
+            Some(parent) if !entries.is_empty() => self.check_dot_entries(&dir, parent, &mut entries),
+            _ => false,
+        };
+
+        let mut lfn_start = None;
+        for i in 0..entries.len() {
+            if entries[i].is_end() || entries[i].is_free() {
+                if let Some(slot) = lfn_start.take() {
+                    modified |= self.drop_lfn(&dir, slot, &mut entries[slot..i]);
+                }
+                if entries[i].is_end() {
+                    break;
+                }
+                continue;
+            }
+            if entries[i].lfn().is_some() {
+                lfn_start.get_or_insert(i);
+                continue;
+            }
+
+            let first_slot = lfn_start.take().unwrap_or(i);
+            let regular = *entries[i].regular_mut().expect("slot holds a regular entry");
+            let short_name = regular.short_name();
+            let lfns = &entries[first_slot..i];
+            let name = if lfns.is_empty() || lfns_match(lfns, dir::lfn_checksum(&short_name)) {
+                dir::lfn_name(lfns).filter(|name| !name.is_empty())
+            } else {
+                modified |= self.drop_lfn(&dir, first_slot, &mut entries[first_slot..i]);
+                None
+            };
+            if short_name == DOT_NAME || short_name == DOT_DOT_NAME || regular.is_volume_label() {
+                continue;
+            }
+
+            let name = name.unwrap_or_else(|| display_name(&short_name));
+            let path = match dir.path.as_str() {
+                "/" => format!("/{}", name),
+                parent => format!("{}/{}", parent, name),
+            };
+            let start = regular.first_cluster();
+            let chain = match start.num() {
+                0 => Vec::new(),
+                _ => self.claim_chain(start, &path, true)?,
+            };
+
+            if regular.is_dir() {
+                if chain.is_empty() {
+                    if start.num() == 0 {
+                        self.report(Problem::BadChain { path, cluster: start }, self.repair);
+                    }
+                    if self.repair {
+                        entries[first_slot..=i].iter_mut().for_each(VFatDirEntry::delete);
+                        modified = true;
+                    }
+                    continue;
+                }
+                pending.push(PendingDir { path, first: start, parent: Some(dir.first), chain });
+            } else {
+                let regular = entries[i].regular_mut().expect("slot holds a regular entry");
+                if self.repair && chain.is_empty() && start.num() != 0 {
+                    regular.set_first_cluster(Cluster::from(0));
+                    modified = true;
+                }
+                modified |= self.check_size(&path, regular, &chain)?;
+            }
+        }
+        if let Some(slot) = lfn_start {
+            modified |= self.drop_lfn(&dir, slot, &mut entries[slot..]);
+        }
+
+        if modified {
+            self.vfat.write_dir_entries(dir.first, &entries)?;
+        }
+        Ok(())
+    }
+
+    /// Checks the `.` and `..` entries in the first two slots of the
+    /// subdirectory `dir` of the directory starting at `parent`. Returns
+    /// whether `entries` was modified.
+    fn check_dot_entries(&mut self, dir: &PendingDir, parent: Cluster, entries: &mut [VFatDirEntry]) -> bool {
+        let expected = [
+            (".", DOT_NAME, dir.first),
+            ("..", DOT_DOT_NAME, self.vfat.dot_dot_cluster(parent)),
+        ];
+        let mut modified = false;
+        for (slot, (name, short_name, cluster)) in expected.into_iter().enumerate() {
+            let (valid, repairable) = match entries[slot].regular_mut() {
+                Some(regular) if regular.short_name() == short_name => {
+                    (regular.is_dir() && regular.first_cluster() == cluster, true)
+                }
+                Some(_) => (false, false),
+                None => (false, entries[slot].is_free()),
+            };
+            if valid {
+                continue;
+            }
+
+            let repaired = self.repair && repairable;
+            self.report(Problem::BadDotEntry { dir: dir.path.clone(), name }, repaired);
+            if repaired {
+                entries[slot] = match slot {
+                    0 => VFatDirEntry::dot(cluster),
+                    _ => VFatDirEntry::dot_dot(cluster),
+                };
+                modified = true;
+            }
+        }
+        modified
+    }
+
+    /// Reports the LFN entries `lfns` starting at slot `slot` of `dir` as
+    /// broken and deletes them when repairing. Returns whether they were
+    /// deleted.
+    fn drop_lfn(&mut self, dir: &PendingDir, slot: usize, lfns: &mut [VFatDirEntry]) -> bool {
+        self.report(Problem::BadLfn { dir: dir.path.clone(), slot }, self.repair);
+        if self.repair {
+            lfns.iter_mut().for_each(VFatDirEntry::delete);
+        }
+        self.repair
+    }
+
+    /// Checks that the size of the file `regular` at `path` agrees with the
+    /// length of its `chain`. Returns whether `regular` was modified.
+    ///
+    /// A chain that is too long is cut without freeing the clusters past the
+    /// end of the file: they may be cross-linked to the file that owns them,
+    /// and are otherwise freed as a lost chain.
+    fn check_size(&mut self, path: &str, regular: &mut VFatRegularDirEntry, chain: &[Cluster]) -> io::Result<bool> {
+        let cluster_size = self.vfat.cluster_size();
+        let size = regular.size();
+        let needed = (size as usize).div_ceil(cluster_size);
+        if chain.len() == needed {
+            return Ok(false);
+        }
+
+        let clusters = chain.len() as u32;
+        self.report(Problem::SizeMismatch { path: String::from(path), size, clusters }, self.repair);
+        if !self.repair {
+            return Ok(false);
+        }
+        if chain.len() < needed {
+            regular.set_size(clusters * cluster_size as u32);
+            return Ok(true);
+        }
+
+        for &cluster in &chain[needed..] {
+            self.owners[cluster.num() as usize] = 0;
+        }
+        if needed == 0 {
+            regular.set_first_cluster(Cluster::from(0));
+            return Ok(true);
+        }
+        self.vfat.set_fat_entry(chain[needed - 1], EOC)?;
+        Ok(false)
+    }
+
+    /// Finds the clusters that are in use but were not reached from any
+    /// directory entry, and frees them when repairing.
+    fn check_lost_chains(&mut self) -> io::Result<()> {
+        let mut lost: HashMap<u32, Option<u32>> = HashMap::new();
+        for num in 2..=self.vfat.max_cluster() {
+            if self.owners[num as usize] != 0 {
+                continue;
+            }
+            let next = match self.vfat.fat_status(Cluster::from(num))? {
+                Status::Free | Status::Bad => continue,
+                Status::Data(next) => Some(next.num()),
+                _ => None,
+            };
+            lost.insert(num, next);
+        }
+
+        // Chains are followed from their heads; clusters left after that are
+        // on cycles
+        let linked: HashSet<u32> = lost.values().flatten().copied().collect();
+        let mut heads: Vec<u32> = lost.keys().copied().filter(|num| !linked.contains(num)).collect();
+        let mut rest: Vec<u32> = lost.keys().copied().collect();
+        heads.sort_unstable();
+        rest.sort_unstable();
+
+        for first in heads.into_iter().chain(rest) {
+            let mut chain = Vec::new();
+            let mut curr = Some(first);
+            while let Some(next) = curr.and_then(|num| lost.remove(&num).map(|next| (num, next))) {
+                chain.push(next.0);
+                curr = next.1;
+            }
+            if chain.is_empty() {
+                continue;
+            }
+
+            let clusters = chain.len() as u32;
+            self.report(Problem::LostChain { first: Cluster::from(first), clusters }, self.repair);
+            if self.repair {
+                for num in chain {
+                    self.vfat.set_fat_entry(Cluster::from(num), 0)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Compares the free cluster count of the FSInfo sector, if it has one,
+    /// with the FAT.
+    fn check_free_count(&mut self) -> io::Result<()> {
+        let recorded = match self.vfat.free_clusters() {
+            Some(recorded) => recorded,
+            None => return Ok(()),
+        };
+        let mut actual = 0;
+        for num in 2..=self.vfat.max_cluster() {
+            if self.vfat.fat_status(Cluster::from(num))? == Status::Free {
+                actual += 1;
+            }
+        }
+
+        if recorded != actual {
+            self.report(Problem::FreeCountMismatch { recorded, actual }, self.repair);
+            if self.repair {
+                self.vfat.set_free_clusters(actual)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether the LFN entries `lfns`, given in the order they are stored on
+/// disk, form a complete long file name for an entry with 8.3 name checksum
+/// `checksum`.
+fn lfns_match(lfns: &[VFatDirEntry], checksum: u8) -> bool {
+    let count = lfns.len();
+    lfns.iter().enumerate().all(|(i, entry)| match entry.lfn() {
+        Some((sequence_num, sum)) => {
+            sum == checksum
+                && (sequence_num & 0x1F) as usize == count - i
+                && (sequence_num & 0x40 != 0) == (i == 0)
+        }
+        None => false,
+    })
+}
+
+/// Formats an 11-byte 8.3 name as `NAME.EXT`.
+fn display_name(short_name: &[u8; 11]) -> String {
+    let trim = |part: &[u8]| -> String {
+        let len = part.iter().rposition(|&c| c != b' ').map_or(0, |i| i + 1);
+        part[..len].iter().map(|&c| c as char).collect()
+    };
+    let (base, ext) = (trim(&short_name[..8]), trim(&short_name[8..]));
+    if ext.is_empty() {
+        base
+    } else {
+        format!("{}.{}", base, ext)
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::FatCopyMismatch { copy, sectors } => {
+                write!(f, "FAT copy {} differs from the first copy in {} sectors", copy, sectors)
+            }
+            Problem::BadChain { path, cluster } => {
+                write!(f, "{}: chain is broken at cluster {}", path, cluster.num())
+            }
+            Problem::CrossLinked { path, other, cluster } => {
+                write!(f, "{}: cluster {} is also used by {}", path, cluster.num(), other)
+            }
+            Problem::LostChain { first, clusters } => {
+                write!(f, "lost chain of {} clusters starting at cluster {}", clusters, first.num())
+            }
+            Problem::SizeMismatch { path, size, clusters } => {
+                write!(f, "{}: size of {} bytes does not match its {} clusters", path, size, clusters)
+            }
+            Problem::BadLfn { dir, slot } => {
+                write!(f, "{}: broken long file name at slot {}", dir, slot)
+            }
+            Problem::BadDotEntry { dir, name } => {
+                write!(f, "{}: `{}` entry is missing or wrong", dir, name)
+            }
+            Problem::FreeCountMismatch { recorded, actual } => {
+                write!(f, "FSInfo records {} free clusters but {} are free", recorded, actual)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.repaired {
+            write!(f, "{} (repaired)", self.problem)
+        } else {
+            write!(f, "{}", self.problem)
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use core::char::decode_utf16;
+use core::mem::size_of;
 use core::ops::BitAnd;
 
 use alloc::string::String;
@@ -13,9 +14,9 @@ use shim::path::PathBuf;
 use crate::traits;
 use crate::traits::Dir as DirTrait;
 use crate::traits::Entry as EntryTrait;
-use crate::util::VecExt;
-use crate::vfat::{Attributes, Date, Metadata, Time};
-use crate::vfat::{Cluster, Entry, File, VFatHandle};
+use crate::util::{SliceExt, VecExt};
+use crate::vfat::{Attributes, Date, Metadata, Time, Timestamp};
+use crate::vfat::{Cluster, Entry, File, VFat, VFatHandle};
 
 #[derive(Debug, Clone)]
 pub struct Dir<HANDLE: VFatHandle> {
@@ -23,8 +24,38 @@ pub struct Dir<HANDLE: VFatHandle> {
     pub first_cluster: Cluster, // first cluster
     pub name: String,
     pub metadata: Option<Metadata>,
+    pub slots: Option<EntrySlots>, // None for the root directory
 }
 
+/// The location of an entry in its parent directory: the index of its first
+/// slot (its first LFN entry, if it has any) and of its regular entry.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct EntrySlots {
+    pub dir: Cluster,
+    pub first: usize,
+    pub regular: usize,
+}
+
+/// Attribute bits of a regular directory entry.
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_ARCHIVE: u8 = 0x20;
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_LFN: u8 = 0x0F;
+
+/// The 8.3 names of the `.` and `..` entries.
+pub(crate) const DOT_NAME: [u8; 11] = *b".          ";
+pub(crate) const DOT_DOT_NAME: [u8; 11] = *b"..         ";
+
+/// `file_id` markers of a directory entry.
+const ID_END: u8 = 0x00;
+const ID_DELETED: u8 = 0xE5;
+
+/// The number of UTF-16 code units held by one LFN entry.
+const LFN_CHARS: usize = 13;
+
+/// The maximum length of a long file name, in UTF-16 code units.
+const LFN_MAX_LEN: usize = 255;
+
 #[repr(C, packed)]
 #[derive(Copy, Clone)]
 pub struct VFatRegularDirEntry {
@@ -80,6 +111,7 @@ pub struct VFatUnknownDirEntry {
 
 const_assert_size!(VFatUnknownDirEntry, 32);
 
+#[derive(Copy, Clone)]
 pub union VFatDirEntry {
     unknown: VFatUnknownDirEntry,
     regular: VFatRegularDirEntry,
@@ -167,10 +199,759 @@ impl<HANDLE: VFatHandle> Dir<HANDLE> {
     }
 }
 
+impl<HANDLE: VFatHandle> Dir<HANDLE> {
+    /// Creates an empty file named `name` in `self` and returns it.
+    ///
+    /// The entry gets a long file name unless `name` is already a valid
+    /// upper-case 8.3 name.
+    ///
+    /// # Errors
+    ///
+    /// If an entry named `name` already exists, an error of `AlreadyExists` is
+    /// returned.
+    ///
+    /// If `name` is not a valid file name, an error of `InvalidInput` is
+    /// returned.
+    pub fn create_file<P: AsRef<OsStr>>(&self, name: P) -> io::Result<File<HANDLE>> {
+        let name = self.check_new_name(name.as_ref())?;
+        let mut entry = VFatRegularDirEntry::new(*b"           ", Attributes(ATTR_ARCHIVE));
+        let slots = self.vfat.lock(|vfat| {
+            if let Some(now) = vfat.now() {
+                entry.set_times(Some(now), Some(now), Some(now));
+            }
+            vfat.insert_entry(self.first_cluster, name, entry)
+        })?;
+
+        let metadata = entry.metadata();
+        Ok(File::new(self.vfat.clone(), Cluster::from(0), metadata, String::from(name), Some(slots)))
+    }
+
+    /// Removes the file named `name` from `self` and frees its clusters.
+    ///
+    /// # Errors
+    ///
+    /// If no entry with name `name` exists in `self`, an error of `NotFound` is
+    /// returned.
+    ///
+    /// If the entry is a directory, an error of `Other` is returned.
+    pub fn remove<P: AsRef<OsStr>>(&self, name: P) -> io::Result<()> {
+        let file = self
+            .find(name)?
+            .into_file()
+            .ok_or(io::Error::new(io::ErrorKind::Other, "entry is a directory"))?;
+        let slots = file.slots.expect("files are always in a directory");
+
+        self.vfat.lock(|vfat| {
+            vfat.remove_entry(slots)?;
+            vfat.remove_open_files(slots);
+            if file.first_cluster.num() != 0 {
+                vfat.free_chain(file.first_cluster)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Creates an empty directory named `name` in `self` and returns it. The
+    /// new directory holds only its `.` and `..` entries.
+    ///
+    /// New files and directories are stamped with the file system's clock, if
+    /// it has one.
+    ///
+    /// # Errors
+    ///
+    /// If an entry named `name` already exists, an error of `AlreadyExists` is
+    /// returned.
+    ///
+    /// If `name` is not a valid file name, an error of `InvalidInput` is
+    /// returned.
+    pub fn create_dir<P: AsRef<OsStr>>(&self, name: P) -> io::Result<Dir<HANDLE>> {
+        let name = self.check_new_name(name.as_ref())?;
+        let (entry, slots) = self.vfat.lock(|vfat| -> io::Result<(VFatRegularDirEntry, EntrySlots)> {
+            let first_cluster = vfat.alloc_cluster(None)?;
+            let parent = vfat.dot_dot_cluster(self.first_cluster);
+            let now = vfat.now();
+
+            let mut entries = vec![VFatDirEntry::free(); vfat.cluster_size() / size_of::<VFatDirEntry>()];
+            entries[0] = VFatDirEntry::dot(first_cluster);
+            entries[1] = VFatDirEntry::dot_dot(parent);
+            if now.is_some() {
+                for entry in &mut entries[..2] {
+                    entry.regular_mut().expect("dot entries are regular").set_times(now, now, now);
+                }
+            }
+            vfat.write_dir_entries(first_cluster, &entries)?;
+
+            let mut entry = VFatRegularDirEntry::new(*b"           ", Attributes(ATTR_DIRECTORY));
+            entry.set_first_cluster(first_cluster);
+            entry.set_times(now, now, now);
+            let slots = vfat.insert_entry(self.first_cluster, name, entry)?;
+            Ok((entry, slots))
+        })?;
+
+        Ok(Dir {
+            vfat: self.vfat.clone(),
+            first_cluster: entry.first_cluster(),
+            name: String::from(name),
+            metadata: Some(entry.metadata()),
+            slots: Some(slots),
+        })
+    }
+
+    /// Removes the empty directory named `name` from `self` and frees its
+    /// clusters.
+    ///
+    /// # Errors
+    ///
+    /// If no entry with name `name` exists in `self`, an error of `NotFound` is
+    /// returned.
+    ///
+    /// If the entry is not a directory, is `.` or `..`, or is not empty, an
+    /// error of `Other` is returned.
+    pub fn remove_dir<P: AsRef<OsStr>>(&self, name: P) -> io::Result<()> {
+        if is_dot_name(name.as_ref()) {
+            return Err(io::Error::new(io::ErrorKind::Other, "cannot remove `.` or `..`"));
+        }
+        let dir = self
+            .find(name)?
+            .into_dir()
+            .ok_or(io::Error::new(io::ErrorKind::Other, "entry is not a directory"))?;
+        if dir.entries()?.any(|entry| entry.name() != "." && entry.name() != "..") {
+            return Err(io::Error::new(io::ErrorKind::Other, "directory is not empty"));
+        }
+        let slots = dir.slots.ok_or(io::Error::new(io::ErrorKind::Other, "cannot remove the root directory"))?;
+
+        self.vfat.lock(|vfat| {
+            vfat.remove_entry(slots)?;
+            vfat.free_chain(dir.first_cluster)
+        })
+    }
+
+    /// Renames the entry named `from` in `self` to `to` in directory `to_dir`,
+    /// which may be `self`. The entry keeps its clusters, attributes and
+    /// timestamps; a moved directory's `..` entry is updated to point to
+    /// `to_dir`.
+    ///
+    /// # Errors
+    ///
+    /// If no entry with name `from` exists in `self`, an error of `NotFound`
+    /// is returned.
+    ///
+    /// If an entry named `to` already exists in `to_dir`, an error of
+    /// `AlreadyExists` is returned. If `from` is `.` or `..`, `to` is not a
+    /// valid file name, or a directory would be moved into itself, an error of
+    /// `InvalidInput` is returned.
+    pub fn rename<P, Q>(&self, from: P, to_dir: &Dir<HANDLE>, to: Q) -> io::Result<()>
+    where
+        P: AsRef<OsStr>,
+        Q: AsRef<OsStr>,
+    {
+        // `..` of a subdirectory of the root is the root itself, which is
+        // called `/` and has no entry to rename
+        let from = from.as_ref();
+        if is_dot_name(from) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "cannot rename `.` or `..`"));
+        }
+        let entry = self.find(from)?;
+        let (slots, moved_dir) = match &entry {
+            Entry::FileEntry(file) => (file.slots, None),
+            Entry::DirEntry(dir) => (dir.slots, Some(dir.first_cluster)),
+        };
+        let slots = slots.ok_or(io::Error::new(io::ErrorKind::InvalidInput, "entry has no directory entry"))?;
+
+        // Renaming an entry to a name that only differs in case is fine
+        let to = to.as_ref();
+        let to_name = match to_dir.check_new_name(to) {
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                let existing = to_dir.find(to)?;
+                let existing_slots = match &existing {
+                    Entry::FileEntry(file) => file.slots,
+                    Entry::DirEntry(dir) => dir.slots,
+                };
+                if existing_slots != Some(slots) {
+                    return Err(e);
+                }
+                to.to_str().expect("name was checked")
+            }
+            result => result?,
+        };
+
+        if let Some(moved_dir) = moved_dir {
+            to_dir.check_not_inside(moved_dir)?;
+        }
+
+        self.vfat.lock(|vfat| {
+            let regular = vfat.regular_entry(slots)?;
+            let new_slots = vfat.insert_entry(to_dir.first_cluster, to_name, regular)?;
+            vfat.remove_entry(slots)?;
+            vfat.move_open_files(slots, new_slots);
+            if let Some(moved_dir) = moved_dir {
+                if to_dir.first_cluster != self.first_cluster {
+                    let parent = vfat.dot_dot_cluster(to_dir.first_cluster);
+                    vfat.set_dot_dot(moved_dir, parent)?;
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Sets the directory's timestamps that are `Some`. FAT32 only records
+    /// the date of `accessed`, and times to two seconds.
+    ///
+    /// # Errors
+    ///
+    /// If `self` is the root directory, which has no directory entry, an error
+    /// of `InvalidInput` is returned. If the directory was removed, an error
+    /// of `NotFound` is returned.
+    pub fn set_times(
+        &mut self,
+        created: Option<Timestamp>,
+        accessed: Option<Timestamp>,
+        modified: Option<Timestamp>,
+    ) -> io::Result<()> {
+        self.modify_entry(|regular| regular.set_times(created, accessed, modified))
+    }
+
+    /// Sets the directory's read-only, hidden, system and archive attributes
+    /// to those in `attributes`.
+    ///
+    /// # Errors
+    ///
+    /// If `attributes` holds any other attribute, or `self` is the root
+    /// directory, an error of `InvalidInput` is returned. If the directory was
+    /// removed, an error of `NotFound` is returned.
+    pub fn set_attributes(&mut self, attributes: Attributes) -> io::Result<()> {
+        check_settable(attributes)?;
+        self.modify_entry(|regular| regular.set_attributes(attributes))
+    }
+
+    /// Applies `f` to the directory's entry in its parent and refreshes
+    /// `metadata` from it.
+    fn modify_entry(&mut self, f: impl FnOnce(&mut VFatRegularDirEntry)) -> io::Result<()> {
+        let slots = self.slots.ok_or(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the root directory has no directory entry",
+        ))?;
+        let regular = self.vfat.lock(|vfat| vfat.modify_entry(slots, self.first_cluster, f))?;
+        self.metadata = Some(regular.metadata());
+        Ok(())
+    }
+
+    /// Returns an error of `InvalidInput` if `self` is the directory starting
+    /// at cluster `dir` or one of its descendants.
+    fn check_not_inside(&self, dir: Cluster) -> io::Result<()> {
+        let root = self.vfat.lock(|vfat| vfat.root_cluster());
+        let mut curr = self.clone();
+        loop {
+            if curr.first_cluster == dir {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "cannot move a directory into itself",
+                ));
+            }
+            if curr.first_cluster == root {
+                return Ok(());
+            }
+            curr = curr
+                .find("..")?
+                .into_dir()
+                .ok_or(io::Error::new(io::ErrorKind::InvalidData, "`..` is not a directory"))?;
+        }
+    }
+
+    /// Checks that `name` is a valid name for a new entry of `self` and returns
+    /// it as a `str`.
+    fn check_new_name<'a>(&self, name: &'a OsStr) -> io::Result<&'a str> {
+        let name = name
+            .to_str()
+            .filter(|name| is_valid_name(name))
+            .ok_or(io::Error::new(io::ErrorKind::InvalidInput, "invalid file name"))?;
+        match self.find(name) {
+            Ok(_) => Err(io::Error::new(io::ErrorKind::AlreadyExists, "entry already exists")),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(name),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl<HANDLE: VFatHandle> VFat<HANDLE> {
+    /// Reads all of the slots of the directory starting at cluster `dir`.
+    pub(crate) fn read_dir_entries(&mut self, dir: Cluster) -> io::Result<Vec<VFatDirEntry>> {
+        let mut data: Vec<u8> = Vec::new();
+        self.read_chain(dir, &mut data)?;
+        Ok(unsafe { data.cast::<VFatDirEntry>() })
+    }
+
+    /// Overwrites the slots of the directory starting at cluster `dir` with
+    /// `entries`, growing its chain if needed.
+    pub(crate) fn write_dir_entries(&mut self, dir: Cluster, entries: &[VFatDirEntry]) -> io::Result<()> {
+        let data: &[u8] = unsafe { entries.cast() };
+        let dir = self.resize_chain(dir, data.len())?;
+        self.write_chain(dir, data)?;
+        Ok(())
+    }
+
+    /// Adds a copy of the regular entry `entry` named `name` to the directory
+    /// starting at cluster `dir`, preceded by LFN entries if `name` is not a
+    /// valid 8.3 name. Returns the slots of the new entry.
+    pub(crate) fn insert_entry(
+        &mut self,
+        dir: Cluster,
+        name: &str,
+        mut entry: VFatRegularDirEntry,
+    ) -> io::Result<EntrySlots> {
+        let mut entries = self.read_dir_entries(dir)?;
+
+        let taken: Vec<[u8; 11]> = entries
+            .iter()
+            .filter(|entry| entry.is_regular())
+            .map(|entry| unsafe { entry.regular.short_name() })
+            .collect();
+        let (short_name, lfn) = match short_name(name) {
+            Some(short_name) if !taken.contains(&short_name) => (short_name, false),
+            _ => (generate_short_name(name, &taken), true),
+        };
+
+        let mut new_entries = Vec::new();
+        if lfn {
+            new_entries.extend(lfn_entries(name, lfn_checksum(&short_name)));
+        }
+        entry.set_short_name(short_name);
+        new_entries.push(VFatDirEntry { regular: entry });
+
+        let first = match free_run(&entries, new_entries.len()) {
+            Some(first) => first,
+            None => {
+                // Append to the free slots at the end, growing the directory
+                // by as many clusters as needed
+                let first = entries.len() - entries.iter().rev().take_while(|e| e.is_free()).count();
+                let per_cluster = self.cluster_size() / size_of::<VFatDirEntry>();
+                let len = (first + new_entries.len()).div_ceil(per_cluster) * per_cluster;
+                entries.resize(len, VFatDirEntry::free());
+                first
+            }
+        };
+        entries[first..first + new_entries.len()].copy_from_slice(&new_entries);
+        self.write_dir_entries(dir, &entries)?;
+
+        Ok(EntrySlots {
+            dir,
+            first,
+            regular: first + new_entries.len() - 1,
+        })
+    }
+
+    /// Marks all of the slots of the entry at `slots` as deleted.
+    pub(crate) fn remove_entry(&mut self, slots: EntrySlots) -> io::Result<()> {
+        let mut entries = self.read_dir_entries(slots.dir)?;
+        for entry in &mut entries[slots.first..=slots.regular] {
+            entry.delete();
+        }
+        self.write_dir_entries(slots.dir, &entries)
+    }
+
+    /// Returns the cluster that the `..` entry of a subdirectory of the
+    /// directory starting at `parent` refers to: 0 for the root directory.
+    pub(crate) fn dot_dot_cluster(&self, parent: Cluster) -> Cluster {
+        if parent == self.root_cluster() {
+            Cluster::from(0)
+        } else {
+            parent
+        }
+    }
+
+    /// Points the `..` entry of the directory starting at cluster `dir` to
+    /// cluster `parent`.
+    pub(crate) fn set_dot_dot(&mut self, dir: Cluster, parent: Cluster) -> io::Result<()> {
+        let mut entries = self.read_dir_entries(dir)?;
+        let dot_dot = entries
+            .iter_mut()
+            .filter(|entry| entry.is_regular())
+            .map(|entry| unsafe { &mut entry.regular })
+            .find(|regular| regular.short_name() == DOT_DOT_NAME)
+            .ok_or(io::Error::new(io::ErrorKind::InvalidData, "directory has no `..` entry"))?;
+        dot_dot.set_first_cluster(parent);
+        self.write_dir_entries(dir, &entries)
+    }
+
+    /// Returns a copy of the regular entry at `slots`.
+    pub(crate) fn regular_entry(&mut self, slots: EntrySlots) -> io::Result<VFatRegularDirEntry> {
+        let entries = self.read_dir_entries(slots.dir)?;
+        entries
+            .get(slots.regular)
+            .filter(|entry| entry.is_regular())
+            .map(|entry| unsafe { entry.regular })
+            .ok_or(io::Error::new(io::ErrorKind::NotFound, "entry was removed"))
+    }
+
+    /// Applies `f` to the regular entry at `slots`, which must still start at
+    /// cluster `first_cluster`, and returns the updated entry.
+    ///
+    /// # Errors
+    ///
+    /// If the entry was removed or moved, an error of `NotFound` is returned.
+    pub(crate) fn modify_entry(
+        &mut self,
+        slots: EntrySlots,
+        first_cluster: Cluster,
+        f: impl FnOnce(&mut VFatRegularDirEntry),
+    ) -> io::Result<VFatRegularDirEntry> {
+        let mut entries = self.read_dir_entries(slots.dir)?;
+        let regular = entries
+            .get_mut(slots.regular)
+            .and_then(|entry| entry.regular_mut())
+            .filter(|regular| regular.first_cluster() == first_cluster)
+            .ok_or(io::Error::new(io::ErrorKind::NotFound, "entry was removed"))?;
+
+        f(regular);
+        let regular = *regular;
+        self.write_dir_entries(slots.dir, &entries)?;
+        Ok(regular)
+    }
+}
+
+impl VFatDirEntry {
+    /// Returns an unused slot.
+    fn free() -> VFatDirEntry {
+        VFatDirEntry {
+            unknown: VFatUnknownDirEntry {
+                file_id: ID_END,
+                _reserved: [0; 10],
+                reg_or_lfn: 0,
+                _reserved2: [0; 20],
+            },
+        }
+    }
+
+    /// Returns the `.` entry of the directory starting at cluster `dir`.
+    pub(crate) fn dot(dir: Cluster) -> VFatDirEntry {
+        let mut dot = VFatRegularDirEntry::new(DOT_NAME, Attributes(ATTR_DIRECTORY));
+        dot.set_first_cluster(dir);
+        VFatDirEntry { regular: dot }
+    }
+
+    /// Returns the `..` entry of a directory whose parent starts at cluster
+    /// `parent`, 0 for the root directory.
+    pub(crate) fn dot_dot(parent: Cluster) -> VFatDirEntry {
+        let mut dot_dot = VFatRegularDirEntry::new(DOT_DOT_NAME, Attributes(ATTR_DIRECTORY));
+        dot_dot.set_first_cluster(parent);
+        VFatDirEntry { regular: dot_dot }
+    }
+
+    /// Whether the slot is unused, either deleted or past the end of the
+    /// directory.
+    pub(crate) fn is_free(&self) -> bool {
+        let file_id = unsafe { self.unknown.file_id };
+        file_id == ID_END || file_id == ID_DELETED
+    }
+
+    /// Whether the slot holds a regular entry.
+    pub(crate) fn is_regular(&self) -> bool {
+        !self.is_free() && unsafe { self.unknown.reg_or_lfn } & ATTR_LFN != ATTR_LFN
+    }
+
+    /// Whether the slot marks the end of the directory.
+    pub(crate) fn is_end(&self) -> bool {
+        let file_id = unsafe { self.unknown.file_id };
+        file_id == ID_END
+    }
+
+    /// Returns the sequence number and the checksum of the slot's LFN entry,
+    /// if it holds one.
+    pub(crate) fn lfn(&self) -> Option<(u8, u8)> {
+        if self.is_free() || self.is_regular() {
+            return None;
+        }
+        let lfn = unsafe { self.long_filename };
+        Some((lfn.sequence_num, lfn.checksum))
+    }
+
+    /// Returns the slot's regular entry, if it holds one.
+    pub(crate) fn regular_mut(&mut self) -> Option<&mut VFatRegularDirEntry> {
+        if self.is_regular() {
+            Some(unsafe { &mut self.regular })
+        } else {
+            None
+        }
+    }
+
+    /// Marks the slot as deleted.
+    pub(crate) fn delete(&mut self) {
+        self.unknown.file_id = ID_DELETED;
+    }
+}
+
+impl VFatRegularDirEntry {
+    fn new(short_name: [u8; 11], attributes: Attributes) -> VFatRegularDirEntry {
+        let mut entry = VFatRegularDirEntry {
+            file_name: [0; 8],
+            file_extension: [0; 3],
+            file_attributes: attributes,
+            reserved_win: 0,
+            creation_secs_tenths: 0,
+            creation_time: Time::default(),
+            creation_date: Date::default(),
+            accessed_date: Date::default(),
+            high_cluster_num: 0,
+            modification_time: Time::default(),
+            modification_date: Date::default(),
+            low_cluster_num: 0,
+            file_size: 0,
+        };
+        entry.set_short_name(short_name);
+        entry
+    }
+
+    fn set_short_name(&mut self, short_name: [u8; 11]) {
+        self.file_name.copy_from_slice(&short_name[..8]);
+        self.file_extension.copy_from_slice(&short_name[8..]);
+    }
+
+    /// The name and extension fields as one 11-byte 8.3 name.
+    pub(crate) fn short_name(&self) -> [u8; 11] {
+        let mut name = [0; 11];
+        name[..8].copy_from_slice(&self.file_name);
+        name[8..].copy_from_slice(&self.file_extension);
+        name
+    }
+
+    pub(crate) fn first_cluster(&self) -> Cluster {
+        Cluster::from(self.low_cluster_num as u32 | ((self.high_cluster_num as u32) << 16))
+    }
+
+    pub(crate) fn set_first_cluster(&mut self, cluster: Cluster) {
+        self.high_cluster_num = (cluster.num() >> 16) as u16;
+        self.low_cluster_num = cluster.num() as u16;
+    }
+
+    pub(crate) fn size(&self) -> u32 {
+        self.file_size
+    }
+
+    pub(crate) fn set_size(&mut self, size: u32) {
+        self.file_size = size;
+    }
+
+    pub(crate) fn is_dir(&self) -> bool {
+        self.file_attributes.0 & ATTR_DIRECTORY != 0
+    }
+
+    pub(crate) fn is_volume_label(&self) -> bool {
+        self.file_attributes.0 & ATTR_VOLUME_ID != 0 && !self.is_dir()
+    }
+
+    /// The entry's attributes, timestamps and size.
+    pub(crate) fn metadata(&self) -> Metadata {
+        Metadata {
+            attributes: self.file_attributes,
+            created_time: self.creation_time,
+            created_date: self.creation_date,
+            accessed_date: self.accessed_date,
+            modified_time: self.modification_time,
+            modified_date: self.modification_date,
+            size: self.file_size,
+        }
+    }
+
+    /// Sets the timestamps that are `Some`. Only the date of `accessed` is
+    /// recorded.
+    pub(crate) fn set_times(
+        &mut self,
+        created: Option<Timestamp>,
+        accessed: Option<Timestamp>,
+        modified: Option<Timestamp>,
+    ) {
+        if let Some(created) = created {
+            self.creation_secs_tenths = 0;
+            self.creation_time = created.time;
+            self.creation_date = created.date;
+        }
+        if let Some(accessed) = accessed {
+            self.accessed_date = accessed.date;
+        }
+        if let Some(modified) = modified {
+            self.modification_time = modified.time;
+            self.modification_date = modified.date;
+        }
+    }
+
+    /// Sets the archive attribute, which tells backup tools that the entry
+    /// changed.
+    pub(crate) fn mark_for_archiving(&mut self) {
+        self.file_attributes.0 |= ATTR_ARCHIVE;
+    }
+
+    /// Replaces the entry's settable attributes with `attributes`, keeping the
+    /// bits that say what kind of entry it is.
+    pub(crate) fn set_attributes(&mut self, attributes: Attributes) {
+        let kind = self.file_attributes.0 & !Attributes::SETTABLE;
+        self.file_attributes = Attributes(kind | attributes.0);
+    }
+}
+
+/// Checks that `attributes` only holds bits that `set_attributes` can change.
+///
+/// # Errors
+///
+/// Returns an error of `InvalidInput` if any other bit is set.
+pub(crate) fn check_settable(attributes: Attributes) -> io::Result<()> {
+    if attributes.0 & !Attributes::SETTABLE != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "only the read-only, hidden, system and archive attributes can be set",
+        ));
+    }
+    Ok(())
+}
+
+/// Returns the index of the first run of `len` free slots in `entries`.
+fn free_run(entries: &[VFatDirEntry], len: usize) -> Option<usize> {
+    let mut run = 0;
+    for (i, entry) in entries.iter().enumerate() {
+        run = if entry.is_free() { run + 1 } else { 0 };
+        if run == len {
+            return Some(i + 1 - len);
+        }
+    }
+    None
+}
+
+/// Whether `name` is `.` or `..`.
+fn is_dot_name(name: &OsStr) -> bool {
+    matches!(name.to_str(), Some(".") | Some(".."))
+}
+
+/// Whether `name` can be the name of a new directory entry.
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name != "."
+        && name != ".."
+        && name.encode_utf16().count() <= LFN_MAX_LEN
+        && !name.ends_with([' ', '.'])
+        && !name.chars().any(|c| c < ' ' || "\"*/:<>?\\|".contains(c))
+}
+
+/// Whether `c` may appear in an 8.3 name.
+fn is_short_name_char(c: u8) -> bool {
+    c.is_ascii_uppercase() || c.is_ascii_digit() || b"$%'-_@~`!(){}^#&".contains(&c)
+}
+
+/// Returns `name` as an 11-byte 8.3 name if it is a valid upper-case 8.3 name.
+fn short_name(name: &str) -> Option<[u8; 11]> {
+    let (base, ext) = match name.rfind('.') {
+        Some(i) => (&name[..i], &name[i + 1..]),
+        None => (name, ""),
+    };
+    let valid = |part: &str, max: usize| {
+        part.len() <= max && part.bytes().all(is_short_name_char)
+    };
+    if base.is_empty() || !valid(base, 8) || !valid(ext, 3) {
+        return None;
+    }
+
+    let mut short_name = [b' '; 11];
+    short_name[..base.len()].copy_from_slice(base.as_bytes());
+    short_name[8..8 + ext.len()].copy_from_slice(ext.as_bytes());
+    Some(short_name)
+}
+
+/// Generates an 8.3 alias of the form `BASE~N.EXT` for the long name `name`
+/// that is not in `taken`.
+fn generate_short_name(name: &str, taken: &[[u8; 11]]) -> [u8; 11] {
+    let convert = |part: &str, max: usize| -> Vec<u8> {
+        part.chars()
+            .filter(|&c| c != ' ' && c != '.')
+            .map(|c| c.to_ascii_uppercase())
+            .map(|c| if c.is_ascii() && is_short_name_char(c as u8) { c as u8 } else { b'_' })
+            .take(max)
+            .collect()
+    };
+    let (base, ext) = match name.rfind('.') {
+        Some(i) if i > 0 => (convert(&name[..i], 8), convert(&name[i + 1..], 3)),
+        _ => (convert(name, 8), Vec::new()),
+    };
+
+    let mut short_name = [b' '; 11];
+    short_name[8..8 + ext.len()].copy_from_slice(&ext);
+    for n in 1u32.. {
+        let tail = format!("~{}", n);
+        let base_len = base.len().min(8 - tail.len());
+        short_name[..8].copy_from_slice(b"        ");
+        short_name[..base_len].copy_from_slice(&base[..base_len]);
+        short_name[base_len..base_len + tail.len()].copy_from_slice(tail.as_bytes());
+        if !taken.contains(&short_name) {
+            break;
+        }
+    }
+    short_name
+}
+
+/// The checksum of an 8.3 name stored in each of its LFN entries.
+pub(crate) fn lfn_checksum(short_name: &[u8; 11]) -> u8 {
+    short_name
+        .iter()
+        .fold(0u8, |sum, &c| ((sum & 1) << 7).wrapping_add(sum >> 1).wrapping_add(c))
+}
+
+/// Decodes the long file name held by the LFN entries `lfns`, given in the
+/// order they are stored on disk. Returns `None` if it is not valid UTF-16.
+pub(crate) fn lfn_name(lfns: &[VFatDirEntry]) -> Option<String> {
+    let mut chars: Vec<u16> = Vec::new();
+    for entry in lfns.iter().rev() {
+        let lfn = unsafe { entry.long_filename };
+        let (first, second, third) = (lfn.first_name_chars, lfn.second_name_chars, lfn.third_name_chars);
+        chars.extend_from_slice(&first);
+        chars.extend_from_slice(&second);
+        chars.extend_from_slice(&third);
+    }
+    let len = chars.iter().position(|&c| c == 0x0000 || c == 0xFFFF).unwrap_or(chars.len());
+    decode_utf16(chars[..len].iter().copied()).collect::<Result<String, _>>().ok()
+}
+
+/// Returns the LFN entries holding `name`, in the order they are stored on
+/// disk (last part of the name first).
+fn lfn_entries(name: &str, checksum: u8) -> Vec<VFatDirEntry> {
+    let mut chars: Vec<u16> = name.encode_utf16().collect();
+    if !chars.len().is_multiple_of(LFN_CHARS) {
+        chars.push(0x0000);
+    }
+    while !chars.len().is_multiple_of(LFN_CHARS) {
+        chars.push(0xFFFF);
+    }
+
+    let count = chars.len() / LFN_CHARS;
+    chars
+        .chunks(LFN_CHARS)
+        .enumerate()
+        .rev()
+        .map(|(i, part)| {
+            let last = if i + 1 == count { 0x40 } else { 0 };
+            let mut first_name_chars = [0; 5];
+            let mut second_name_chars = [0; 6];
+            let mut third_name_chars = [0; 2];
+            first_name_chars.copy_from_slice(&part[..5]);
+            second_name_chars.copy_from_slice(&part[5..11]);
+            third_name_chars.copy_from_slice(&part[11..]);
+            VFatDirEntry {
+                long_filename: VFatLfnDirEntry {
+                    sequence_num: (i + 1) as u8 | last,
+                    first_name_chars,
+                    file_attributes: Attributes(ATTR_LFN),
+                    file_type: 0,
+                    checksum,
+                    second_name_chars,
+                    zeroes: 0,
+                    third_name_chars,
+                },
+            }
+        })
+        .collect()
+}
+
 pub struct DirIterator<HANDLE: VFatHandle> {
     directory_data: Vec<VFatDirEntry>,
     index: usize,
     vfat: HANDLE,
+    dir: Cluster,
 }
 
 // this is really something
@@ -185,18 +966,24 @@ impl<HANDLE: VFatHandle> Iterator for DirIterator<HANDLE> {
         let mut lfn_data: HashMap<u8, Vec<u16>> = HashMap::new();
         let regular_entry: VFatRegularDirEntry;
         let mut counter: i32 = 0;
+        let mut first_slot = None;
         loop {
+            if self.index >= self.directory_data.len() {
+                return None;
+            }
             let unknown_entry: VFatUnknownDirEntry =
                 unsafe { self.directory_data[self.index].unknown };
 
-            if unknown_entry.file_id == 0xE5 {
+            if unknown_entry.file_id == ID_DELETED {
                 self.index += 1;
                 continue;
-            } else if unknown_entry.reg_or_lfn.bitand(0x0F) == 0x0F {
+            }
+            first_slot.get_or_insert(self.index);
+            if unknown_entry.reg_or_lfn.bitand(0x0F) == 0x0F {
                 let mut local_data: Vec<u16> = Vec::new();
                 let lfn_entry: VFatLfnDirEntry =
                     unsafe { self.directory_data[self.index].long_filename };
-                let idx = lfn_entry.sequence_num & 0xF;
+                let idx = lfn_entry.sequence_num & 0x1F;
                 let temp_copy1 = lfn_entry.first_name_chars;
                 let temp_copy2 = lfn_entry.second_name_chars;
                 let temp_copy3 = lfn_entry.third_name_chars;
@@ -232,7 +1019,7 @@ impl<HANDLE: VFatHandle> Iterator for DirIterator<HANDLE> {
                         }
                     }
                 }
-            } else if unknown_entry.file_id == 0x00 {
+            } else if unknown_entry.file_id == ID_END {
                 return None;
             } else {
                 // When parsing a directory entry’s name, you must manually add a . to the non-LFN based directory entries to demarcate the file’s extension.
@@ -266,47 +1053,35 @@ impl<HANDLE: VFatHandle> Iterator for DirIterator<HANDLE> {
 
         // process regular entry
         // get metadata
-        let metadata = Metadata {
-            attributes: regular_entry.file_attributes,
-            created_time: regular_entry.creation_time,
-            created_date: regular_entry.creation_date,
-            accessed_date: regular_entry.accessed_date,
-            modified_time: regular_entry.modification_time,
-            modified_date: regular_entry.modification_date,
-            size: regular_entry.file_size
-        };
+        let metadata = regular_entry.metadata();
 
         // get first_cluster
         let first_cluster: u32 =
             regular_entry.low_cluster_num as u32 | ((regular_entry.high_cluster_num as u32) << 16);
+        let slots = Some(EntrySlots {
+            dir: self.dir,
+            first: first_slot.expect("entry has a slot"),
+            regular: self.index - 1,
+        });
 
-        if regular_entry.file_attributes.0.bitand(0x10) == 0x10 {
+        if regular_entry.file_attributes.0.bitand(ATTR_DIRECTORY) == ATTR_DIRECTORY {
             // directory
             return Some(Entry::DirEntry(Dir {
                 first_cluster: first_cluster.into(),
                 vfat: self.vfat.clone(),
                 metadata: Some(metadata),
                 name,
+                slots,
             }));
         } else {
             // file
-            let mut data: Vec<u8> = Vec::new();
-            if first_cluster != 0 {
-                // Volume Label
-                let br = self.vfat
-                    .lock(|s| s.read_chain(first_cluster.into(), &mut data))
-                    .ok()?;
-                let j = regular_entry.file_size;
-                assert!(br >= j as usize, "br = {}, regular_entry.file_size = {}", br, j);
-            }
-            return Some(Entry::FileEntry(File {
-                first_cluster: first_cluster.into(),
-                vfat: self.vfat.clone(),
+            return Some(Entry::FileEntry(File::new(
+                self.vfat.clone(),
+                first_cluster.into(),
                 metadata,
                 name,
-                data: data[..(regular_entry.file_size as usize)].to_vec(),
-                offset: 0,
-            }));
+                slots,
+            )));
         }
     }
 }
@@ -317,14 +1092,15 @@ impl<HANDLE: VFatHandle> traits::Dir for Dir<HANDLE> {
     /// . You will likely need to use at-most one line of unsafe when implementing entries();
     /// you may find the VecExt and SliceExt trait implementations we have provided particularly useful here.
     fn entries(&self) -> io::Result<Self::Iter> {
-        let mut data: Vec<u8> = Vec::new();
         // TODO: why are we reading an entire chain into memory? we should only do this on demand.
-        self.vfat
-            .lock(|s| s.read_chain(self.first_cluster, &mut data))?; //
+        let directory_data = self
+            .vfat
+            .lock(|s| s.read_dir_entries(self.first_cluster))?;
         Ok(DirIterator {
-            directory_data: unsafe { data.cast::<VFatDirEntry>() },
+            directory_data,
             index: 0,
             vfat: self.vfat.clone(),
+            dir: self.first_cluster,
         })
     }
 }
@@ -1,6 +1,7 @@
 use shim::const_assert_size;
 
 use crate::traits::BlockDevice;
+use crate::vfat::format::Layout;
 use crate::vfat::Error;
 
 #[repr(C, packed)]
@@ -19,12 +20,12 @@ pub struct BiosParameterBlock {
     sectors_per_track: u16,
     num_heads: u16,
     num_hidden_sectors : u32, // LBA of the beginning of the partition
-    num_logical_sectors : u32,
+    pub num_logical_sectors : u32,
     pub sectors_per_fat : u32,
     flags : u16,
     version : u16,
     pub root_dir_cluster : u32,
-    fsinfo_sector : u16,
+    pub fsinfo_sector : u16,
     backup_boot_sector : u16,
     _reserved : [u8; 12],
     drive_num : u8,
@@ -79,9 +80,40 @@ impl Default for BiosParameterBlock {
 const_assert_size!(BiosParameterBlock, 512);
 const VALID_SIGNATURE: [u8; 2] = [0x55, 0xAA];
 
+/// The media descriptor of fixed disks.
+const MEDIA_FIXED: u8 = 0xF8;
+
 use alloc::slice;
 
 impl BiosParameterBlock {
+    /// Returns the EBPB of a FAT32 volume laid out as `layout`.
+    pub(crate) fn new(layout: &Layout) -> BiosParameterBlock {
+        BiosParameterBlock {
+            jmp_instruction: [0xEB, 0x58, 0x90],
+            oem_identifier: u64::from_le_bytes(*b"JELLYOS "),
+            bytes_per_sector: layout.bytes_per_sector,
+            sectors_per_cluster: layout.sectors_per_cluster,
+            num_reserved_sectors: layout.reserved_sectors,
+            num_fats: layout.num_fats,
+            fat_id: MEDIA_FIXED,
+            sectors_per_track: 32,
+            num_heads: 64,
+            num_hidden_sectors: layout.start,
+            num_logical_sectors: layout.num_sectors,
+            sectors_per_fat: layout.sectors_per_fat,
+            root_dir_cluster: layout.root_cluster,
+            fsinfo_sector: layout.fsinfo_sector,
+            backup_boot_sector: layout.backup_boot_sector,
+            drive_num: 0x80,
+            signature: 0x29,
+            volume_id: layout.volume_id,
+            volume_label: *b"JELLYOS    ",
+            sys_identifier: u64::from_le_bytes(*b"FAT32   "),
+            boot_signature: VALID_SIGNATURE,
+            ..BiosParameterBlock::default()
+        }
+    }
+
     /// Reads the FAT32 extended BIOS parameter block from sector `sector` of
     /// device `device`.
     ///
@@ -1,19 +1,64 @@
 use alloc::string::String;
+use alloc::sync::{Arc, Weak};
 use alloc::vec::Vec;
+use core::ops::Range;
 
 use shim::io::{self, SeekFrom};
 
 use crate::traits;
-use crate::vfat::{Cluster, Metadata, VFatHandle};
+use crate::vfat::dir::{check_settable, VFatRegularDirEntry};
+use crate::vfat::{Attributes, Cluster, EntrySlots, Metadata, Timestamp, VFat, VFatHandle};
+
+/// The most clusters a sequential `read()` loads from the disk ahead of the
+/// data it returns.
+const MAX_READ_AHEAD: usize = 32;
 
 #[derive(Debug)]
 pub struct File<HANDLE: VFatHandle> {
     pub vfat: HANDLE,           // file system handle
-    pub first_cluster: Cluster, // first cluster
-    pub metadata: Metadata,
+    pub first_cluster: Cluster, // first cluster, as of the last operation on the file
+    pub metadata: Metadata,     // as of the last operation on the file
     pub name : String,
-    pub data : Vec<u8>,
     pub offset : usize,
+    pub slots: Option<EntrySlots>, // location of the entry in its directory
+    pub read_ahead: usize,      // clusters loaded by the next read that reaches past `data`
+    open: Option<Arc<()>>,      // keeps the file's entry in the open file table alive
+}
+
+/// The state of a file that all of its handles share: the location of its
+/// directory entry, its size and the data loaded from the disk. It lives in
+/// the file system's open file table while a handle has been used to access
+/// the file, so that every handle sees the others' writes and keeps working
+/// after the file is renamed.
+#[derive(Debug)]
+pub(crate) struct FileState {
+    slots: Option<EntrySlots>,
+    first_cluster: Cluster,
+    metadata: Metadata,
+    data: Vec<u8>,                   // the start of the file, loaded from the disk as it is read
+    next_cluster: Option<Cluster>,   // first cluster not in `data`, if any
+    changed: bool,                   // written to since the last `store()`
+    dirty: Option<Range<usize>>,     // bytes of `data` not stored since the last `store()`
+    resized: bool,                   // size changed since the last `store()`
+    removed: bool,                   // the entry was removed and the chain freed
+}
+
+/// An entry of the open file table. It is dropped once no handle holds a
+/// clone of the `Arc` that `token` points to.
+#[derive(Debug)]
+pub(crate) struct OpenFile {
+    token: Weak<()>,
+    state: FileState,
+}
+
+impl OpenFile {
+    fn is(&self, token: &Arc<()>) -> bool {
+        Weak::as_ptr(&self.token) == Arc::as_ptr(token)
+    }
+
+    fn is_open(&self) -> bool {
+        self.token.strong_count() > 0
+    }
 }
 
 impl<HANDLE: VFatHandle> Clone for File<HANDLE> {
@@ -23,31 +68,423 @@ impl<HANDLE: VFatHandle> Clone for File<HANDLE> {
             first_cluster: self.first_cluster,
             metadata: self.metadata.clone(),
             name: self.name.clone(),
-            data: self.data.clone(),
             offset: self.offset,
+            slots: self.slots,
+            read_ahead: self.read_ahead,
+            open: self.open.clone(),
         }
     }
 }
 
 impl<HANDLE: VFatHandle> io::Read for File<HANDLE> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        let bytes_to_read = (self.data[self.offset..].len() as usize).min(buf.len());
-        (buf[..bytes_to_read]).copy_from_slice(&self.data[self.offset..self.offset+bytes_to_read]);
-        self.offset += bytes_to_read;
-        Ok(bytes_to_read)
+        let offset = self.offset;
+        let bytes_read = self.with_state(|vfat, state, read_ahead| {
+            let bytes_to_read = state.len().saturating_sub(offset).min(buf.len());
+            state.load(vfat, offset + bytes_to_read, read_ahead)?;
+            buf[..bytes_to_read].copy_from_slice(&state.data[offset..offset + bytes_to_read]);
+            Ok(bytes_to_read)
+        })?;
+        self.offset += bytes_read;
+        Ok(bytes_read)
+    }
+}
+
+impl<HANDLE: VFatHandle> File<HANDLE> {
+    /// Returns the file `name` that starts at cluster `first_cluster` and is
+    /// described by the directory entry at `slots`. Its data is read from the
+    /// disk as it is needed.
+    pub fn new(
+        vfat: HANDLE,
+        first_cluster: Cluster,
+        metadata: Metadata,
+        name: String,
+        slots: Option<EntrySlots>,
+    ) -> File<HANDLE> {
+        File {
+            vfat,
+            first_cluster,
+            metadata,
+            name,
+            offset: 0,
+            slots,
+            read_ahead: 1,
+            open: None,
+        }
+    }
+
+    /// Runs `f` on the state the file shares with its other handles, adding
+    /// the file to the open file table first if this handle was not used yet.
+    /// `first_cluster`, `metadata` and `slots` are refreshed from the state
+    /// afterwards.
+    ///
+    /// # Errors
+    ///
+    /// If the file was removed, an error of `NotFound` is returned and `f`
+    /// is not run.
+    fn with_state<R>(
+        &mut self,
+        f: impl FnOnce(&mut VFat<HANDLE>, &mut FileState, &mut usize) -> io::Result<R>,
+    ) -> io::Result<R> {
+        let File { vfat, first_cluster, metadata, slots, read_ahead, open, .. } = self;
+        vfat.lock(|vfat| {
+            let token = match open {
+                Some(token) => token.clone(),
+                None => open.insert(vfat.open_file(*slots, *first_cluster, metadata)?).clone(),
+            };
+            let index = vfat
+                .open_files
+                .iter()
+                .position(|open| open.is(&token))
+                .expect("an open file stays in the table");
+            // The chain of a removed file was freed and may be reused
+            if vfat.open_files[index].state.removed {
+                return Err(io::Error::new(io::ErrorKind::NotFound, "entry was removed"));
+            }
+            // `f` needs the file system too, so take the entry out meanwhile
+            let mut open_file = vfat.open_files.swap_remove(index);
+            let state = &mut open_file.state;
+            let result = f(vfat, state, read_ahead);
+            *first_cluster = state.first_cluster;
+            *metadata = state.metadata.clone();
+            *slots = state.slots;
+            vfat.open_files.push(open_file);
+            result
+        })
+    }
+
+    /// Returns the file's metadata as its handles see it: the size includes
+    /// writes that were not stored yet.
+    pub fn current_metadata(&self) -> Metadata {
+        self.vfat.lock(|vfat| {
+            vfat.open_files
+                .iter()
+                .find(|open| match &self.open {
+                    Some(token) => open.is(token),
+                    None => open.is_open() && !open.state.removed && open.state.slots == self.slots,
+                })
+                .map(|open| open.state.metadata.clone())
+                .unwrap_or_else(|| self.metadata.clone())
+        })
+    }
+
+    /// Returns the size of the file in bytes.
+    fn len(&self) -> usize {
+        self.current_metadata().size as usize
+    }
+
+    /// Truncates or extends the file to `size` bytes. Extended bytes are
+    /// zeroed. The position is moved to the new end if it is past it.
+    ///
+    /// Like writes, the new size reaches the disk on the next `sync()`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `InvalidInput` error if `size` is larger than the maximum
+    /// FAT32 file size, and an error of kind `Other` if the volume does not
+    /// have enough free clusters for it or there is not enough memory to hold
+    /// the file.
+    pub fn set_len(&mut self, size: u64) -> io::Result<()> {
+        self.with_state(|vfat, state, read_ahead| state.set_len(vfat, size, read_ahead))?;
+        self.offset = self.offset.min(size as usize);
+        Ok(())
+    }
+
+    /// Resizes the file's chain to its size if it changed, writes the data
+    /// written since it was last stored and records its first cluster and size
+    /// in its directory entry. If the file was written to since it was last
+    /// stored, its modification time is set from the file system's clock and
+    /// it is marked for archiving.
+    ///
+    /// Only the clusters holding changed data are written, so storing after
+    /// every write does not rewrite the whole file. Writes made through other
+    /// handles of the file are stored as well.
+    ///
+    /// The changes are made in the file system's sector cache; they reach the
+    /// disk when the file system is synced.
+    ///
+    /// # Errors
+    ///
+    /// If the file was removed, an error of `NotFound` is returned and
+    /// nothing is written.
+    pub fn store(&mut self) -> io::Result<()> {
+        self.with_state(|vfat, state, _| state.store(vfat))
+    }
+
+    /// Sets the file's timestamps that are `Some`. FAT32 only records the
+    /// date of `accessed`, and times to two seconds.
+    ///
+    /// The times replace the ones that the next `store()` would set for
+    /// earlier writes.
+    ///
+    /// # Errors
+    ///
+    /// If the file was removed, an error of `NotFound` is returned.
+    pub fn set_times(
+        &mut self,
+        created: Option<Timestamp>,
+        accessed: Option<Timestamp>,
+        modified: Option<Timestamp>,
+    ) -> io::Result<()> {
+        self.with_state(|vfat, state, _| {
+            state.modify_entry(vfat, |regular| regular.set_times(created, accessed, modified))?;
+            state.changed = false;
+            Ok(())
+        })
+    }
+
+    /// Sets the file's read-only, hidden, system and archive attributes to
+    /// those in `attributes`.
+    ///
+    /// # Errors
+    ///
+    /// If `attributes` holds any other attribute, an error of `InvalidInput`
+    /// is returned. If the file was removed, an error of `NotFound` is
+    /// returned.
+    pub fn set_attributes(&mut self, attributes: Attributes) -> io::Result<()> {
+        check_settable(attributes)?;
+        self.with_state(|vfat, state, _| state.modify_entry(vfat, |regular| regular.set_attributes(attributes)))
+    }
+}
+
+impl FileState {
+    /// Returns the size of the file in bytes.
+    fn len(&self) -> usize {
+        self.metadata.size as usize
+    }
+
+    /// Loads the file's data from the disk until at least its first `end`
+    /// bytes are in memory.
+    ///
+    /// Every load reads at least `read_ahead` clusters, which doubles (up to
+    /// `MAX_READ_AHEAD`) each time, so sequential reads need few, large disk
+    /// requests.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `InvalidData` error if the cluster chain is shorter than the
+    /// file.
+    fn load<HANDLE: VFatHandle>(
+        &mut self,
+        vfat: &mut VFat<HANDLE>,
+        end: usize,
+        read_ahead: &mut usize,
+    ) -> io::Result<()> {
+        let end = end.min(self.len());
+        while self.data.len() < end {
+            let cluster = self.next_cluster.ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "cluster chain is shorter than the file")
+            })?;
+            let needed = (end - self.data.len()).div_ceil(vfat.cluster_size());
+            self.next_cluster = vfat.read_clusters(cluster, needed.max(*read_ahead), &mut self.data)?;
+            *read_ahead = (*read_ahead * 2).min(MAX_READ_AHEAD);
+        }
+
+        if self.data.len() >= self.len() {
+            self.data.truncate(self.len());
+            self.next_cluster = None;
+        }
+        Ok(())
+    }
+
+    /// Records that the bytes in `range` must be written by the next
+    /// `store()`.
+    fn mark_dirty(&mut self, range: Range<usize>) {
+        if range.is_empty() {
+            return;
+        }
+        self.dirty = Some(match self.dirty.take() {
+            Some(dirty) => dirty.start.min(range.start)..dirty.end.max(range.end),
+            None => range,
+        });
+    }
+
+    /// Truncates or extends the file to `size` bytes, like `File::set_len()`.
+    fn set_len<HANDLE: VFatHandle>(
+        &mut self,
+        vfat: &mut VFat<HANDLE>,
+        size: u64,
+        read_ahead: &mut usize,
+    ) -> io::Result<()> {
+        if size > u32::MAX as u64 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "file too large"));
+        }
+        let (old_len, size) = (self.len(), size as usize);
+        if size > old_len {
+            let cluster_size = vfat.cluster_size();
+            let needed = size.div_ceil(cluster_size) - old_len.div_ceil(cluster_size);
+            let free = vfat.free_clusters().unwrap_or(vfat.max_cluster() - 1);
+            if needed > free as usize {
+                return Err(io::Error::new(io::ErrorKind::Other, "no free clusters left"));
+            }
+        }
+
+        // Only a file that grows needs all of its old data in memory
+        self.load(vfat, old_len.min(size), read_ahead)?;
+        if size > self.data.len() {
+            self.data
+                .try_reserve(size - self.data.len())
+                .map_err(|_| io::Error::new(io::ErrorKind::Other, "out of memory"))?;
+        }
+        self.data.resize(size, 0);
+        self.next_cluster = None;
+        self.metadata.size = size as u32;
+        self.dirty = self.dirty.take().map(|dirty| dirty.start.min(size)..dirty.end.min(size));
+        self.mark_dirty(old_len..size);
+        self.changed = true;
+        self.resized = true;
+        Ok(())
+    }
+
+    /// Stores the file, like `File::store()`.
+    fn store<HANDLE: VFatHandle>(&mut self, vfat: &mut VFat<HANDLE>) -> io::Result<()> {
+        let (changed, size) = (self.changed, self.len());
+        let first_cluster = match self.resized {
+            true => vfat.resize_chain(self.first_cluster, size)?,
+            false => self.first_cluster,
+        };
+        let dirty = self.dirty.clone().unwrap_or(0..0);
+        vfat.write_chain_at(first_cluster, dirty.start, &self.data[dirty])?;
+        if let Some(slots) = self.slots {
+            let now = vfat.now().filter(|_| changed);
+            let regular = vfat.modify_entry(slots, self.first_cluster, |regular| {
+                regular.set_first_cluster(first_cluster);
+                regular.set_size(size as u32);
+                if changed {
+                    regular.set_times(None, now, now);
+                    regular.mark_for_archiving();
+                }
+            })?;
+            self.metadata = regular.metadata();
+        }
+        self.first_cluster = first_cluster;
+        self.changed = false;
+        self.dirty = None;
+        self.resized = false;
+        Ok(())
+    }
+
+    /// Applies `f` to the file's directory entry and refreshes the timestamps
+    /// and attributes in `metadata` from it. The size in `metadata` is kept:
+    /// it may not have been stored yet.
+    fn modify_entry<HANDLE: VFatHandle>(
+        &mut self,
+        vfat: &mut VFat<HANDLE>,
+        f: impl FnOnce(&mut VFatRegularDirEntry),
+    ) -> io::Result<()> {
+        let slots = self.slots.ok_or(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "file has no directory entry",
+        ))?;
+        let regular = vfat.modify_entry(slots, self.first_cluster, f)?;
+        self.metadata = Metadata {
+            size: self.metadata.size,
+            ..regular.metadata()
+        };
+        Ok(())
+    }
+}
+
+impl<HANDLE: VFatHandle> VFat<HANDLE> {
+    /// Returns the token of the open file whose entry is at `slots`, adding
+    /// the file to the open file table if no handle has it open. A file
+    /// without an entry is always added anew, starting at `first_cluster`
+    /// and described by `metadata`.
+    ///
+    /// # Errors
+    ///
+    /// If the entry at `slots` was removed or now belongs to a file that does
+    /// not start at `first_cluster`, an error of `NotFound` is returned.
+    fn open_file(
+        &mut self,
+        slots: Option<EntrySlots>,
+        first_cluster: Cluster,
+        metadata: &Metadata,
+    ) -> io::Result<Arc<()>> {
+        self.open_files.retain(OpenFile::is_open);
+        let shared = self
+            .open_files
+            .iter()
+            .find(|open| slots.is_some() && !open.state.removed && open.state.slots == slots)
+            .and_then(|open| open.token.upgrade());
+        if let Some(token) = shared {
+            return Ok(token);
+        }
+
+        // Another handle may have stored the file since `first_cluster` and
+        // `metadata` were read; an empty file gets its first cluster then
+        let (first_cluster, metadata) = match slots {
+            Some(slots) => {
+                let regular = self.regular_entry(slots)?;
+                if first_cluster.num() != 0 && regular.first_cluster() != first_cluster {
+                    return Err(io::Error::new(io::ErrorKind::NotFound, "entry was removed"));
+                }
+                (regular.first_cluster(), regular.metadata())
+            }
+            None => (first_cluster, metadata.clone()),
+        };
+        let next_cluster = Some(first_cluster).filter(|c| c.num() != 0 && metadata.size != 0);
+        let token = Arc::new(());
+        self.open_files.push(OpenFile {
+            token: Arc::downgrade(&token),
+            state: FileState {
+                slots,
+                first_cluster,
+                metadata,
+                data: Vec::new(),
+                next_cluster,
+                changed: false,
+                dirty: None,
+                resized: false,
+                removed: false,
+            },
+        });
+        Ok(token)
+    }
+
+    /// Points the open files whose entry was at `from` to the entry at `to`.
+    pub(crate) fn move_open_files(&mut self, from: EntrySlots, to: EntrySlots) {
+        let moved = self
+            .open_files
+            .iter_mut()
+            .filter(|open| !open.state.removed && open.state.slots == Some(from));
+        for open in moved {
+            open.state.slots = Some(to);
+        }
+    }
+
+    /// Marks the open files whose entry at `slots` was removed, so that their
+    /// handles no longer read or write the freed chain.
+    pub(crate) fn remove_open_files(&mut self, slots: EntrySlots) {
+        for open in self.open_files.iter_mut().filter(|open| open.state.slots == Some(slots)) {
+            open.state.removed = true;
+        }
     }
 }
 
 impl<HANDLE: VFatHandle> io::Write for File<HANDLE> {
+    /// Writes `buf` at the current position, extending the file if the write
+    /// goes past its end. The data reaches the disk on the next `sync()`.
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        let bytes_to_write = (self.data[self.offset..].len() as usize).min(buf.len());
-        self.data[self.offset..self.offset+bytes_to_write].copy_from_slice(&buf);
-        self.offset += bytes_to_write;
-        Ok(bytes_to_write)
+        let offset = self.offset;
+        let end = offset + buf.len();
+        self.with_state(|vfat, state, read_ahead| {
+            // Another handle may have truncated the file below `offset`
+            if end > state.len() {
+                state.set_len(vfat, end as u64, read_ahead)?;
+            }
+            state.load(vfat, end, read_ahead)?;
+            state.data[offset..end].copy_from_slice(buf);
+            state.mark_dirty(offset..end);
+            state.changed = true;
+            Ok(())
+        })?;
+        self.offset = end;
+        Ok(buf.len())
     }
 
     fn flush(&mut self) -> io::Result<()> {
-        Ok(())
+        traits::File::sync(self)
     }
 }
 
@@ -57,6 +494,10 @@ impl<HANDLE: VFatHandle> io::Seek for File<HANDLE> {
     /// A seek to the end of the file is allowed. A seek _beyond_ the end of the
     /// file returns an `InvalidInput` error.
     ///
+    /// Seeking never touches the disk: the next read continues loading the
+    /// file from the first cluster that is not in memory yet, so the chain is
+    /// not walked again from its start.
+    ///
     /// If the seek operation completes successfully, this method returns the
     /// new position from the start of the stream. That position can be used
     /// later with SeekFrom::Start.
@@ -65,32 +506,19 @@ impl<HANDLE: VFatHandle> io::Seek for File<HANDLE> {
     ///
     /// Seeking before the start of a file or beyond the end of the file results
     /// in an `InvalidInput` error.
-    fn seek(&mut self, _pos: SeekFrom) -> io::Result<u64> {
-        match _pos {
-            SeekFrom::Start(new) => {
-                if new < self.data.len() as u64 {
-                    self.offset = new as usize;
-                    Ok(self.offset as u64)
-                } else {
-                    Err(io::Error::new(io::ErrorKind::InvalidInput, "SeekFrom::Start overflowed"))
-                }
-            },
-            SeekFrom::End(sub) => {
-                if self.data.len() > sub.abs() as usize {
-                    self.offset = self.data.len() + (sub.abs() as usize);
-                    Ok(self.offset as u64)
-                } else {
-                    Err(io::Error::new(io::ErrorKind::InvalidInput, "SeekFrom::End overflowed"))
-                }
-            },
-            SeekFrom::Current(add_curr) => {
-                if add_curr.is_negative() && self.offset < add_curr.abs() as usize {
-                    Err(io::Error::new(io::ErrorKind::InvalidInput, "SeekFrom::Current overflowed"))
-                } else {
-                    self.offset = ((self.offset as i64)+add_curr) as usize;
-                    Ok(self.offset as u64)
-                }
-            },
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let len = self.len() as u64;
+        let new = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(delta) => len.checked_add_signed(delta),
+            SeekFrom::Current(delta) => (self.offset as u64).checked_add_signed(delta),
+        };
+        match new.filter(|&new| new <= len) {
+            Some(new) => {
+                self.offset = new as usize;
+                Ok(new)
+            }
+            None => Err(io::Error::new(io::ErrorKind::InvalidInput, "seek out of bounds")),
         }
     }
 }
@@ -98,14 +526,15 @@ impl<HANDLE: VFatHandle> io::Seek for File<HANDLE> {
 
 
 impl<HANDLE: VFatHandle> traits::File for File<HANDLE> {
+    /// Stores the file like `store()` and then writes every modified sector of
+    /// the file system back to the disk.
     fn sync(&mut self) -> io::Result<()> {
-        self.vfat.lock(|f| {
-            f.write_chain(self.first_cluster, &self.data)
-        }).map(|_| ())
+        self.store()?;
+        self.vfat.lock(|f| f.sync())
     }
 
     fn size(&self) -> u64 {
-        self.metadata.size.into()
+        self.len() as u64
     }
 }
 
@@ -113,10 +542,25 @@ impl<HANDLE: VFatHandle> traits::File for File<HANDLE> {
 use alloc::fmt;
 impl<HANDLE: VFatHandle> fmt::Display for File<HANDLE> {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        // let mut buffer = Vec::new();
-        // self.read_to_end(&mut buffer);
-        // write!(f, "{}", buffer)?;
-        match core::str::from_utf8(&self.data) {
+        let data = self.vfat.lock(|vfat| -> io::Result<Vec<u8>> {
+            let state = self.open.as_ref().and_then(|token| {
+                vfat.open_files.iter().find(|open| open.is(token)).map(|open| &open.state)
+            });
+            let (mut data, next_cluster, len) = match state {
+                Some(state) => (state.data.clone(), state.next_cluster, state.len()),
+                None => {
+                    let len = self.metadata.size as usize;
+                    (Vec::new(), Some(self.first_cluster).filter(|c| c.num() != 0 && len != 0), len)
+                }
+            };
+            if let Some(cluster) = next_cluster {
+                vfat.read_clusters(cluster, usize::MAX, &mut data)?;
+                data.truncate(len);
+            }
+            Ok(data)
+        })
+        .map_err(|_| fmt::Error)?;
+        match core::str::from_utf8(&data) {
             Ok(s) => write!(f, "{}", s),
             Err(_) => write!(f, "<invalid UTF-8 data>"),
         }
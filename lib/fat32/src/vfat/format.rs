@@ -0,0 +1,161 @@
+use alloc::vec;
+use core::mem::size_of;
+use core::slice;
+
+use shim::io;
+
+use crate::mbr::{MasterBootRecord, PartitionEntry};
+use crate::traits::BlockDevice;
+use crate::vfat::fsinfo::FsInfo;
+use crate::vfat::BiosParameterBlock;
+
+/// The MBR partition type of FAT32 partitions addressed with LBA.
+const PARTITION_TYPE: u8 = 0x0C;
+const RESERVED_SECTORS: u16 = 32;
+const NUM_FATS: u8 = 2;
+const FSINFO_SECTOR: u16 = 1;
+const BACKUP_BOOT_SECTOR: u16 = 6;
+const ROOT_CLUSTER: u32 = 2;
+const VOLUME_ID: u32 = 0x4A45_4C59;
+
+/// Partitions start on a 1 MiB boundary, unless that would waste more than a
+/// sixty-fourth of the device.
+const ALIGNMENT: u64 = 1 << 20;
+
+/// The geometry of a FAT32 volume written by `format`.
+#[derive(Debug)]
+pub(crate) struct Layout {
+    pub bytes_per_sector: u16,
+    pub sectors_per_cluster: u8,
+    pub reserved_sectors: u16,
+    pub num_fats: u8,
+    pub start: u32,       // first sector of the partition on the device
+    pub num_sectors: u32, // number of sectors in the partition
+    pub sectors_per_fat: u32,
+    pub root_cluster: u32,
+    pub fsinfo_sector: u16,
+    pub backup_boot_sector: u16,
+    pub volume_id: u32,
+    clusters: u32, // number of data clusters
+}
+
+/// Returns the cluster size recommended for a FAT32 volume of `size` bytes.
+fn cluster_size(size: u64) -> u64 {
+    match size {
+        size if size <= 260 << 20 => 512,
+        size if size <= 8 << 30 => 4 << 10,
+        size if size <= 16 << 30 => 8 << 10,
+        size if size <= 32 << 30 => 16 << 10,
+        _ => 32 << 10,
+    }
+}
+
+impl Layout {
+    /// Returns the layout of a FAT32 volume filling a device of `num_sectors`
+    /// sectors of `sector_size` bytes.
+    fn new(sector_size: u64, num_sectors: u64) -> io::Result<Layout> {
+        if !sector_size.is_power_of_two() || !(512..=4096).contains(&sector_size) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "unsupported sector size"));
+        }
+
+        let alignment = ALIGNMENT / sector_size;
+        let start = if num_sectors >= 64 * alignment { alignment } else { 1 };
+        let sectors = num_sectors.saturating_sub(start);
+        if sectors > u32::MAX as u64 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "device is too large for an MBR"));
+        }
+
+        let spc = (cluster_size(sectors * sector_size) / sector_size).max(1);
+        let data = sectors.saturating_sub(RESERVED_SECTORS as u64);
+        let sectors_per_fat = ((data / spc + 2) * 4).div_ceil(sector_size);
+        let clusters = data.saturating_sub(NUM_FATS as u64 * sectors_per_fat) / spc;
+        if clusters < 2 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "device is too small"));
+        }
+
+        Ok(Layout {
+            bytes_per_sector: sector_size as u16,
+            sectors_per_cluster: spc as u8,
+            reserved_sectors: RESERVED_SECTORS,
+            num_fats: NUM_FATS,
+            start: start as u32,
+            num_sectors: sectors as u32,
+            sectors_per_fat: sectors_per_fat as u32,
+            root_cluster: ROOT_CLUSTER,
+            fsinfo_sector: FSINFO_SECTOR,
+            backup_boot_sector: BACKUP_BOOT_SECTOR,
+            volume_id: VOLUME_ID,
+            clusters: clusters as u32,
+        })
+    }
+}
+
+/// Writes the 512-byte on-disk structure `value` to sector `n` of `device`,
+/// zeroing the rest of the sector.
+fn write_struct<D: BlockDevice, T>(device: &mut D, n: u64, value: &T) -> io::Result<()> {
+    let bytes = unsafe { slice::from_raw_parts(value as *const T as *const u8, size_of::<T>()) };
+    let mut buf = vec![0u8; device.sector_size() as usize];
+    buf[..bytes.len()].copy_from_slice(bytes);
+    device.write_sector(n, &buf)?;
+    Ok(())
+}
+
+/// Formats the first `num_sectors` sectors of `device` as an MBR disk with a
+/// single FAT32 partition holding an empty root directory.
+///
+/// The EBPB and FSInfo sector are written along with their backups and the
+/// volume is marked as cleanly unmounted, so the result can be opened with
+/// `VFat::from` right away. Every sector the file system relies on is
+/// written, so `device` need not be zeroed beforehand.
+///
+/// # Errors
+///
+/// Returns an error of kind `InvalidInput` if the device's sector size is not
+/// supported, or if `num_sectors` is too small to hold a FAT32 volume or too
+/// large to be described by an MBR. Errors from `device` are propagated.
+pub fn format<T: BlockDevice>(mut device: T, num_sectors: u64) -> io::Result<()> {
+    let layout = Layout::new(device.sector_size(), num_sectors)?;
+    let start = layout.start as u64;
+    let fat_start = start + layout.reserved_sectors as u64;
+    let data_start = fat_start + layout.num_fats as u64 * layout.sectors_per_fat as u64;
+    let root_start =
+        data_start + (layout.root_cluster - 2) as u64 * layout.sectors_per_cluster as u64;
+
+    // Reserved sectors, every FAT and the root directory's cluster
+    let zeroes = vec![0u8; device.sector_size() as usize];
+    for n in start..data_start {
+        device.write_sector(n, &zeroes)?;
+    }
+    for n in root_start..root_start + layout.sectors_per_cluster as u64 {
+        device.write_sector(n, &zeroes)?;
+    }
+
+    let partition = PartitionEntry::new(PARTITION_TYPE, layout.start, layout.num_sectors);
+    let mbr = MasterBootRecord::new([
+        partition,
+        PartitionEntry::default(),
+        PartitionEntry::default(),
+        PartitionEntry::default(),
+    ]);
+    write_struct(&mut device, 0, &mbr)?;
+
+    // EBPB and FSInfo, and their backups: every cluster but the root
+    // directory's is free
+    let bpb = BiosParameterBlock::new(&layout);
+    let fsinfo = FsInfo::new(layout.clusters - 1, layout.root_cluster + 1);
+    for boot in [start, start + layout.backup_boot_sector as u64] {
+        write_struct(&mut device, boot, &bpb)?;
+        write_struct(&mut device, boot + layout.fsinfo_sector as u64, &fsinfo)?;
+    }
+
+    // Every FAT: the two reserved entries, the second one with the clean
+    // shutdown bit set, and the root directory's chain
+    let mut fat = zeroes;
+    for (i, entry) in [0x0FFF_FFF8u32, 0x0FFF_FFFF, 0x0FFF_FFFF].iter().enumerate() {
+        fat[i * 4..i * 4 + 4].copy_from_slice(&entry.to_le_bytes());
+    }
+    for copy in 0..layout.num_fats as u64 {
+        device.write_sector(fat_start + copy * layout.sectors_per_fat as u64, &fat)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,83 @@
+use core::mem::size_of;
+use core::slice;
+
+use shim::const_assert_size;
+
+use crate::traits::BlockDevice;
+use crate::vfat::Error;
+
+const LEAD_SIGNATURE: u32 = 0x4161_5252;
+const STRUCT_SIGNATURE: u32 = 0x6141_7272;
+const TRAIL_SIGNATURE: u32 = 0xAA55_0000;
+
+/// The value of the `free_count` and `next_free` hints when they are unknown.
+pub const UNKNOWN: u32 = 0xFFFF_FFFF;
+
+/// The byte offset of the `free_count` field in the FSInfo sector.
+pub const FREE_COUNT_OFFSET: usize = 488;
+
+/// The byte offset of the `next_free` field in the FSInfo sector.
+pub const NEXT_FREE_OFFSET: usize = 492;
+
+/// The FAT32 file system information sector (FSInfo).
+#[repr(C, packed)]
+pub struct FsInfo {
+    lead_signature: u32,
+    _reserved: [u8; 480],
+    struct_signature: u32,
+    /// The last known number of free clusters, or `UNKNOWN`.
+    pub free_count: u32,
+    /// The cluster number at which to start looking for a free cluster, or
+    /// `UNKNOWN`.
+    pub next_free: u32,
+    _reserved2: [u8; 12],
+    trail_signature: u32,
+}
+
+const_assert_size!(FsInfo, 512);
+
+impl FsInfo {
+    /// Returns an FSInfo structure holding the `free_count` and `next_free`
+    /// hints.
+    pub fn new(free_count: u32, next_free: u32) -> FsInfo {
+        FsInfo {
+            lead_signature: LEAD_SIGNATURE,
+            _reserved: [0; 480],
+            struct_signature: STRUCT_SIGNATURE,
+            free_count,
+            next_free,
+            _reserved2: [0; 12],
+            trail_signature: TRAIL_SIGNATURE,
+        }
+    }
+
+    /// Reads the FSInfo structure from sector `sector` of device `device`.
+    ///
+    /// # Errors
+    ///
+    /// If any of the three FSInfo signatures is invalid, returns an error of
+    /// `BadSignature`.
+    pub fn from<T: BlockDevice>(mut device: T, sector: u64) -> Result<FsInfo, Error> {
+        let mut fsinfo = FsInfo {
+            lead_signature: 0,
+            _reserved: [0; 480],
+            struct_signature: 0,
+            free_count: 0,
+            next_free: 0,
+            _reserved2: [0; 12],
+            trail_signature: 0,
+        };
+        let fsinfo_slice: &mut [u8] = unsafe {
+            slice::from_raw_parts_mut(&mut fsinfo as *mut FsInfo as *mut u8, size_of::<FsInfo>())
+        };
+        device.read_sector(sector, fsinfo_slice)?;
+
+        let (lead, structure, trail) =
+            (fsinfo.lead_signature, fsinfo.struct_signature, fsinfo.trail_signature);
+        if lead != LEAD_SIGNATURE || structure != STRUCT_SIGNATURE || trail != TRAIL_SIGNATURE {
+            return Err(Error::BadSignature);
+        }
+
+        Ok(fsinfo)
+    }
+}
@@ -19,6 +19,53 @@ pub struct Time(u16);
 #[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
 pub struct Attributes(pub u8);
 
+impl Attributes {
+    pub const READ_ONLY: u8 = 0x01;
+    pub const HIDDEN: u8 = 0x02;
+    pub const SYSTEM: u8 = 0x04;
+    pub const VOLUME_ID: u8 = 0x08;
+    pub const DIRECTORY: u8 = 0x10;
+    pub const ARCHIVE: u8 = 0x20;
+
+    /// The attributes that can be changed with `set_attributes`. The others
+    /// describe what kind of entry it is.
+    pub const SETTABLE: u8 = Self::READ_ONLY | Self::HIDDEN | Self::SYSTEM | Self::ARCHIVE;
+}
+
+/// Unix time of the FAT epoch, 1980-01-01 00:00:00.
+const FAT_EPOCH: u64 = 315_532_800;
+
+/// The last year a FAT32 date can represent.
+const MAX_YEAR: u64 = 1980 + 127;
+
+/// Returns the number of days from 1970-01-01 to the given date, which must
+/// not be before 1970.
+fn days_from_civil(year: u64, month: u64, day: u64) -> u64 {
+    // Count from 0000-03-01 so that leap days end their year
+    let (year, month) = if month <= 2 { (year - 1, month + 9) } else { (year, month - 3) };
+    let day_of_year = (153 * month + 2) / 5 + day - 1;
+    let days = year * 365 + year / 4 - year / 100 + year / 400 + day_of_year;
+    days - 719_468
+}
+
+/// Returns the year, month and day of the date `days` days after 1970-01-01.
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let year = era * 400 + year_of_era;
+    if month < 10 {
+        (year, month + 3, day)
+    } else {
+        (year + 1, month - 9, day)
+    }
+}
+
 /// A structure containing a date and time.
 #[derive(Default, Copy, Clone, Debug, PartialEq, Eq)]
 pub struct Timestamp {
@@ -38,6 +85,34 @@ pub struct Metadata {
     pub size : u32
 }
 
+impl Timestamp {
+    /// Returns the timestamp of the Unix time `secs`, in UTC. Times that FAT32
+    /// cannot represent are clamped to 1980-01-01 00:00:00 or
+    /// 2107-12-31 23:59:58, and odd seconds are rounded down.
+    pub fn from_unix(secs: u64) -> Timestamp {
+        let max = days_from_civil(MAX_YEAR, 12, 31) * 86_400 + 86_399;
+        let secs = secs.clamp(FAT_EPOCH, max);
+        let (year, month, day) = civil_from_days(secs / 86_400);
+        let secs_of_day = secs % 86_400;
+        let (hour, minute, second) = (secs_of_day / 3600, secs_of_day / 60 % 60, secs_of_day % 60);
+        Timestamp {
+            date: Date((((year - 1980) << 9) | (month << 5) | day) as u16),
+            time: Time(((hour << 11) | (minute << 5) | (second / 2)) as u16),
+        }
+    }
+
+    /// Returns the Unix time of the timestamp, taken to be in UTC, or 0 if the
+    /// timestamp is unset or invalid.
+    pub fn to_unix(&self) -> u64 {
+        let (month, day) = (self.month() as u64, self.day() as u64);
+        if !(1..=12).contains(&month) || day == 0 {
+            return 0;
+        }
+        let days = days_from_civil(self.year() as u64, month, day);
+        days * 86_400 + self.hour() as u64 * 3600 + self.minute() as u64 * 60 + self.second() as u64
+    }
+}
+
 impl traits::Timestamp for Timestamp {
     fn year(&self) -> usize {
         // Bits 15-9 represent the year offset from 1980
@@ -1,19 +1,24 @@
 pub(crate) mod cache;
+pub(crate) mod check;
 pub(crate) mod cluster;
 pub(crate) mod dir;
 pub(crate) mod ebpb;
 pub(crate) mod entry;
 pub(crate) mod error;
 pub(crate) mod fat;
+pub(crate) mod format;
 pub(crate) mod file;
+pub(crate) mod fsinfo;
 pub(crate) mod metadata;
 pub(crate) mod vfat;
 
-pub use self::dir::Dir;
+pub use self::check::{Finding, Problem};
+pub use self::dir::{Dir, EntrySlots};
 pub use self::ebpb::BiosParameterBlock;
 pub use self::entry::Entry;
 pub use self::error::Error;
 pub use self::file::File;
+pub use self::format::format;
 pub use self::metadata::{Attributes, Date, Metadata, Time, Timestamp};
 pub use self::vfat::{VFat, VFatHandle};
 
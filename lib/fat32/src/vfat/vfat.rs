@@ -5,14 +5,24 @@ use core::mem::size_of;
 use alloc::vec::Vec;
 use alloc::string::String;
 
+use shim::ffi::OsStr;
 use shim::io;
 use shim::path::Path;
 
 use crate::mbr::MasterBootRecord;
 use crate::traits::{BlockDevice, FileSystem};
 use crate::util::SliceExt;
+use crate::vfat::file::OpenFile;
+use crate::vfat::fsinfo::{self, FsInfo};
 use crate::vfat::{BiosParameterBlock, CachedPartition, Partition};
-use crate::vfat::{Cluster, Dir, Entry, Error, FatEntry, File, Status};
+use crate::vfat::{Cluster, Dir, Entry, Error, FatEntry, File, Status, Timestamp};
+
+/// The value written to the FAT entry of the last cluster in a chain.
+pub(crate) const EOC: u32 = 0x0FFF_FFFF;
+
+/// The bit of FAT entry 1 that is set while the volume is not in use, i.e.
+/// every change made to it has been synced.
+const CLEAN_SHUTDOWN: u32 = 0x0800_0000;
 
 /// A generic trait that handles a critical section as a closure
 pub trait VFatHandle: Clone + Debug + Send + Sync {
@@ -26,10 +36,18 @@ pub struct VFat<HANDLE: VFatHandle> {
     device: CachedPartition,
     bytes_per_sector: u16,
     sectors_per_cluster: u8,
-    _sectors_per_fat: u32,
+    sectors_per_fat: u32,
+    num_fats: u8,
     fat_start_sector: u64,
     data_start_sector: u64,
     rootdir_cluster: Cluster,
+    max_cluster: u32,             // highest data cluster number
+    fsinfo_sector: Option<u64>,   // None if the FSInfo sector is missing or invalid
+    free_clusters: Option<u32>,   // FSInfo free cluster count, if known
+    next_free: Option<u32>,       // FSInfo next free cluster hint, if known
+    dirty: bool,                  // the volume is marked as in use on the disk
+    clock: Option<fn() -> Timestamp>, // source of the current time, if any
+    pub(crate) open_files: Vec<OpenFile>, // files with handles that were used
 }
 
 impl<HANDLE: VFatHandle> VFat<HANDLE> {
@@ -46,23 +64,128 @@ impl<HANDLE: VFatHandle> VFat<HANDLE> {
             sector_size: bpb.bytes_per_sector as u64,
         };
 
-        let cp = CachedPartition::new(device, partition);
-
         let dss: u64 =
             (bpb.num_reserved_sectors as u64) + (bpb.num_fats as u32 * bpb.sectors_per_fat) as u64;
-        let vfat: VFat<HANDLE> = VFat {
+        let num_sectors = match bpb.num_logical_sectors {
+            0 => p_entry.total_sectors,
+            n => n,
+        } as u64;
+        let num_data_clusters = (num_sectors.saturating_sub(dss) / bpb.sectors_per_cluster as u64) as u32;
+        let fat_capacity = bpb.sectors_per_fat * (bpb.bytes_per_sector as u32 / size_of::<FatEntry>() as u32);
+        let max_cluster = (num_data_clusters + 1).min(fat_capacity.saturating_sub(1));
+
+        // A missing or corrupt FSInfo sector only costs us the hints
+        let fsinfo_sector = match bpb.fsinfo_sector {
+            0 | 0xFFFF => None,
+            n => Some(n as u64),
+        };
+        let fsinfo = fsinfo_sector
+            .and_then(|n| FsInfo::from(&mut device, p_entry.relative_sector as u64 + n).ok());
+        let (free_clusters, next_free) = match &fsinfo {
+            Some(info) => {
+                let (free, next) = (info.free_count, info.next_free);
+                (
+                    Some(free).filter(|&n| n <= num_data_clusters),
+                    Some(next).filter(|&n| n >= 2 && n <= max_cluster),
+                )
+            }
+            None => (None, None),
+        };
+
+        let cp = CachedPartition::new(device, partition);
+
+        let mut vfat: VFat<HANDLE> = VFat {
             phantom: PhantomData,
             device: cp,
             bytes_per_sector: bpb.bytes_per_sector,
             sectors_per_cluster: bpb.sectors_per_cluster,
-            _sectors_per_fat: bpb.sectors_per_fat,
+            sectors_per_fat: bpb.sectors_per_fat,
+            num_fats: bpb.num_fats,
             fat_start_sector: bpb.num_reserved_sectors as u64,
             data_start_sector: dss,
             rootdir_cluster: Cluster::from(bpb.root_dir_cluster),
+            max_cluster,
+            fsinfo_sector: fsinfo.and(fsinfo_sector),
+            free_clusters,
+            next_free,
+            dirty: false,
+            clock: None,
+            open_files: Vec::new(),
         };
+        vfat.dirty = vfat.fat_value(Cluster::from(1))? & CLEAN_SHUTDOWN == 0;
         Ok(HANDLE::new(vfat))
     }
 
+    /// Returns the size of a cluster in bytes.
+    pub fn cluster_size(&self) -> usize {
+        self.bytes_per_sector as usize * self.sectors_per_cluster as usize
+    }
+
+    /// Returns the first cluster of the root directory.
+    pub fn root_cluster(&self) -> Cluster {
+        self.rootdir_cluster
+    }
+
+    /// Returns the number of free clusters recorded in the FSInfo sector, if
+    /// it is known.
+    pub fn free_clusters(&self) -> Option<u32> {
+        self.free_clusters
+    }
+
+    /// Sets the clock that new entries and writes are stamped with. Without a
+    /// clock, new entries get zeroed timestamps and writes leave them as is.
+    pub fn set_clock(&mut self, clock: fn() -> Timestamp) {
+        self.clock = Some(clock);
+    }
+
+    /// Returns the current time according to the clock, if there is one.
+    pub(crate) fn now(&self) -> Option<Timestamp> {
+        self.clock.map(|clock| clock())
+    }
+
+    /// Returns `true` if the volume is marked as in use: it was modified and
+    /// not synced since, for instance because of an unclean shutdown. Such a
+    /// volume should be checked with `check()`.
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// Writes every modified sector back to the underlying device and then
+    /// marks the volume as clean.
+    ///
+    /// Changes made through the file system are kept in the sector cache and
+    /// only reach the device when they are synced here or evicted.
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.device.sync()?;
+        if self.dirty {
+            self.set_volume_flag(CLEAN_SHUTDOWN)?;
+            self.device.sync()?;
+            self.dirty = false;
+        }
+        Ok(())
+    }
+
+    //
+    //  * A method to mark the volume as in use on the disk before its first
+    //    modification since it was last synced.
+    //
+    fn mark_dirty(&mut self) -> io::Result<()> {
+        if !self.dirty {
+            self.dirty = true;
+            self.set_volume_flag(0)?;
+            self.device.sync()?;
+        }
+        Ok(())
+    }
+
+    //
+    //  * A method to set the clean shutdown bit of FAT entry 1 to `flag`.
+    //
+    fn set_volume_flag(&mut self, flag: u32) -> io::Result<()> {
+        let value = self.fat_value(Cluster::from(1))?;
+        self.write_fat_entry(Cluster::from(1), (value & !CLEAN_SHUTDOWN) | flag)
+    }
+
     // Recommended
     fn cluster_start_sector(&mut self, cluster: Cluster) -> io::Result<u64> {
         let cluster_num: u32 = cluster.into();
@@ -72,114 +195,275 @@ impl<HANDLE: VFatHandle> VFat<HANDLE> {
         Ok(sfc)
     }
 
-    //
-    //  * A method to read from an offset of a cluster into a buffer.
-    //
-    fn read_cluster(
-        &mut self,
-        cluster: Cluster,
-        offset: usize,
-        buf: &mut Vec<u8>,
-    ) -> io::Result<usize> {
-        let curr_sector =
-            self.cluster_start_sector(cluster)? + offset as u64 / self.bytes_per_sector as u64;
-        let mut bytes_read = 0;
-        for i in 0..self.sectors_per_cluster {
-            bytes_read += self
-                .device
-                .read_all_sector(curr_sector+i as u64, buf)?;
-        }
-        Ok(bytes_read)
-    }
     fn write_cluster(&mut self, cluster: Cluster, offset: usize, buf: &[u8]) -> io::Result<usize> {
-        let mut curr_sector =
-            self.cluster_start_sector(cluster)? + offset as u64 / self.bytes_per_sector as u64;
-        let mut curr_offset = offset % (self.bytes_per_sector as usize);
+        self.mark_dirty()?;
+        let sector_size = self.bytes_per_sector as usize;
+        let first_sector = self.cluster_start_sector(cluster)?;
+        let end_sector = first_sector + self.sectors_per_cluster as u64;
+        let mut curr_sector = first_sector + (offset / sector_size) as u64;
+        let mut curr_offset = offset % sector_size;
 
-        let mut bytes_read = 0;
-        for _ in 0..self.sectors_per_cluster {
-            bytes_read += self.device.write_sector(curr_sector, &buf[curr_offset..])?;
+        let mut bytes_written = 0;
+        while bytes_written < buf.len() && curr_sector < end_sector {
+            let n = (sector_size - curr_offset).min(buf.len() - bytes_written);
+            let sector = self.device.get_mut(curr_sector)?;
+            sector[curr_offset..curr_offset + n]
+                .copy_from_slice(&buf[bytes_written..bytes_written + n]);
 
-            curr_offset += bytes_read;
+            bytes_written += n;
+            curr_offset = 0;
             curr_sector += 1;
         }
-        Ok(bytes_read)
+        Ok(bytes_written)
     }
+    /// Appends the contents of `cluster` to `buf` without looking at its FAT
+    /// entry.
+    pub(crate) fn read_cluster(&mut self, cluster: Cluster, buf: &mut Vec<u8>) -> io::Result<()> {
+        let sector = self.cluster_start_sector(cluster)?;
+        let offset = buf.len();
+        buf.resize(offset + self.cluster_size(), 0);
+        self.device.read_sectors(sector, &mut buf[offset..])?;
+        Ok(())
+    }
+
     //
     //  * A method to read all of the clusters chained from a starting cluster
     //    into a vector.
     //
     pub fn read_chain(&mut self, start: Cluster, buf: &mut Vec<u8>) -> io::Result<usize> {
-        let mut curr_cluster = start;
-        let mut bytes_read = 0;
+        let len = buf.len();
+        self.read_clusters(start, usize::MAX, buf)?;
+        Ok(buf.len() - len)
+    }
 
-        loop {
-            bytes_read += self.read_cluster(curr_cluster, 0, buf)?;
-            match self.fat_entry(curr_cluster)?.status() {
-                Status::Data(cluster) => {
-                    curr_cluster = cluster;
-                },
-                Status::Eoc(_) => {
-                    break;
-                }
-                Status::Free => {
-                    return Err(io::Error::new(
-                        io::ErrorKind::InvalidData,
-                        "Writing: found cluster with free status",
-                    ));
-                },
-                Status::Reserved => {
-                    return Err(io::Error::new(
-                        io::ErrorKind::InvalidData,
-                        "Reading: found cluster with res status",
-                    ));
-                },
-                Status::Bad => {
-                    return Err(io::Error::new(
-                        io::ErrorKind::InvalidData,
-                        "Reading: found cluster with bad status",
-                    ));
+    /// Appends up to `max` clusters of the chain starting at `start` to `buf`
+    /// and returns the cluster that follows the last one read, or `None` if
+    /// the end of the chain was reached.
+    ///
+    /// Runs of contiguous clusters are read from the device with a single
+    /// multi-sector request.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `InvalidData` error if the chain contains a free, reserved
+    /// or bad cluster, or if it is longer than the file system.
+    pub fn read_clusters(
+        &mut self,
+        start: Cluster,
+        max: usize,
+        buf: &mut Vec<u8>,
+    ) -> io::Result<Option<Cluster>> {
+        let cluster_size = self.cluster_size();
+        let mut next = Some(start);
+        let mut read = 0;
+
+        while read < max {
+            let run_start = match next {
+                Some(cluster) => cluster,
+                None => break,
+            };
+            let mut run_len = 0;
+            next = None;
+            let mut curr = run_start;
+            while read + run_len < max {
+                run_len += 1;
+                next = self.next_cluster(curr)?;
+                match next {
+                    Some(cluster) if cluster.num() == curr.num() + 1 => curr = cluster,
+                    _ => break,
                 }
             }
+            if read + run_len > self.max_cluster as usize {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "cluster chain has a cycle"));
+            }
+
+            let sector = self.cluster_start_sector(run_start)?;
+            let offset = buf.len();
+            buf.resize(offset + run_len * cluster_size, 0);
+            self.device.read_sectors(sector, &mut buf[offset..])?;
+            read += run_len;
         }
-        Ok(bytes_read)
+        Ok(next)
     }
-    pub fn write_chain(&mut self, start: Cluster, buf: &Vec<u8>) -> io::Result<usize> {
+
+    /// Returns the cluster that follows `cluster` in its chain, or `None` if
+    /// `cluster` is the last one.
+    fn next_cluster(&mut self, cluster: Cluster) -> io::Result<Option<Cluster>> {
+        match self.fat_entry(cluster)?.status() {
+            Status::Data(next) => Ok(Some(next)),
+            Status::Eoc(_) => Ok(None),
+            Status::Free => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Reading: found cluster with free status",
+            )),
+            Status::Reserved => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Reading: found cluster with res status",
+            )),
+            Status::Bad => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Reading: found cluster with bad status",
+            )),
+        }
+    }
+
+    //
+    //  * A method to write `buf` over the clusters chained from a starting
+    //    cluster. The chain must be long enough to hold all of `buf`.
+    //
+    pub fn write_chain(&mut self, start: Cluster, buf: &[u8]) -> io::Result<usize> {
+        self.write_chain_at(start, 0, buf)
+    }
+
+    /// Writes `buf` into the chain starting at `start`, beginning `offset`
+    /// bytes into the chain. Only the clusters that `buf` covers are written.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `UnexpectedEof` error if the chain is shorter than
+    /// `offset + buf.len()` bytes.
+    pub fn write_chain_at(&mut self, start: Cluster, offset: usize, buf: &[u8]) -> io::Result<usize> {
+        let cluster_size = self.cluster_size();
         let mut curr_cluster = start;
+        for _ in 0..offset / cluster_size {
+            curr_cluster = self.next_chain_cluster(curr_cluster)?;
+        }
+
+        let mut cluster_offset = offset % cluster_size;
         let mut bytes_write = 0;
+        while bytes_write < buf.len() {
+            if bytes_write != 0 {
+                curr_cluster = self.next_chain_cluster(curr_cluster)?;
+            }
+            let n = (cluster_size - cluster_offset).min(buf.len() - bytes_write);
+            bytes_write += self.write_cluster(curr_cluster, cluster_offset, &buf[bytes_write..bytes_write + n])?;
+            cluster_offset = 0;
+        }
+        Ok(bytes_write)
+    }
+
+    /// Returns the cluster that follows `cluster` in a chain being written.
+    fn next_chain_cluster(&mut self, cluster: Cluster) -> io::Result<Cluster> {
+        match self.fat_entry(cluster)?.status() {
+            Status::Data(cluster) => Ok(cluster),
+            Status::Eoc(_) => Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Writing: chain is shorter than the data",
+            )),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Writing: found cluster with invalid status",
+            )),
+        }
+    }
 
+    //
+    //  * A method to resize the chain starting at `start` so it holds exactly
+    //    `size` bytes, allocating or freeing clusters at its end. A `start`
+    //    of cluster 0 is an empty chain. Returns the (new) first cluster.
+    //
+    pub fn resize_chain(&mut self, start: Cluster, size: usize) -> io::Result<Cluster> {
+        let wanted = size.div_ceil(self.cluster_size());
+        if wanted == 0 {
+            if start.num() != 0 {
+                self.free_chain(start)?;
+            }
+            return Ok(Cluster::from(0));
+        }
+        if start.num() == 0 {
+            let first = self.alloc_cluster(None)?;
+            let mut last = first;
+            for _ in 1..wanted {
+                last = self.alloc_cluster(Some(last))?;
+            }
+            return Ok(first);
+        }
+
+        let mut last = start;
+        let mut count = 1;
         loop {
-            bytes_write += self.write_cluster(curr_cluster, 0, buf)?;
-
-            match self.fat_entry(curr_cluster)?.status() {
-                Status::Data(cluster) => {
-                    curr_cluster = cluster;
-                },
-                Status::Eoc(_) => {
-                    break;
+            match self.fat_entry(last)?.status() {
+                Status::Data(next) if count < wanted => {
+                    last = next;
+                    count += 1;
                 }
-                Status::Free => {
-                    return Err(io::Error::new(
-                        io::ErrorKind::InvalidData,
-                        "Writing: found cluster with free status",
-                    ));
-                },
-                Status::Reserved => {
-                    return Err(io::Error::new(
-                        io::ErrorKind::InvalidData,
-                        "Writing: found cluster with res status",
-                    ));
-                },
-                Status::Bad => {
+                Status::Data(next) => {
+                    self.set_fat_entry(last, EOC)?;
+                    self.free_chain(next)?;
+                    return Ok(start);
+                }
+                Status::Eoc(_) => break,
+                _ => {
                     return Err(io::Error::new(
                         io::ErrorKind::InvalidData,
-                        "Writing: found cluster with bad status",
+                        "Resizing: found cluster with invalid status",
                     ));
                 }
             }
         }
-        Ok(bytes_write)
+        for _ in count..wanted {
+            last = self.alloc_cluster(Some(last))?;
+        }
+        Ok(start)
+    }
+
+    //
+    //  * A method to allocate a zeroed cluster, appending it to the chain
+    //    ending at `prev` if there is one.
+    //
+    pub fn alloc_cluster(&mut self, prev: Option<Cluster>) -> io::Result<Cluster> {
+        let first = self.next_free.unwrap_or(2);
+        let mut found = None;
+        for num in (first..=self.max_cluster).chain(2..first) {
+            if self.fat_entry(Cluster::from(num))?.status() == Status::Free {
+                found = Some(Cluster::from(num));
+                break;
+            }
+        }
+        let cluster = found.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::Other, "no free clusters left")
+        })?;
+
+        self.set_fat_entry(cluster, EOC)?;
+        if let Some(prev) = prev {
+            self.set_fat_entry(prev, cluster.num())?;
+        }
+        let zeroes = vec![0u8; self.cluster_size()];
+        self.write_cluster(cluster, 0, &zeroes)?;
+
+        self.free_clusters = self.free_clusters.map(|n| n.saturating_sub(1));
+        self.next_free = Some(cluster.num() + 1).filter(|&n| n <= self.max_cluster);
+        self.write_fsinfo()?;
+        Ok(cluster)
+    }
+
+    //
+    //  * A method to free every cluster of the chain starting at `start`.
+    //
+    pub fn free_chain(&mut self, start: Cluster) -> io::Result<()> {
+        let mut curr_cluster = start;
+        for _ in 0..self.max_cluster {
+            let status = self.fat_entry(curr_cluster)?.status();
+            if let Status::Free | Status::Reserved | Status::Bad = status {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Freeing: found cluster with invalid status",
+                ));
+            }
+            self.set_fat_entry(curr_cluster, 0)?;
+            self.free_clusters = self.free_clusters.map(|n| n + 1);
+            if self.next_free.is_none_or(|n| curr_cluster.num() < n) {
+                self.next_free = Some(curr_cluster.num());
+            }
+
+            match status {
+                Status::Data(cluster) => curr_cluster = cluster,
+                _ => return self.write_fsinfo(),
+            }
+        }
+        Err(io::Error::new(io::ErrorKind::InvalidData, "Freeing: chain has a cycle"))
     }
+
     //
     //  * A method to return a reference to a `FatEntry` for a cluster where the
     //    reference points directly into a cached sector.
@@ -195,12 +479,122 @@ impl<HANDLE: VFatHandle> VFat<HANDLE> {
         Ok(&entries[(cluster.num() as usize) % entries_per_sector as usize])
     }
 
+    //
+    //  * A method to return the value of the FAT entry for `cluster`, without
+    //    its reserved high 4 bits.
+    //
+    pub(crate) fn fat_value(&mut self, cluster: Cluster) -> io::Result<u32> {
+        Ok(self.fat_entry(cluster)?.0 & 0x0FFF_FFFF)
+    }
+
+    //
+    //  * A method to return the `Status` of the FAT entry for `cluster`.
+    //
+    pub(crate) fn fat_status(&mut self, cluster: Cluster) -> io::Result<Status> {
+        Ok(self.fat_entry(cluster)?.status())
+    }
+
+    //
+    //  * A method to set the FAT entry for `cluster` to `value` in every copy
+    //    of the FAT. The reserved high 4 bits of the entry are preserved.
+    //
+    pub(crate) fn set_fat_entry(&mut self, cluster: Cluster, value: u32) -> io::Result<()> {
+        self.mark_dirty()?;
+        self.write_fat_entry(cluster, value)
+    }
+
+    //
+    //  * A method to set the FAT entry for `cluster` like `set_fat_entry()`
+    //    without marking the volume as in use.
+    //
+    fn write_fat_entry(&mut self, cluster: Cluster, value: u32) -> io::Result<()> {
+        let entries_per_sector = (self.bytes_per_sector as usize / size_of::<FatEntry>()) as u64;
+        let sector_in_fat = cluster.num() as u64 / entries_per_sector;
+        let index = (cluster.num() as u64 % entries_per_sector) as usize;
+        for fat in 0..self.num_fats as u64 {
+            let fat_sector = self.fat_start_sector + fat * self.sectors_per_fat as u64 + sector_in_fat;
+            let buf = self.device.get_mut(fat_sector)?;
+            let entries: &mut [FatEntry] = unsafe { buf.cast_mut() };
+            entries[index].0 = (entries[index].0 & 0xF000_0000) | (value & 0x0FFF_FFFF);
+        }
+        Ok(())
+    }
+
+    //
+    //  * A method to record the free cluster count and next free cluster
+    //    hints in the FSInfo sector, if the file system has one.
+    //
+    fn write_fsinfo(&mut self) -> io::Result<()> {
+        let sector = match self.fsinfo_sector {
+            Some(sector) => sector,
+            None => return Ok(()),
+        };
+        self.mark_dirty()?;
+        let free_count = self.free_clusters.unwrap_or(fsinfo::UNKNOWN);
+        let next_free = self.next_free.unwrap_or(fsinfo::UNKNOWN);
+
+        let buf = self.device.get_mut(sector)?;
+        buf[fsinfo::FREE_COUNT_OFFSET..fsinfo::FREE_COUNT_OFFSET + 4]
+            .copy_from_slice(&free_count.to_le_bytes());
+        buf[fsinfo::NEXT_FREE_OFFSET..fsinfo::NEXT_FREE_OFFSET + 4]
+            .copy_from_slice(&next_free.to_le_bytes());
+        Ok(())
+    }
+
+    /// Returns the highest data cluster number.
+    pub(crate) fn max_cluster(&self) -> u32 {
+        self.max_cluster
+    }
+
+    /// Records `count` as the number of free clusters in the FSInfo sector.
+    pub(crate) fn set_free_clusters(&mut self, count: u32) -> io::Result<()> {
+        self.free_clusters = Some(count);
+        self.write_fsinfo()
+    }
+
+    /// Returns, for every copy of the FAT after the first one, the number of
+    /// its sectors that differ from the first copy.
+    pub(crate) fn fat_copy_mismatches(&mut self) -> io::Result<Vec<(u8, u32)>> {
+        let mut mismatches = Vec::new();
+        for copy in 1..self.num_fats {
+            let mut differing = 0;
+            for sector in 0..self.sectors_per_fat as u64 {
+                let first = self.device.get(self.fat_start_sector + sector)?.to_vec();
+                let other = self.fat_copy_sector(copy, sector);
+                if self.device.get(other)? != &first[..] {
+                    differing += 1;
+                }
+            }
+            if differing > 0 {
+                mismatches.push((copy, differing));
+            }
+        }
+        Ok(mismatches)
+    }
+
+    /// Overwrites copy `copy` of the FAT with the first copy.
+    pub(crate) fn restore_fat_copy(&mut self, copy: u8) -> io::Result<()> {
+        self.mark_dirty()?;
+        for sector in 0..self.sectors_per_fat as u64 {
+            let first = self.device.get(self.fat_start_sector + sector)?.to_vec();
+            let other = self.fat_copy_sector(copy, sector);
+            self.device.get_mut(other)?.copy_from_slice(&first);
+        }
+        Ok(())
+    }
+
+    /// Returns the number of sector `sector` of copy `copy` of the FAT.
+    fn fat_copy_sector(&self, copy: u8, sector: u64) -> u64 {
+        self.fat_start_sector + copy as u64 * self.sectors_per_fat as u64 + sector
+    }
+
     pub fn get_root_dir(&mut self, handle: &HANDLE) -> io::Result<Dir<HANDLE>> {
         Ok(Dir {
             first_cluster: self.rootdir_cluster,
             vfat: handle.clone(),
             name: String::from("/"),
             metadata: None,
+            slots: None,
         })
     }
 }
@@ -214,4 +608,34 @@ impl<'a, HANDLE: VFatHandle> FileSystem for &'a HANDLE {
         let root_dir = self.lock(|s| s.get_root_dir(&self).unwrap());
         root_dir.open_path(path.as_ref(), &mut Path::new("/").to_path_buf())
     }
+
+    fn create_dir<P: AsRef<Path>>(self, path: P) -> io::Result<Self::Dir> {
+        let (parent, name) = split_path(path.as_ref())?;
+        self.open_dir(parent)?.create_dir(name)
+    }
+
+    fn remove_dir<P: AsRef<Path>>(self, path: P) -> io::Result<()> {
+        let (parent, name) = split_path(path.as_ref())?;
+        self.open_dir(parent)?.remove_dir(name)
+    }
+
+    fn rename<P: AsRef<Path>, Q: AsRef<Path>>(self, from: P, to: Q) -> io::Result<()> {
+        let (from_parent, from_name) = split_path(from.as_ref())?;
+        let (to_parent, to_name) = split_path(to.as_ref())?;
+        let to_dir = self.open_dir(to_parent)?;
+        self.open_dir(from_parent)?.rename(from_name, &to_dir, to_name)
+    }
+}
+
+/// Splits `path` into its parent directory and its last component.
+///
+/// # Errors
+///
+/// Returns an error of `InvalidInput` if `path` has no last component, like
+/// `/` or a path ending in `..`.
+fn split_path(path: &Path) -> io::Result<(&Path, &OsStr)> {
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => Ok((parent, name)),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "path has no file name")),
+    }
 }
@@ -0,0 +1,59 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use fat32::traits::BlockDevice;
+
+/// A disk image file used as a `BlockDevice` with 512-byte sectors.
+pub struct ImageFile(File);
+
+impl ImageFile {
+    /// Opens the image at `path` for reading and writing.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<ImageFile> {
+        Ok(ImageFile(OpenOptions::new().read(true).write(true).open(path)?))
+    }
+
+    /// Creates an image of `size` zeroed bytes at `path`, replacing any file
+    /// that is already there.
+    pub fn create<P: AsRef<Path>>(path: P, size: u64) -> io::Result<ImageFile> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        file.set_len(size)?;
+        Ok(ImageFile(file))
+    }
+}
+
+impl BlockDevice for ImageFile {
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len().min(self.sector_size() as usize);
+        self.0.seek(SeekFrom::Start(n * self.sector_size()))?;
+        self.0.read_exact(&mut buf[..len])?;
+        Ok(len)
+    }
+
+    fn read_sectors(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        if !buf.len().is_multiple_of(self.sector_size() as usize) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "buffer is not a multiple of the sector size",
+            ));
+        }
+        self.0.seek(SeekFrom::Start(n * self.sector_size()))?;
+        self.0.read_exact(buf)?;
+        Ok(buf.len())
+    }
+
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        let len = self.sector_size() as usize;
+        if buf.len() < len {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "buffer is smaller than a sector"));
+        }
+        self.0.seek(SeekFrom::Start(n * self.sector_size()))?;
+        self.0.write_all(&buf[..len])?;
+        Ok(len)
+    }
+}
@@ -0,0 +1,257 @@
+extern crate fat32;
+extern crate structopt;
+#[macro_use]
+extern crate structopt_derive;
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use fat32::traits::{Dir as DirTrait, Entry as EntryTrait, File as FileTrait, FileSystem};
+use fat32::vfat::{Timestamp, VFat, VFatHandle};
+use structopt::StructOpt;
+
+mod disk;
+mod parsers;
+
+use disk::ImageFile;
+use parsers::parse_size;
+
+#[derive(StructOpt, Debug)]
+#[structopt(about = "Inspect and modify a FAT32 partition inside an MBR disk image.")]
+struct Opt {
+    #[structopt(help = "Path to the disk image", parse(from_os_str))]
+    image: PathBuf,
+
+    #[structopt(subcommand)]
+    command: Command,
+}
+
+#[derive(StructOpt, Debug)]
+enum Command {
+    #[structopt(name = "mkfs")]
+    /// Create an image of the given size holding an empty FAT32 partition.
+    Mkfs {
+        #[structopt(help = "Size of the image, like 128M", parse(try_from_str = "parse_size"))]
+        size: u64,
+    },
+    #[structopt(name = "ls")]
+    /// List the entries of a directory.
+    Ls {
+        #[structopt(help = "Directory in the image", default_value = "/")]
+        path: String,
+    },
+    #[structopt(name = "cat")]
+    /// Write the contents of a file to stdout.
+    Cat {
+        #[structopt(help = "File in the image")]
+        path: String,
+    },
+    #[structopt(name = "cp-in")]
+    /// Copy a host file into the image, replacing any existing file.
+    CpIn {
+        #[structopt(help = "Host file", parse(from_os_str))]
+        from: PathBuf,
+        #[structopt(help = "File or directory in the image")]
+        to: String,
+    },
+    #[structopt(name = "cp-out")]
+    /// Copy a file out of the image.
+    CpOut {
+        #[structopt(help = "File in the image")]
+        from: String,
+        #[structopt(help = "Host file", parse(from_os_str))]
+        to: PathBuf,
+    },
+    #[structopt(name = "mkdir")]
+    /// Create a directory.
+    Mkdir {
+        #[structopt(short = "p", help = "Create missing parents and accept an existing directory")]
+        parents: bool,
+        #[structopt(help = "Directory in the image")]
+        path: String,
+    },
+    #[structopt(name = "rm")]
+    /// Remove a file or an empty directory.
+    Rm {
+        #[structopt(help = "Entry in the image")]
+        path: String,
+    },
+    #[structopt(name = "fsck")]
+    /// Check the consistency of the file system.
+    Fsck {
+        #[structopt(short = "r", long = "repair", help = "Fix the problems found")]
+        repair: bool,
+    },
+}
+
+#[derive(Clone)]
+struct Handle(Arc<Mutex<VFat<Self>>>);
+
+impl fmt::Debug for Handle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Handle")
+    }
+}
+
+impl VFatHandle for Handle {
+    fn new(val: VFat<Handle>) -> Self {
+        Handle(Arc::new(Mutex::new(val)))
+    }
+
+    fn lock<R>(&self, f: impl FnOnce(&mut VFat<Handle>) -> R) -> R {
+        f(&mut self.0.lock().expect("lock is not poisoned"))
+    }
+}
+
+/// Returns the current time, which new and written entries are stamped with.
+fn now() -> Timestamp {
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |now| now.as_secs());
+    Timestamp::from_unix(secs)
+}
+
+fn mount(image: &Path) -> io::Result<Handle> {
+    let device = ImageFile::open(image)?;
+    let vfat = VFat::<Handle>::from(device).map_err(|e| {
+        io::Error::new(io::ErrorKind::InvalidData, format!("not a FAT32 image: {:?}", e))
+    })?;
+    vfat.lock(|vfat| vfat.set_clock(now));
+    Ok(vfat)
+}
+
+/// Turns `path` into an absolute path in the image.
+fn image_path(path: &str) -> PathBuf {
+    Path::new("/").join(path)
+}
+
+/// Splits the image path `path` into its parent directory and its name.
+fn split(path: &Path) -> io::Result<(&Path, &str)> {
+    match (path.parent(), path.file_name().and_then(|name| name.to_str())) {
+        (Some(parent), Some(name)) => Ok((parent, name)),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "path has no file name")),
+    }
+}
+
+fn ls(vfat: &Handle, path: &str) -> io::Result<()> {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    for entry in vfat.open_dir(image_path(path))?.entries()? {
+        match entry.as_file() {
+            Some(file) => writeln!(out, "{:>10}  {}", file.size(), entry.name())?,
+            None => writeln!(out, "{:>10}  {}/", "", entry.name())?,
+        }
+    }
+    Ok(())
+}
+
+fn cat(vfat: &Handle, path: &str) -> io::Result<()> {
+    let mut file = vfat.open_file(image_path(path))?;
+    io::copy(&mut file, &mut io::stdout().lock())?;
+    Ok(())
+}
+
+fn cp_in(vfat: &Handle, from: &Path, to: &str) -> io::Result<()> {
+    let mut to = image_path(to);
+    if vfat.open(&to).is_ok_and(|entry| entry.is_dir()) {
+        let name = from
+            .file_name()
+            .ok_or(io::Error::new(io::ErrorKind::InvalidInput, "source has no file name"))?;
+        to.push(name);
+    }
+
+    let (parent, name) = split(&to)?;
+    let mut file = match vfat.open(&to) {
+        Ok(entry) => {
+            let mut file = entry
+                .into_file()
+                .ok_or(io::Error::new(io::ErrorKind::AlreadyExists, "destination is a directory"))?;
+            file.set_len(0)?;
+            file
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => vfat.open_dir(parent)?.create_file(name)?,
+        Err(e) => return Err(e),
+    };
+    io::copy(&mut File::open(from)?, &mut file)?;
+    file.sync()
+}
+
+fn cp_out(vfat: &Handle, from: &str, to: &Path) -> io::Result<()> {
+    let mut file = vfat.open_file(image_path(from))?;
+    io::copy(&mut file, &mut File::create(to)?)?;
+    Ok(())
+}
+
+fn mkdir(vfat: &Handle, path: &str, parents: bool) -> io::Result<()> {
+    let path = image_path(path);
+    if !parents {
+        return vfat.create_dir(&path).map(|_| ());
+    }
+    for dir in path.ancestors().collect::<Vec<_>>().into_iter().rev().skip(1) {
+        match vfat.create_dir(dir) {
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                vfat.open_dir(dir)?;
+            }
+            result => {
+                result?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn rm(vfat: &Handle, path: &str) -> io::Result<()> {
+    let path = image_path(path);
+    if vfat.open(&path)?.is_dir() {
+        return vfat.remove_dir(&path);
+    }
+    let (parent, name) = split(&path)?;
+    vfat.open_dir(parent)?.remove(name)
+}
+
+/// Checks the file system and returns whether it is consistent, or was made
+/// consistent.
+fn fsck(vfat: &Handle, repair: bool) -> io::Result<bool> {
+    let findings = vfat.lock(|vfat| if repair { vfat.repair() } else { vfat.check() })?;
+    for finding in &findings {
+        println!("{}", finding);
+    }
+    Ok(findings.iter().all(|finding| finding.repaired))
+}
+
+fn run(opt: Opt) -> io::Result<bool> {
+    if let Command::Mkfs { size } = opt.command {
+        let mut image = ImageFile::create(&opt.image, size)?;
+        fat32::vfat::format(&mut image, size / 512)?;
+        return Ok(true);
+    }
+
+    let vfat = mount(&opt.image)?;
+    let consistent = match opt.command {
+        Command::Mkfs { .. } => unreachable!("handled above"),
+        Command::Ls { path } => ls(&vfat, &path).map(|_| true),
+        Command::Cat { path } => cat(&vfat, &path).map(|_| true),
+        Command::CpIn { from, to } => cp_in(&vfat, &from, &to).map(|_| true),
+        Command::CpOut { from, to } => cp_out(&vfat, &from, &to).map(|_| true),
+        Command::Mkdir { parents, path } => mkdir(&vfat, &path, parents).map(|_| true),
+        Command::Rm { path } => rm(&vfat, &path).map(|_| true),
+        Command::Fsck { repair } => fsck(&vfat, repair),
+    }?;
+    vfat.lock(|vfat| vfat.sync())?;
+    Ok(consistent)
+}
+
+fn main() {
+    let opt = Opt::from_args();
+    match run(opt) {
+        Ok(true) => {}
+        Ok(false) => process::exit(1),
+        Err(e) => {
+            eprintln!("error: {}", e);
+            process::exit(1);
+        }
+    }
+}
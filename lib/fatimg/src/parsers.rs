@@ -0,0 +1,15 @@
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let (digits, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => s.split_at(i),
+        None => (s, ""),
+    };
+    let shift = match unit {
+        "" => 0,
+        "K" | "KiB" => 10,
+        "M" | "MiB" => 20,
+        "G" | "GiB" => 30,
+        _ => return Err(format!("unknown size unit '{}': use K, M or G", unit)),
+    };
+    let n: u64 = digits.parse().map_err(|e| format!("invalid size '{}': {}", s, e))?;
+    n.checked_mul(1 << shift).ok_or(format!("size '{}' is too large", s))
+}
@@ -30,6 +30,17 @@ impl Allocator {
             bins: [LinkedList::new(); 32 - 2],
         }
     }
+
+    /// Moves the end of the region this allocator hands out memory from to
+    /// `end`, for backing memory that can grow after creation.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `end` is below the current end of the region.
+    pub fn grow(&mut self, end: usize) {
+        assert!(end >= self.end, "allocator region cannot shrink");
+        self.end = end;
+    }
 }
 use core::cmp::max;
 
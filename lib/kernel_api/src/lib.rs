@@ -31,9 +31,12 @@ pub enum OsError {
 
     InvalidFile = 110,
     InvalidDirectory = 111,
+    InvalidExecutable = 112,
 
     InvalidSocket = 200,
     IllegalSocketOperation = 201,
+    PortInUse = 202,
+    WouldBlock = 203,
 }
 
 impl core::convert::From<u64> for OsError {
@@ -53,9 +56,16 @@ impl core::convert::From<u64> for OsError {
             102 => OsError::IoErrorEof,
             103 => OsError::IoErrorInvalidData,
             104 => OsError::IoErrorInvalidInput,
+            105 => OsError::IoErrorTimedOut,
+
+            110 => OsError::InvalidFile,
+            111 => OsError::InvalidDirectory,
+            112 => OsError::InvalidExecutable,
 
             200 => OsError::InvalidSocket,
             201 => OsError::IllegalSocketOperation,
+            202 => OsError::PortInUse,
+            203 => OsError::WouldBlock,
 
             _ => OsError::Unknown,
         }
@@ -70,6 +80,8 @@ impl core::convert::From<io::Error> for OsError {
             io::ErrorKind::InvalidInput => OsError::IoErrorInvalidInput,
             io::ErrorKind::TimedOut => OsError::IoErrorTimedOut,
             io::ErrorKind::NotFound => OsError::NoEntry,
+            io::ErrorKind::AlreadyExists => OsError::FileExists,
+            io::ErrorKind::WouldBlock => OsError::WouldBlock,
             _ => OsError::IoError,
         }
     }
@@ -90,12 +102,64 @@ pub const NR_READDIR: usize = 12;
 pub const NR_EXEC: usize = 13;
 pub const NR_FORK: usize = 14;
 pub const NR_WAITPID: usize = 15;
+pub const NR_BRK: usize = 16;
+pub const NR_MMAP: usize = 17;
+pub const NR_MKDIR: usize = 18;
+pub const NR_RMDIR: usize = 19;
 pub const NR_SOCK_CREATE: usize = 20;
 pub const NR_SOCK_STATUS: usize = 21;
 pub const NR_SOCK_CONNECT: usize = 22;
 pub const NR_SOCK_LISTEN: usize = 23;
 pub const NR_SOCK_SEND: usize = 24;
 pub const NR_SOCK_RECV: usize = 25;
+pub const NR_RENAME: usize = 26;
+pub const NR_SYNC: usize = 27;
+pub const NR_STAT: usize = 28;
+pub const NR_FSTAT: usize = 29;
+pub const NR_SET_TIMES: usize = 30;
+pub const NR_SET_ATTRIBUTES: usize = 31;
+pub const NR_UNLINK: usize = 32;
+pub const NR_TRUNCATE: usize = 33;
+pub const NR_CHDIR: usize = 34;
+pub const NR_GETCWD: usize = 35;
+pub const NR_SOCK_BIND: usize = 36;
+pub const NR_SOCK_SENDTO: usize = 37;
+pub const NR_SOCK_RECVFROM: usize = 38;
+pub const NR_SOCK_ACCEPT: usize = 39;
+pub const NR_SOCK_CONFIGURE: usize = 40;
+pub const NR_WALL_TIME: usize = 41;
+pub const NR_SET_WALL_TIME: usize = 42;
+
+/// `open` flag: create an empty file if no entry exists at the path.
+pub const O_CREAT: u64 = 0x1;
+/// `open` flag: truncate an existing file to length 0.
+pub const O_TRUNC: u64 = 0x2;
+
+/// `sock_configure` flag: fail socket calls that would wait with
+/// `OsError::WouldBlock` instead of sleeping.
+pub const SOCK_NONBLOCK: u64 = 0x1;
+
+/// `seek` whence: the offset is relative to the start of the file.
+pub const SEEK_SET: u64 = 0;
+/// `seek` whence: the offset is relative to the current position.
+pub const SEEK_CUR: u64 = 1;
+/// `seek` whence: the offset is relative to the end of the file.
+pub const SEEK_END: u64 = 2;
+
+/// `wait` flag: return immediately if no selected child has terminated yet.
+pub const WNOHANG: u64 = 0x1;
+
+/// `set_times` value that leaves a timestamp unchanged.
+pub const TIME_OMIT: u64 = u64::MAX;
+
+/// Attribute bits of `Stat::attributes`. Only `ATTR_READ_ONLY`,
+/// `ATTR_HIDDEN`, `ATTR_SYSTEM` and `ATTR_ARCHIVE` can be changed with
+/// `set_attributes`.
+pub const ATTR_READ_ONLY: u64 = 0x01;
+pub const ATTR_HIDDEN: u64 = 0x02;
+pub const ATTR_SYSTEM: u64 = 0x04;
+pub const ATTR_DIRECTORY: u64 = 0x10;
+pub const ATTR_ARCHIVE: u64 = 0x20;
 
 
 
@@ -119,6 +183,69 @@ impl SocketDescriptor {
     }
 }
 
+/// The metadata of a file or directory, as reported by `stat` and `fstat`.
+///
+/// Timestamps are Unix times in seconds, or 0 if the file system did not
+/// record them. FAT32 only keeps the date of the last access, so `accessed`
+/// is always midnight for files on the SD card.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Stat {
+    /// The size in bytes; 0 for directories.
+    pub size: u64,
+    /// The `ATTR_*` bits of the entry.
+    pub attributes: u64,
+    pub created: u64,
+    pub accessed: u64,
+    pub modified: u64,
+}
+
+impl Stat {
+    /// Returns `true` if the entry is a directory.
+    pub fn is_dir(&self) -> bool {
+        self.attributes & ATTR_DIRECTORY != 0
+    }
+}
+
+/// How a child process terminated, as reported by `wait`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ExitStatus {
+    /// The code the process passed to `exit`, or the code the kernel
+    /// assigned if it killed the process.
+    pub code: i32,
+    /// `true` if the kernel killed the process because of a fault.
+    pub killed: bool,
+}
+
+impl ExitStatus {
+    /// Returns `true` if the process exited normally with code `0`.
+    pub fn success(&self) -> bool {
+        !self.killed && self.code == 0
+    }
+}
+
+/// The transport protocol of a socket, chosen when it is created.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SocketType {
+    /// A TCP stream socket, used with `sock_connect`, `sock_listen`,
+    /// `sock_accept`, `sock_send` and `sock_recv`.
+    Tcp = 0,
+    /// A UDP datagram socket, used with `sock_bind`, `sock_sendto` and
+    /// `sock_recvfrom`.
+    Udp = 1,
+}
+
+impl SocketType {
+    /// Returns the socket type with the raw value `raw`, if there is one.
+    pub fn from_raw(raw: u64) -> Option<SocketType> {
+        match raw {
+            0 => Some(SocketType::Tcp),
+            1 => Some(SocketType::Udp),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct SocketStatus {
     pub is_active: bool,
@@ -127,6 +254,7 @@ pub struct SocketStatus {
     pub can_recv: bool,
 }
 
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub struct IpAddr {
     pub ip: u32,
     pub port: u16,
@@ -65,10 +65,64 @@ pub fn time() -> Duration {
     Duration::from_secs(current_time) + Duration::from_nanos(frac_time)
 }
 
-pub fn exit() -> ! {
+/// Returns the wall-clock time as a duration since the Unix epoch. This is the
+/// clock that file timestamps are taken from.
+pub fn wall_time() -> OsResult<Duration> {
+    let mut ecode: u64;
+    let mut secs: u64;
+    let mut nanos: u64;
+
+    unsafe {
+        asm!(
+            "svc {nr_wall_time}",
+            "mov {secs}, x0",
+            "mov {nanos}, x1",
+            "mov {ecode}, x7",
+            nr_wall_time = const NR_WALL_TIME,
+            secs = out(reg) secs,
+            nanos = out(reg) nanos,
+            ecode = out(reg) ecode,
+            out("x0") _,   // Clobbers x0
+            out("x1") _,   // Clobbers x1
+            out("x7") _,   // Clobbers x7
+            options(nostack),
+        );
+    }
+
+    err_or!(ecode, Duration::new(secs, nanos as u32))
+}
+
+/// Sets the wall-clock time to `now`, a duration since the Unix epoch. The
+/// clock is not kept across reboots.
+pub fn set_wall_time(now: Duration) -> OsResult<()> {
+    let mut ecode: u64;
+
+    unsafe {
+        asm!(
+            "mov x0, {secs}",
+            "mov x1, {nanos}",
+            "svc {nr_set_wall_time}",
+            "mov {ecode}, x7",
+            secs = in(reg) now.as_secs(),
+            nanos = in(reg) now.subsec_nanos() as u64,
+            nr_set_wall_time = const NR_SET_WALL_TIME,
+            ecode = out(reg) ecode,
+            out("x0") _,   // Clobbers x0
+            out("x1") _,   // Clobbers x1
+            out("x7") _,   // Clobbers x7
+            options(nostack),
+        );
+    }
+
+    err_or!(ecode, ())
+}
+
+pub fn exit(code: i32) -> ! {
     unsafe {
         asm!(
+            "mov x0, {code}",
             "svc {nr_exit}",
+            code = in(reg) code as i64,
             nr_exit = const NR_EXIT,
             options(nostack),
         );
@@ -154,6 +208,12 @@ pub fn write(fd: usize, buf: &[u8]) -> OsResult<usize> {
 }
 
 pub fn open(path: &str) -> OsResult<usize> {
+    open_with(path, 0)
+}
+
+/// Opens the file or directory at `path` as `open` does, modified by the
+/// `O_CREAT` and `O_TRUNC` bits of `flags`.
+pub fn open_with(path: &str, flags: u64) -> OsResult<usize> {
     let mut ecode: u64;
     let mut fd: u64;
     let mut buf = [0u8; 256];
@@ -166,14 +226,17 @@ pub fn open(path: &str) -> OsResult<usize> {
     unsafe {
         asm!(
             "mov x0, {path_addr}",
+            "mov x1, {flags}",
             "svc {nr_open}",
             "mov {fd}, x0",
             "mov {ecode}, x7",
             path_addr = in(reg) buf.as_ptr(),
+            flags = in(reg) flags,
             nr_open = const NR_OPEN,
             fd = out(reg) fd,
             ecode = out(reg) ecode,
             out("x0") _,   // Clobbers x0
+            out("x1") _,   // Clobbers x1
             out("x7") _,   // Clobbers x7
             options(nostack),
         );
@@ -229,25 +292,36 @@ pub fn read(fd: usize, buf: &mut [u8]) -> OsResult<usize> {
     err_or!(ecode, bytes_read as usize)
 }
 
-pub fn seek(fd: usize, offset: usize) -> OsResult<()> {
+/// Moves the position of file `fd` by `offset` bytes from the point given by
+/// `whence` (`SEEK_SET`, `SEEK_CUR` or `SEEK_END`) and returns the new
+/// position from the start of the file.
+pub fn seek(fd: usize, offset: i64, whence: u64) -> OsResult<u64> {
     let mut ecode: u64;
+    let mut pos: u64;
 
     unsafe {
         asm!(
             "mov x0, {fd}",
             "mov x1, {offset}",
+            "mov x2, {whence}",
             "svc {nr_seek}",
+            "mov {pos}, x0",
             "mov {ecode}, x7",
             fd = in(reg) fd,
             offset = in(reg) offset,
+            whence = in(reg) whence,
             nr_seek = const NR_SEEK,
+            pos = out(reg) pos,
             ecode = out(reg) ecode,
+            out("x0") _,   // Clobbers x0
+            out("x1") _,   // Clobbers x1
+            out("x2") _,   // Clobbers x2
             out("x7") _,   // Clobbers x7
             options(nostack),
         );
     }
 
-    err_or!(ecode, ())
+    err_or!(ecode, pos)
 }
 
 pub fn len(fd: usize) -> OsResult<usize> {
@@ -302,6 +376,307 @@ pub fn readdir(fd: usize, buf: &mut [u8]) -> OsResult<usize> {
 }
 
 
+/// Copies `path` into a NUL-terminated buffer that the kernel can read.
+fn path_buf(path: &str) -> OsResult<[u8; 256]> {
+    let mut buf = [0u8; 256];
+    if path.len() >= buf.len() {
+        return Err(OsError::InvalidArgument);
+    }
+    buf[..path.len()].copy_from_slice(path.as_bytes());
+    Ok(buf)
+}
+
+pub fn mkdir(path: &str) -> OsResult<()> {
+    let mut ecode: u64;
+    let buf = path_buf(path)?;
+
+    unsafe {
+        asm!(
+            "mov x0, {path_addr}",
+            "svc {nr_mkdir}",
+            "mov {ecode}, x7",
+            path_addr = in(reg) buf.as_ptr(),
+            nr_mkdir = const NR_MKDIR,
+            ecode = out(reg) ecode,
+            out("x0") _,   // Clobbers x0
+            out("x7") _,   // Clobbers x7
+            options(nostack),
+        );
+    }
+
+    err_or!(ecode, ())
+}
+
+pub fn rmdir(path: &str) -> OsResult<()> {
+    let mut ecode: u64;
+    let buf = path_buf(path)?;
+
+    unsafe {
+        asm!(
+            "mov x0, {path_addr}",
+            "svc {nr_rmdir}",
+            "mov {ecode}, x7",
+            path_addr = in(reg) buf.as_ptr(),
+            nr_rmdir = const NR_RMDIR,
+            ecode = out(reg) ecode,
+            out("x0") _,   // Clobbers x0
+            out("x7") _,   // Clobbers x7
+            options(nostack),
+        );
+    }
+
+    err_or!(ecode, ())
+}
+
+pub fn rename(from: &str, to: &str) -> OsResult<()> {
+    let mut ecode: u64;
+    let from_buf = path_buf(from)?;
+    let to_buf = path_buf(to)?;
+
+    unsafe {
+        asm!(
+            "mov x0, {from_addr}",
+            "mov x1, {to_addr}",
+            "svc {nr_rename}",
+            "mov {ecode}, x7",
+            from_addr = in(reg) from_buf.as_ptr(),
+            to_addr = in(reg) to_buf.as_ptr(),
+            nr_rename = const NR_RENAME,
+            ecode = out(reg) ecode,
+            out("x0") _,   // Clobbers x0
+            out("x1") _,   // Clobbers x1
+            out("x7") _,   // Clobbers x7
+            options(nostack),
+        );
+    }
+
+    err_or!(ecode, ())
+}
+
+/// Removes the file at `path`. A file on the tmpfs that is still open stays
+/// readable and writable through its descriptors until they are closed; on
+/// the SD card, reads and writes through them fail with `OsError::NoEntry`.
+pub fn unlink(path: &str) -> OsResult<()> {
+    let mut ecode: u64;
+    let buf = path_buf(path)?;
+
+    unsafe {
+        asm!(
+            "mov x0, {path_addr}",
+            "svc {nr_unlink}",
+            "mov {ecode}, x7",
+            path_addr = in(reg) buf.as_ptr(),
+            nr_unlink = const NR_UNLINK,
+            ecode = out(reg) ecode,
+            out("x0") _,   // Clobbers x0
+            out("x7") _,   // Clobbers x7
+            options(nostack),
+        );
+    }
+
+    err_or!(ecode, ())
+}
+
+/// Truncates or zero-extends the open file `fd` to `len` bytes.
+pub fn truncate(fd: usize, len: u64) -> OsResult<()> {
+    let mut ecode: u64;
+
+    unsafe {
+        asm!(
+            "mov x0, {fd}",
+            "mov x1, {len}",
+            "svc {nr_truncate}",
+            "mov {ecode}, x7",
+            fd = in(reg) fd,
+            len = in(reg) len,
+            nr_truncate = const NR_TRUNCATE,
+            ecode = out(reg) ecode,
+            out("x0") _,   // Clobbers x0
+            out("x1") _,   // Clobbers x1
+            out("x7") _,   // Clobbers x7
+            options(nostack),
+        );
+    }
+
+    err_or!(ecode, ())
+}
+
+/// Changes the working directory of the calling process to the directory
+/// at `path`. Relative paths given to other system calls are resolved
+/// against it.
+pub fn chdir(path: &str) -> OsResult<()> {
+    let mut ecode: u64;
+    let buf = path_buf(path)?;
+
+    unsafe {
+        asm!(
+            "mov x0, {path_addr}",
+            "svc {nr_chdir}",
+            "mov {ecode}, x7",
+            path_addr = in(reg) buf.as_ptr(),
+            nr_chdir = const NR_CHDIR,
+            ecode = out(reg) ecode,
+            out("x0") _,   // Clobbers x0
+            out("x7") _,   // Clobbers x7
+            options(nostack),
+        );
+    }
+
+    err_or!(ecode, ())
+}
+
+/// Writes the absolute path of the calling process's working directory to
+/// `buf` and returns it.
+pub fn getcwd(buf: &mut [u8]) -> OsResult<&str> {
+    let mut ecode: u64;
+    let mut len: u64;
+
+    unsafe {
+        asm!(
+            "mov x0, {buf_addr}",
+            "mov x1, {buf_len}",
+            "svc {nr_getcwd}",
+            "mov {len}, x0",
+            "mov {ecode}, x7",
+            buf_addr = in(reg) buf.as_mut_ptr(),
+            buf_len = in(reg) buf.len(),
+            nr_getcwd = const NR_GETCWD,
+            len = out(reg) len,
+            ecode = out(reg) ecode,
+            out("x0") _,   // Clobbers x0
+            out("x1") _,   // Clobbers x1
+            out("x7") _,   // Clobbers x7
+            options(nostack),
+        );
+    }
+
+    err_or!(ecode, len as usize)
+        .and_then(|len| str::from_utf8(&buf[..len]).map_err(|_| OsError::InvalidArgument))
+}
+
+pub fn sync() -> OsResult<()> {
+    let mut ecode: u64;
+
+    unsafe {
+        asm!(
+            "svc {nr_sync}",
+            "mov {ecode}, x7",
+            nr_sync = const NR_SYNC,
+            ecode = out(reg) ecode,
+            out("x7") _,   // Clobbers x7
+            options(nostack),
+        );
+    }
+
+    err_or!(ecode, ())
+}
+
+/// Returns the metadata of the file or directory at `path`.
+pub fn stat(path: &str) -> OsResult<Stat> {
+    let mut ecode: u64;
+    let buf = path_buf(path)?;
+    let mut stat = Stat::default();
+
+    unsafe {
+        asm!(
+            "mov x0, {path_addr}",
+            "mov x1, {stat_addr}",
+            "svc {nr_stat}",
+            "mov {ecode}, x7",
+            path_addr = in(reg) buf.as_ptr(),
+            stat_addr = in(reg) &mut stat as *mut Stat,
+            nr_stat = const NR_STAT,
+            ecode = out(reg) ecode,
+            out("x0") _,   // Clobbers x0
+            out("x1") _,   // Clobbers x1
+            out("x7") _,   // Clobbers x7
+            options(nostack),
+        );
+    }
+
+    err_or!(ecode, stat)
+}
+
+/// Returns the metadata of the open file or directory `fd`.
+pub fn fstat(fd: usize) -> OsResult<Stat> {
+    let mut ecode: u64;
+    let mut stat = Stat::default();
+
+    unsafe {
+        asm!(
+            "mov x0, {fd}",
+            "mov x1, {stat_addr}",
+            "svc {nr_fstat}",
+            "mov {ecode}, x7",
+            fd = in(reg) fd,
+            stat_addr = in(reg) &mut stat as *mut Stat,
+            nr_fstat = const NR_FSTAT,
+            ecode = out(reg) ecode,
+            out("x0") _,   // Clobbers x0
+            out("x1") _,   // Clobbers x1
+            out("x7") _,   // Clobbers x7
+            options(nostack),
+        );
+    }
+
+    err_or!(ecode, stat)
+}
+
+/// Sets the access and modification times of the open file or directory
+/// `fd` to the given Unix times. A `None` time is left unchanged.
+pub fn set_times(fd: usize, accessed: Option<u64>, modified: Option<u64>) -> OsResult<()> {
+    let mut ecode: u64;
+
+    unsafe {
+        asm!(
+            "mov x0, {fd}",
+            "mov x1, {accessed}",
+            "mov x2, {modified}",
+            "svc {nr_set_times}",
+            "mov {ecode}, x7",
+            fd = in(reg) fd,
+            accessed = in(reg) accessed.unwrap_or(TIME_OMIT),
+            modified = in(reg) modified.unwrap_or(TIME_OMIT),
+            nr_set_times = const NR_SET_TIMES,
+            ecode = out(reg) ecode,
+            out("x0") _,   // Clobbers x0
+            out("x1") _,   // Clobbers x1
+            out("x2") _,   // Clobbers x2
+            out("x7") _,   // Clobbers x7
+            options(nostack),
+        );
+    }
+
+    err_or!(ecode, ())
+}
+
+/// Sets the `ATTR_READ_ONLY`, `ATTR_HIDDEN`, `ATTR_SYSTEM` and
+/// `ATTR_ARCHIVE` attributes of the open file or directory `fd` to those in
+/// `attributes`.
+pub fn set_attributes(fd: usize, attributes: u64) -> OsResult<()> {
+    let mut ecode: u64;
+
+    unsafe {
+        asm!(
+            "mov x0, {fd}",
+            "mov x1, {attributes}",
+            "svc {nr_set_attributes}",
+            "mov {ecode}, x7",
+            fd = in(reg) fd,
+            attributes = in(reg) attributes,
+            nr_set_attributes = const NR_SET_ATTRIBUTES,
+            ecode = out(reg) ecode,
+            out("x0") _,   // Clobbers x0
+            out("x1") _,   // Clobbers x1
+            out("x7") _,   // Clobbers x7
+            options(nostack),
+        );
+    }
+
+    err_or!(ecode, ())
+}
+
+
 pub fn fork() -> OsResult<usize> {
     let mut ecode: u64;
     let mut pid: u64;
@@ -375,37 +750,124 @@ pub fn exec(path: &str, argv: &[&str]) -> OsResult<()> {
 }
 
 
-pub fn wait(pid: usize) -> OsResult<()> {
+/// Waits for the child `pid` to terminate, reaps it and returns how it
+/// terminated.
+pub fn wait(pid: usize) -> OsResult<ExitStatus> {
+    match waitpid(pid as isize, 0)? {
+        Some((_, status)) => Ok(status),
+        None => Err(OsError::Unknown),
+    }
+}
+
+/// Waits for a child to terminate and reaps it. `pid` selects the child to
+/// wait for; `-1` selects any child.
+///
+/// Returns the process ID of the reaped child and how it terminated. If
+/// `flags` contains `WNOHANG` and no selected child has terminated yet,
+/// returns `Ok(None)` immediately instead of blocking.
+pub fn waitpid(pid: isize, flags: u64) -> OsResult<Option<(usize, ExitStatus)>> {
     let mut ecode: u64;
+    let mut child: u64;
+    let mut code: u64;
+    let mut killed: u64;
 
     unsafe {
         asm!(
             "mov x0, {pid}",
+            "mov x1, {flags}",
             "svc {nr_wait}",
+            "mov {child}, x0",
+            "mov {code}, x1",
+            "mov {killed}, x2",
             "mov {ecode}, x7",
             pid = in(reg) pid,
+            flags = in(reg) flags,
             nr_wait = const NR_WAITPID,
+            child = out(reg) child,
+            code = out(reg) code,
+            killed = out(reg) killed,
             ecode = out(reg) ecode,
+            out("x0") _, // Clobbers x0
+            out("x1") _, // Clobbers x1
+            out("x2") _, // Clobbers x2
             out("x7") _, // Clobbers x7
             options(nostack),
         );
     }
 
-    err_or!(ecode, ())
+    // the init process (pid 0) is nobody's child, so pid 0 means "none yet"
+    let status = ExitStatus { code: code as i32, killed: killed != 0 };
+    err_or!(ecode, if child == 0 { None } else { Some((child as usize, status)) })
 }
 
 
+/// Moves the program break to `addr` and returns the resulting break.
+/// `brk(0)` returns the current break without changing it.
+pub fn brk(addr: usize) -> OsResult<usize> {
+    let mut ecode: u64;
+    let mut brk: u64;
+
+    unsafe {
+        asm!(
+            "mov x0, {addr}",
+            "svc {nr_brk}",
+            "mov {brk}, x0",
+            "mov {ecode}, x7",
+            addr = in(reg) addr,
+            nr_brk = const NR_BRK,
+            brk = out(reg) brk,
+            ecode = out(reg) ecode,
+            out("x0") _,   // Clobbers x0
+            out("x7") _,   // Clobbers x7
+            options(nostack),
+        );
+    }
+
+    err_or!(ecode, brk as usize)
+}
+
+/// Maps `len` bytes of zero-filled read-write memory and returns its address.
+pub fn mmap(len: usize) -> OsResult<usize> {
+    let mut ecode: u64;
+    let mut addr: u64;
+
+    unsafe {
+        asm!(
+            "mov x0, {len}",
+            "svc {nr_mmap}",
+            "mov {addr}, x0",
+            "mov {ecode}, x7",
+            len = in(reg) len,
+            nr_mmap = const NR_MMAP,
+            addr = out(reg) addr,
+            ecode = out(reg) ecode,
+            out("x0") _,   // Clobbers x0
+            out("x7") _,   // Clobbers x7
+            options(nostack),
+        );
+    }
+
+    err_or!(ecode, addr as usize)
+}
+
 pub fn sock_create() -> SocketDescriptor {
     
     // Lab 5 2.D
+    sock_create_with(SocketType::Tcp).unwrap_or(SocketDescriptor(u64::MAX))
+}
+
+/// Creates a socket of type `kind` and returns its descriptor.
+pub fn sock_create_with(kind: SocketType) -> OsResult<SocketDescriptor> {
     let mut ecode: u64;
     let mut sockfd: u64;
 
     unsafe {
         asm!(
+            "mov x0, {kind}",
             "svc {nr_sock_create}",
             "mov {sockfd}, x0",
             "mov {ecode}, x7",
+            kind = in(reg) kind as u64,
             nr_sock_create = const NR_SOCK_CREATE,
             sockfd = out(reg) sockfd,
             ecode = out(reg) ecode,
@@ -415,7 +877,7 @@ pub fn sock_create() -> SocketDescriptor {
         );
     }
 
-    SocketDescriptor(sockfd)
+    err_or!(ecode, SocketDescriptor(sockfd))
 }
 
 pub fn sock_status(descriptor: SocketDescriptor) -> OsResult<SocketStatus> {
@@ -481,17 +943,58 @@ pub fn sock_connect(descriptor: SocketDescriptor, addr: IpAddr) -> OsResult<()>
 }
 
 pub fn sock_listen(descriptor: SocketDescriptor, local_port: u16) -> OsResult<()> {
+    sock_listen_with(descriptor, local_port, 1)
+}
+
+/// Listens on `local_port` with the socket `descriptor`, letting up to
+/// `backlog` connections be established before `sock_accept` takes them.
+pub fn sock_listen_with(descriptor: SocketDescriptor, local_port: u16, backlog: usize) -> OsResult<()> {
     let mut ecode: u64;
     unsafe {
         asm!(
             "mov x0, {descriptor}",
             "mov x1, {local_port:x}",
+            "mov x2, {backlog}",
             "svc {nr_sock_listen}",
             "mov {ecode}, x7",
             descriptor = in(reg) descriptor.0,
             local_port = in(reg) local_port,
+            backlog = in(reg) backlog,
             nr_sock_listen = const NR_SOCK_LISTEN,
             ecode = out(reg) ecode,
+            out("x0") _,   // Clobbers x0
+            out("x1") _,   // Clobbers x1
+            out("x2") _,   // Clobbers x2
+            out("x7") _,   // Clobbers x7
+            options(nostack),
+        );
+    }
+    err_or!(ecode, ())
+}
+
+/// Sets the `SOCK_*` flags of the socket `descriptor` and how long its calls
+/// wait before failing with `OsError::IoErrorTimedOut`. Without a timeout,
+/// they wait for as long as it takes. Sockets start with no flags and no
+/// timeout.
+pub fn sock_configure(descriptor: SocketDescriptor, flags: u64, timeout: Option<Duration>) -> OsResult<()> {
+    // 0 stands for no timeout, so round shorter timeouts up to 1 ms
+    let timeout_ms = timeout.map_or(0, |timeout| timeout.as_millis().max(1) as u64);
+    let mut ecode: u64;
+    unsafe {
+        asm!(
+            "mov x0, {descriptor}",
+            "mov x1, {flags}",
+            "mov x2, {timeout_ms}",
+            "svc {nr_sock_configure}",
+            "mov {ecode}, x7",
+            descriptor = in(reg) descriptor.0,
+            flags = in(reg) flags,
+            timeout_ms = in(reg) timeout_ms,
+            nr_sock_configure = const NR_SOCK_CONFIGURE,
+            ecode = out(reg) ecode,
+            out("x0") _,   // Clobbers x0
+            out("x1") _,   // Clobbers x1
+            out("x2") _,   // Clobbers x2
             out("x7") _,   // Clobbers x7
             options(nostack),
         );
@@ -499,6 +1002,34 @@ pub fn sock_listen(descriptor: SocketDescriptor, local_port: u16) -> OsResult<()
     err_or!(ecode, ())
 }
 
+/// Takes a connection established on the listening socket `descriptor` and
+/// returns a new descriptor for it. The listening socket keeps listening.
+/// Waits for a connection unless the socket is `SOCK_NONBLOCK`, in which case
+/// it fails with `OsError::WouldBlock` if no connection is established.
+pub fn sock_accept(descriptor: SocketDescriptor) -> OsResult<SocketDescriptor> {
+    let mut ecode: u64;
+    let mut sockfd: u64;
+    unsafe {
+        asm!(
+            "mov x0, {descriptor}",
+            "svc {nr_sock_accept}",
+            "mov {sockfd}, x0",
+            "mov {ecode}, x7",
+            descriptor = in(reg) descriptor.0,
+            nr_sock_accept = const NR_SOCK_ACCEPT,
+            sockfd = out(reg) sockfd,
+            ecode = out(reg) ecode,
+            out("x0") _,   // Clobbers x0
+            out("x7") _,   // Clobbers x7
+            options(nostack),
+        );
+    }
+    err_or!(ecode, SocketDescriptor(sockfd))
+}
+
+/// Sends bytes of `buf` on the TCP socket `descriptor` and returns how many.
+/// Waits until some can be sent, or the connection closes, unless the socket
+/// is `SOCK_NONBLOCK`, in which case it may return 0.
 pub fn sock_send(descriptor: SocketDescriptor, buf: &[u8]) -> OsResult<usize> {
     let mut ecode: u64;
     let mut bytes_sent: u64;
@@ -524,6 +1055,9 @@ pub fn sock_send(descriptor: SocketDescriptor, buf: &[u8]) -> OsResult<usize> {
     err_or!(ecode, bytes_sent as usize)
 }
 
+/// Receives bytes from the TCP socket `descriptor` into `buf` and returns
+/// how many. Waits until some arrive, or the connection closes, unless the
+/// socket is `SOCK_NONBLOCK`, in which case it may return 0.
 pub fn sock_recv(descriptor: SocketDescriptor, buf: &mut [u8]) -> OsResult<usize> {
     let mut ecode: u64;
     let mut bytes_received: u64;
@@ -548,3 +1082,96 @@ pub fn sock_recv(descriptor: SocketDescriptor, buf: &mut [u8]) -> OsResult<usize
     }
     err_or!(ecode, bytes_received as usize)
 }
+
+/// Binds the UDP socket `descriptor` to `local_port` on every local address.
+/// Port 0 picks a free ephemeral port.
+pub fn sock_bind(descriptor: SocketDescriptor, local_port: u16) -> OsResult<()> {
+    let mut ecode: u64;
+    unsafe {
+        asm!(
+            "mov x0, {descriptor}",
+            "mov x1, {local_port:x}",
+            "svc {nr_sock_bind}",
+            "mov {ecode}, x7",
+            descriptor = in(reg) descriptor.0,
+            local_port = in(reg) local_port,
+            nr_sock_bind = const NR_SOCK_BIND,
+            ecode = out(reg) ecode,
+            out("x7") _,   // Clobbers x7
+            options(nostack),
+        );
+    }
+    err_or!(ecode, ())
+}
+
+/// Queues `buf` as one datagram to `addr` on the UDP socket `descriptor`,
+/// binding the socket to an ephemeral port first if it is not bound.
+pub fn sock_sendto(descriptor: SocketDescriptor, buf: &[u8], addr: IpAddr) -> OsResult<usize> {
+    let mut ecode: u64;
+    let mut bytes_sent: u64;
+    unsafe {
+        asm!(
+            "mov x0, {descriptor}",
+            "mov x1, {buf_addr}",
+            "mov x2, {buf_len}",
+            "mov x3, {addr:x}",
+            "mov x4, {port:x}",
+            "svc {nr_sock_sendto}",
+            "mov {bytes_sent}, x0",
+            "mov {ecode}, x7",
+            descriptor = in(reg) descriptor.0,
+            buf_addr = in(reg) buf.as_ptr(),
+            buf_len = in(reg) buf.len(),
+            addr = in(reg) addr.ip,
+            port = in(reg) addr.port,
+            nr_sock_sendto = const NR_SOCK_SENDTO,
+            bytes_sent = out(reg) bytes_sent,
+            ecode = out(reg) ecode,
+            out("x0") _,   // Clobbers x0
+            out("x1") _,   // Clobbers x1
+            out("x2") _,   // Clobbers x2
+            out("x3") _,   // Clobbers x3
+            out("x4") _,   // Clobbers x4
+            out("x7") _,   // Clobbers x7
+            options(nostack),
+        );
+    }
+    err_or!(ecode, bytes_sent as usize)
+}
+
+/// Dequeues one datagram from the UDP socket `descriptor` into `buf` and
+/// returns its length and sender. The rest of a datagram longer than `buf`
+/// is dropped. Waits for a datagram unless the socket is `SOCK_NONBLOCK`, in
+/// which case it fails with `OsError::WouldBlock` if none is queued.
+pub fn sock_recvfrom(descriptor: SocketDescriptor, buf: &mut [u8]) -> OsResult<(usize, IpAddr)> {
+    let mut ecode: u64;
+    let mut bytes_received: u64;
+    let mut ip: u64;
+    let mut port: u64;
+    unsafe {
+        asm!(
+            "mov x0, {descriptor}",
+            "mov x1, {buf_addr}",
+            "mov x2, {buf_len}",
+            "svc {nr_sock_recvfrom}",
+            "mov {bytes_received}, x0",
+            "mov {ip}, x1",
+            "mov {port}, x2",
+            "mov {ecode}, x7",
+            descriptor = in(reg) descriptor.0,
+            buf_addr = in(reg) buf.as_mut_ptr(),
+            buf_len = in(reg) buf.len(),
+            nr_sock_recvfrom = const NR_SOCK_RECVFROM,
+            bytes_received = out(reg) bytes_received,
+            ip = out(reg) ip,
+            port = out(reg) port,
+            ecode = out(reg) ecode,
+            out("x0") _,   // Clobbers x0
+            out("x1") _,   // Clobbers x1
+            out("x2") _,   // Clobbers x2
+            out("x7") _,   // Clobbers x7
+            options(nostack),
+        );
+    }
+    err_or!(ecode, (bytes_received as usize, IpAddr { ip: ip as u32, port: port as u16 }))
+}
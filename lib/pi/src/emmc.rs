@@ -0,0 +1,172 @@
+use core::time::Duration;
+
+use volatile::prelude::*;
+use volatile::{ReadVolatile, Reserved, Volatile};
+
+use crate::common::IO_BASE;
+use crate::timer;
+
+/// The base address for the EMMC (SD host controller) registers.
+/// The Physical (hardware) base address for the EMMC is 0x7E300000.
+const EMMC_REG_BASE: usize = IO_BASE + 0x300000;
+
+/// How long a command or a data transfer may take before it is abandoned.
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+/// `STATUS` bits
+const SR_CMD_INHIBIT: u32 = 1 << 0;
+const SR_DAT_INHIBIT: u32 = 1 << 1;
+
+/// `INTERRUPT` bits
+const INT_CMD_DONE: u32 = 1 << 0;
+const INT_DATA_DONE: u32 = 1 << 1;
+const INT_WRITE_RDY: u32 = 1 << 4;
+const INT_READ_RDY: u32 = 1 << 5;
+const INT_CMD_TIMEOUT: u32 = 1 << 16;
+const INT_DATA_TIMEOUT: u32 = 1 << 20;
+const INT_ERROR_MASK: u32 = 0x017E_8000;
+
+/// `CMDTM` encodings (command index, response type, data transfer) of the
+/// commands issued by this driver (ref: BCM2835 ARM Peripherals, 5.4).
+const CMD_READ_MULTIPLE: u32 = 0x1222_0036; // CMD18, R1, multi-block card-to-host, auto CMD12
+const CMD_WRITE_SINGLE: u32 = 0x1822_0000; // CMD24, R1, data host-to-card
+
+/// The size of a block transferred by `read_blocks()` and `write_block()`.
+pub const BLOCK_SIZE: usize = 512;
+
+/// The most blocks `read_blocks()` transfers at once.
+pub const MAX_BLOCKS: usize = 0xFFFF;
+
+#[repr(C)]
+#[allow(non_snake_case)]
+struct Registers {
+    __r0: Reserved<u32>, // ARG2
+    BLKSIZECNT: Volatile<u32>,
+    ARG1: Volatile<u32>,
+    CMDTM: Volatile<u32>,
+    __r1: [Reserved<u32>; 4], // RESP0-RESP3
+    DATA: Volatile<u32>,
+    STATUS: ReadVolatile<u32>,
+    __r2: [Reserved<u32>; 2], // CONTROL0, CONTROL1
+    INTERRUPT: Volatile<u32>,
+}
+
+/// An error reported by the EMMC controller.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Error {
+    /// The controller or the card did not respond in time.
+    TimedOut,
+    /// The controller flagged an error; holds the `INTERRUPT` register.
+    Failed(u32),
+}
+
+/// The Raspberry Pi EMMC controller, driving an SD card that has already been
+/// brought to the transfer state (e.g. by the `sd_init` C driver).
+pub struct Emmc {
+    registers: &'static mut Registers,
+}
+
+impl Emmc {
+    /// Returns a new instance of `Emmc`.
+    ///
+    /// # Safety
+    ///
+    /// The controller and the card must have been initialized, and no other
+    /// code may use the controller while the returned instance is in use.
+    pub unsafe fn new() -> Emmc {
+        Emmc {
+            registers: &mut *(EMMC_REG_BASE as *mut Registers),
+        }
+    }
+
+    /// Reads `buf.len() / BLOCK_SIZE` consecutive blocks from the card into
+    /// `buf` with a single multi-block transfer, starting at address `addr`,
+    /// which is a block number or a byte offset depending on how the card is
+    /// addressed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `buf` is empty, if its length is not a multiple of
+    /// `BLOCK_SIZE` or if it holds more than `MAX_BLOCKS` blocks.
+    pub fn read_blocks(&mut self, addr: u32, buf: &mut [u8]) -> Result<(), Error> {
+        let blocks = buf.len() / BLOCK_SIZE;
+        assert!(blocks > 0 && blocks <= MAX_BLOCKS && buf.len() % BLOCK_SIZE == 0);
+
+        self.wait_status(SR_DAT_INHIBIT)?;
+        self.registers.BLKSIZECNT.write(((blocks as u32) << 16) | BLOCK_SIZE as u32);
+        self.command(CMD_READ_MULTIPLE, addr)?;
+
+        for block in buf.chunks_exact_mut(BLOCK_SIZE) {
+            self.wait_interrupt(INT_READ_RDY)?;
+            for word in block.chunks_exact_mut(4) {
+                word.copy_from_slice(&self.registers.DATA.read().to_le_bytes());
+            }
+        }
+        self.wait_interrupt(INT_DATA_DONE)
+    }
+
+    /// Writes `buf` to the card at address `addr`, which is a block number or
+    /// a byte offset depending on how the card is addressed.
+    pub fn write_block(&mut self, addr: u32, buf: &[u8; BLOCK_SIZE]) -> Result<(), Error> {
+        self.wait_status(SR_DAT_INHIBIT)?;
+        self.registers.BLKSIZECNT.write((1 << 16) | BLOCK_SIZE as u32);
+        self.command(CMD_WRITE_SINGLE, addr)?;
+
+        self.wait_interrupt(INT_WRITE_RDY)?;
+        for word in buf.chunks_exact(4) {
+            self.registers.DATA.write(u32::from_le_bytes([word[0], word[1], word[2], word[3]]));
+        }
+        self.wait_interrupt(INT_DATA_DONE)
+    }
+
+    /// Issues the command `cmdtm` with argument `arg` and waits for it to
+    /// complete.
+    fn command(&mut self, cmdtm: u32, arg: u32) -> Result<(), Error> {
+        self.wait_status(SR_CMD_INHIBIT)?;
+
+        // acknowledge stale interrupts before issuing the command
+        let pending = self.registers.INTERRUPT.read();
+        self.registers.INTERRUPT.write(pending);
+        self.registers.ARG1.write(arg);
+        self.registers.CMDTM.write(cmdtm);
+
+        self.wait_interrupt(INT_CMD_DONE)
+    }
+
+    /// Waits until none of the `mask` bits is set in `STATUS`.
+    fn wait_status(&self, mask: u32) -> Result<(), Error> {
+        let deadline = timer::current_time() + TIMEOUT;
+        while self.registers.STATUS.read() & mask != 0 {
+            if timer::current_time() > deadline {
+                return Err(Error::TimedOut);
+            }
+        }
+        Ok(())
+    }
+
+    /// Waits for one of the `mask` interrupts (or an error) and acknowledges
+    /// it.
+    fn wait_interrupt(&mut self, mask: u32) -> Result<(), Error> {
+        let deadline = timer::current_time() + TIMEOUT;
+        let status = loop {
+            let status = self.registers.INTERRUPT.read();
+            if status & (mask | INT_ERROR_MASK) != 0 {
+                break status;
+            }
+            if timer::current_time() > deadline {
+                return Err(Error::TimedOut);
+            }
+        };
+
+        if status & (INT_CMD_TIMEOUT | INT_DATA_TIMEOUT) != 0 {
+            self.registers.INTERRUPT.write(status);
+            Err(Error::TimedOut)
+        } else if status & INT_ERROR_MASK != 0 {
+            self.registers.INTERRUPT.write(status);
+            Err(Error::Failed(status))
+        } else {
+            self.registers.INTERRUPT.write(mask);
+            Ok(())
+        }
+    }
+}
@@ -5,6 +5,7 @@
 
 pub mod atags;
 pub mod common;
+pub mod emmc;
 pub mod gpio;
 pub mod interrupt;
 pub mod local_interrupt;
@@ -1,47 +1,19 @@
 #!/bin/bash -e
 
 IMG=fs.img
-MNT=mnt
-CACHE=cache
-PROGS=($(ls code/build/*.bin | xargs -n 1 basename | sed 's/.bin//'))
-
-# Remove existing image
-rm -f $IMG
-
-# Create the image file
-dd if=/dev/zero of=$IMG bs=1m count=128
-
-# Attach the image and capture the device
-DEVICE=$(hdiutil attach -imagekey diskimage-class=CRawDiskImage -nomount $IMG | awk '{print $1}' | tr -d '[:space:]')
-
-echo "Attached device: $DEVICE"
-
-# Ensure cleanup on exit
-trap "sudo umount $MNT; hdiutil detach $DEVICE; rm -rf $MNT" EXIT
+FATIMG="cargo run --quiet --release --manifest-path ../lib/fatimg/Cargo.toml --"
 
-# Partition the disk and format it as FAT32
-diskutil partitionDisk $DEVICE 1 MBR FAT32 "PARTITION" R
-
-# Mount the partition
-PARTITION="${DEVICE}s1"
-sudo diskutil unmount $PARTITION
-mkdir -p $MNT
-sudo mount -t msdos $PARTITION $MNT
-
-# Create the cache directory
-mkdir -p $CACHE
+# Build the binaries first so that the list of programs is complete
+make -C code
+PROGS=($(ls code/build/*.bin | xargs -n 1 basename | sed 's/.bin//'))
 
-# Create the programs directory on the mounted partition
-mkdir -p $MNT/programs
+# Create the image with an empty FAT32 partition
+$FATIMG $IMG mkfs 128M
 
-# Build the binaries and copy them to the mounted partition
-make -C code
+# Copy the binaries and their sources to the programs directory
+$FATIMG $IMG mkdir /programs
 for prog in "${PROGS[@]}"; do
-    echo "Copying $prog.bin to $MNT/programs/"
-    sudo cp code/build/$prog.bin $CACHE/$prog.bin
-    sudo cp code/src/bin/$prog.rs $CACHE/$prog.rs
-
-    sudo cp $CACHE/$prog.bin $MNT/programs/$prog.bin
-    sudo cp $CACHE/$prog.rs $MNT/programs/$prog.rs
-    
+    echo "Copying $prog.bin to /programs/"
+    $FATIMG $IMG cp-in code/build/$prog.bin /programs/$prog.bin
+    $FATIMG $IMG cp-in code/src/bin/$prog.rs /programs/$prog.rs
 done
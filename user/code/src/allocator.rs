@@ -8,6 +8,7 @@ use core::fmt;
 use spin::Mutex;
 
 use crate::uprintln;
+use kernel_api::syscall;
 /// Thread-safe (locking) wrapper around a particular memory allocator.
 pub struct Allocator(Mutex<Option<AllocatorImpl>>);
 
@@ -24,29 +25,40 @@ impl Allocator {
     /// The caller should assure that the method is invoked only once during the
     /// kernel initialization.
     ///
-    /// # Panics
-    ///
-    /// Panics if the system's memory map could not be retrieved.
+    /// The heap is zero-filled by the kernel when its pages are first touched.
     pub unsafe fn initialize(&self, start: usize, end: usize) {
-        for i in start..=end {
-            let ptr = i as *mut u8;
-            unsafe {
-                *ptr = 0;
-            }
-        }
         *self.0.lock() = Some(AllocatorImpl::new(start, end));
     }
 }
 
+/// Grows the heap with `brk` so that `allocator` can serve `layout`.
+/// Returns `false` if the kernel refused to grow the heap.
+fn grow_heap(allocator: &mut AllocatorImpl, layout: Layout) -> bool {
+    // bins hand out power-of-two blocks, which may also need to be aligned
+    let needed = layout.size().next_power_of_two() + layout.align();
+    let end = match syscall::brk(0) {
+        Ok(end) => end,
+        Err(_) => return false,
+    };
+    match syscall::brk(end + align_up(needed, USER_HEAP_GROW_SIZE)) {
+        Ok(new_end) => {
+            allocator.grow(new_end);
+            true
+        }
+        Err(_) => false,
+    }
+}
+
 unsafe impl GlobalAlloc for Allocator {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         let aligned_layout = Layout::from_size_align(layout.size(), layout.align().max(4))
             .expect("Invalid layout for allocation");
-        let ptr = self.0
-            .lock()
-            .as_mut()
-            .expect("allocator uninitialized")
-            .alloc(aligned_layout);
+        let mut guard = self.0.lock();
+        let allocator = guard.as_mut().expect("allocator uninitialized");
+        let mut ptr = allocator.alloc(aligned_layout);
+        if ptr.is_null() && grow_heap(allocator, aligned_layout) {
+            ptr = allocator.alloc(aligned_layout);
+        }
         //uprintln!("alloc {:x}, {:#?}", ptr as u64, layout);
         ptr
     }
@@ -78,21 +90,24 @@ impl fmt::Debug for Allocator {
 #[global_allocator]
 pub static ALLOCATOR: Allocator = Allocator::uninitialized();
 
-extern "C" {
-    static __text_end: u8;
-}
 const PAGE_SIZE : usize = 64 * 1024;
-const USER_IMG_BASE : usize = 0xffff_ffff_c000_0000;
 const USER_HEAP_PAGES : usize = 16;
+/// The heap grows in steps of at least this many bytes.
+const USER_HEAP_GROW_SIZE : usize = USER_HEAP_PAGES * PAGE_SIZE;
 
 
-/// Returns the (start address, end address) of the available memory on this
-/// system if it can be determined. If it cannot, `None` is returned.
+/// Returns the (start address, end address) of the initial heap.
+///
+/// The heap starts right after the program image, where the kernel placed the
+/// program break, and is grown with `brk` whenever the allocator runs out of
+/// memory.
+///
+/// # Panics
 ///
-/// This function is expected to return `Some` under all normal cirumstances.
+/// Panics if the kernel refuses to set up the initial heap.
 pub fn memory_map() -> (usize, usize) {
-    let mut binary_end = unsafe { (&__text_end as *const u8) as usize };
-    binary_end = align_up(binary_end, PAGE_SIZE);
-    (binary_end, binary_end + USER_HEAP_PAGES*PAGE_SIZE - 1)
+    let start = syscall::brk(0).expect("failed to query the program break");
+    let end = syscall::brk(start + USER_HEAP_GROW_SIZE).expect("failed to set up the heap");
+    (start, end)
 }
 
@@ -67,7 +67,7 @@ fn main_inner() -> OsResult<!> {
             // Check if the message is "exit" to terminate the server
             if message.trim() == "exit" {
                 println!("Exiting echo server...");
-                exit();
+                exit(0);
             }
         }
     }
@@ -9,18 +9,19 @@ use kernel_api::*;
 fn main(argc: usize, argv_ptr: *const *const u8) {
     // Fork chain of 10 processes recursively:
     fork_chain(10);
+    fork_read();
 }
 
 fn fork_chain(n: usize) {
     if n == 0 {
-        syscall::exit();
+        syscall::exit(0);
     }
 
     let pid = syscall::fork().unwrap();
     if pid == 0 {
         // Child process
         fork_chain(n - 1);
-        syscall::exit();
+        syscall::exit(0);
     } else if pid > 0 {
         // Parent process
         let _ = syscall::wait(pid);
@@ -28,4 +29,31 @@ fn fork_chain(n: usize) {
         // Fork failed
         panic!("Fork failed");
     }
-}
\ No newline at end of file
+}
+const PATH: &str = "/tmp/fork-read";
+const MESSAGE: &[u8] = b"read into a copy-on-write page";
+
+/// Has a forked child read a file into a buffer it shares copy-on-write with
+/// the parent, after touching the buffer so that a read-only translation of it
+/// is cached. The kernel's write must see the resolved, writable page.
+fn fork_read() {
+    let fd = syscall::open_with(PATH, O_CREAT | O_TRUNC).unwrap();
+    syscall::write(fd, MESSAGE).unwrap();
+    syscall::close(fd).unwrap();
+
+    let mut buf = [0u8; MESSAGE.len()];
+    let pid = syscall::fork().unwrap();
+    if pid == 0 {
+        core::hint::black_box(&buf);
+        let fd = syscall::open(PATH).unwrap();
+        let read = syscall::read(fd, &mut buf).unwrap();
+        syscall::exit(if read == MESSAGE.len() && buf == MESSAGE { 0 } else { 1 });
+    }
+
+    let status = syscall::wait(pid).unwrap();
+    let _ = syscall::unlink(PATH);
+    if !status.success() || buf != [0u8; MESSAGE.len()] {
+        println!("fork: read into a copy-on-write page failed: {:?}", status);
+        syscall::exit(1);
+    }
+}
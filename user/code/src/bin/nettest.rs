@@ -0,0 +1,105 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use core::time::Duration;
+
+use user::*;
+
+use kernel_api::{syscall, IpAddr, OsError, OsResult, SocketDescriptor};
+
+const PORT: u16 = 8080;
+const MESSAGE: &[u8] = b"ping over loopback";
+/// The number of connections the client opens at once
+const CLIENTS: usize = 2;
+/// How long any socket call may wait
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Forks a TCP echo server and a client that opens several connections to it
+/// over 127.0.0.1, and checks that each connection gets its message back.
+#[no_mangle]
+fn main(argc: usize, argv_ptr: *const *const u8) {
+    match syscall::fork() {
+        Ok(0) => {
+            let result = server();
+            if result.is_err() {
+                println!("server: {:?}", result);
+                syscall::exit(1);
+            }
+            syscall::exit(0);
+        }
+        Ok(pid) => {
+            let result = client();
+            let server = syscall::wait(pid);
+            match (result, server) {
+                (Ok(true), Ok(status)) if status.success() => println!("nettest: ok"),
+                (result, server) => {
+                    println!("nettest: failed, client {:?}, server {:?}", result, server);
+                    syscall::exit(1);
+                }
+            }
+        }
+        Err(e) => println!("nettest: fork failed: {:?}", e),
+    }
+}
+
+/// Receives into `buf` until it is full.
+fn recv_exact(sock: SocketDescriptor, buf: &mut [u8]) -> OsResult<()> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match syscall::sock_recv(sock, &mut buf[filled..])? {
+            0 => return Err(OsError::IoErrorEof),
+            bytes => filled += bytes,
+        }
+    }
+    Ok(())
+}
+
+/// Sends all of `buf`.
+fn send_all(sock: SocketDescriptor, buf: &[u8]) -> OsResult<()> {
+    let mut sent = 0;
+    while sent < buf.len() {
+        sent += syscall::sock_send(sock, &buf[sent..])?;
+    }
+    Ok(())
+}
+
+fn server() -> OsResult<()> {
+    let sock = syscall::sock_create();
+    syscall::sock_configure(sock, 0, Some(TIMEOUT))?;
+    syscall::sock_listen_with(sock, PORT, CLIENTS)?;
+    for _ in 0..CLIENTS {
+        let connection = syscall::sock_accept(sock)?;
+        syscall::sock_configure(connection, 0, Some(TIMEOUT))?;
+        let mut buf = [0u8; MESSAGE.len()];
+        recv_exact(connection, &mut buf)?;
+        send_all(connection, &buf)?;
+    }
+    Ok(())
+}
+
+fn client() -> OsResult<bool> {
+    // Give the server time to listen
+    syscall::sleep(Duration::from_millis(200))?;
+
+    // Connect all at once, so that the connections wait in the backlog
+    let mut socks = Vec::new();
+    for _ in 0..CLIENTS {
+        let sock = syscall::sock_create();
+        syscall::sock_configure(sock, 0, Some(TIMEOUT))?;
+        syscall::sock_connect(sock, IpAddr::new((127, 0, 0, 1), PORT))?;
+        socks.push(sock);
+    }
+    for sock in socks {
+        // Sending waits for the connection to be established
+        send_all(sock, MESSAGE)?;
+        let mut buf = [0u8; MESSAGE.len()];
+        recv_exact(sock, &mut buf)?;
+        if buf != MESSAGE {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
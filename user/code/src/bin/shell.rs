@@ -2,7 +2,7 @@
 #![no_main]
 
 use user::*;
-use kernel_api::syscall;
+use kernel_api::{syscall, Stat, ATTR_ARCHIVE, ATTR_HIDDEN, ATTR_READ_ONLY, ATTR_SYSTEM, O_CREAT, O_TRUNC, WNOHANG};
 use alloc::vec::Vec;
 use alloc::string::{String, ToString};
 use crate::alloc::format;
@@ -22,43 +22,56 @@ const WELCOME_TXT: &str = r#"
 
 
 
-fn normalize_path(path: &str) -> String {
-    let mut components = Vec::new();
-
-    for part in path.split('/') {
-        match part {
-            "" | "." => continue, // Ignore empty components & `.`
-            ".." => { components.pop(); } // Move up a directory
-            _ => components.push(part),
-        }
-    }
+/// Returns the working directory, which the kernel resolves relative paths
+/// against.
+fn cwd() -> String {
+    let mut buf = [0u8; 256];
+    syscall::getcwd(&mut buf).unwrap_or(ROOT_NAME).to_string()
+}
 
-    if components.is_empty() {
-        "/".to_string() // If empty, return root
-    } else {
-        format!("/{}", components.join("/")) // Rebuild path
+/// Formats the Unix time `secs` as `YYYY-MM-DD HH:MM`.
+fn format_time(secs: u64) -> String {
+    if secs == 0 {
+        return "-".to_string();
     }
+    // Civil date from days since 1970-01-01, counting years from March
+    let days = secs / 86_400 + 719_468;
+    let (era, day_of_era) = (days / 146_097, days % 146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let (year, month) = if month < 10 { (era * 400 + year_of_era, month + 3) } else { (era * 400 + year_of_era + 1, month - 9) };
+    format!("{:04}-{:02}-{:02} {:02}:{:02}", year, month, day, secs / 3600 % 24, secs / 60 % 60)
 }
 
-
+/// Formats `stat` as a line of `ls -l`: the kind and attributes of the
+/// entry, its size and its modification time.
+fn format_stat(stat: &Stat) -> String {
+    let flag = |bit, c| if stat.attributes & bit != 0 { c } else { '-' };
+    format!(
+        "{}{}{}{}{} {:>10} {}",
+        if stat.is_dir() { 'd' } else { '-' },
+        flag(ATTR_READ_ONLY, 'r'),
+        flag(ATTR_HIDDEN, 'h'),
+        flag(ATTR_SYSTEM, 's'),
+        flag(ATTR_ARCHIVE, 'a'),
+        stat.size,
+        format_time(stat.modified),
+    )
+}
 
 #[no_mangle]
 fn main(argc: usize, argv_ptr: *const *const u8) {
     println!("{}", WELCOME_TXT);
 
-    let mut pwd = ROOT_NAME.to_string();
-    let mut open_dirs: Vec<(String, usize)> = Vec::new(); // Cache opened directories
-
-    // Open the root directory and cache its file descriptor
-    if let Ok(fd) = syscall::open(ROOT_NAME) {
-        open_dirs.push((pwd.clone(), fd));
-    } else {
-        println!("Error: Could not open root directory");
-        return;
-    }
-
     loop {
-        print!("({}) $ ", pwd.to_uppercase());
+        // reap background and orphaned children that have terminated
+        while let Ok(Some((pid, status))) = syscall::waitpid(-1, WNOHANG) {
+            trace!("reaped child process {} ({:?})", pid, status);
+        }
+
+        print!("({}) $ ", cwd().to_uppercase());
         let mut line = Vec::new();
 
         loop {
@@ -94,84 +107,60 @@ fn main(argc: usize, argv_ptr: *const *const u8) {
 
         match args[0] {
             "echo" => {
-                println!("{}", args.iter().skip(1).cloned().collect::<Vec<_>>().join(" "));
+                // `echo words > file` writes the words to `file` instead
+                match args.iter().position(|&arg| arg == ">") {
+                    Some(i) if i + 2 == args.len() => {
+                        let text = format!("{}\n", args[1..i].join(" "));
+                        let result = syscall::open_with(args[i + 1], O_CREAT | O_TRUNC)
+                            .and_then(|fd| {
+                                let written = syscall::write(fd, text.as_bytes());
+                                let _ = syscall::close(fd);
+                                written
+                            });
+                        if let Err(e) = result {
+                            println!("error: cannot write {}: {:?}", args[i + 1].to_uppercase(), e);
+                        }
+                    }
+                    Some(_) => println!("usage: echo [words...] [> file]"),
+                    None => println!("{}", args.iter().skip(1).cloned().collect::<Vec<_>>().join(" ")),
+                }
             }
             "pwd" => {
-                println!("{}", pwd.to_uppercase());
+                println!("{}", cwd().to_uppercase());
             }
             "cd" => {
-                if args.len() > 1 {
-                    let target = args[1];
-
-                    let new_path = if target == ".." {
-                        // If already at root, stay at root
-                        if pwd == "/" {
-                            "/".to_string()
-                        } else {
-                            // Split by `/`, remove last component, and reconstruct
-                            let mut components: Vec<&str> = pwd.split('/').filter(|c| !c.is_empty()).collect();
-                            components.pop(); // Remove last directory
-                            if components.is_empty() {
-                                "/".to_string() // If empty, stay at root
-                            } else {
-                                format!("/{}", components.join("/"))
-                            }
-                        }
-                    } else if target.starts_with('/') {
-                        // Absolute path
-                        target.to_string()
-                    } else {
-                        // Relative path
-                        format!("{}/{}", pwd.trim_end_matches('/'), target.trim_start_matches('/'))
-                    };
-
-                    // Normalize redundant `..` and `.` (like real UNIX systems)
-                    let normalized_path = normalize_path(&new_path);
-
-                    let fd = syscall::open(&normalized_path);
-                    if let Ok(fd) = fd {
-                        // If it's a directory, update `pwd` and cache it
-                        pwd = normalized_path;
-                        open_dirs.push((pwd.clone(), fd));
-                    } else {
-                        println!("error: directory {} not found", target.to_uppercase());
-                    }
+                let target = args.get(1).copied().unwrap_or(ROOT_NAME);
+                if syscall::chdir(target).is_err() {
+                    println!("error: directory {} not found", target.to_uppercase());
                 }
             }
 
             "ls" => {
-                let path = if args.len() > 1 {
-                    let p = args[1];
-                    if p.starts_with('/') {
-                        p.to_string() // Absolute path
-                    } else {
-                        format!("{}/{}", pwd.trim_end_matches('/'), p.trim_start_matches('/')) // Relative path
-                    }
-                } else {
-                    pwd.clone()
-                };
-
-                // Check if the directory is already open
-                let fd = open_dirs.iter().find(|(p, _)| p == &path).map(|(_, fd)| *fd)
-                    .or_else(|| syscall::open(&path).ok());
+                let long = args.get(1) == Some(&"-l");
+                let args: Vec<&str> = if long { args[1..].to_vec() } else { args.clone() };
+                let path = args.get(1).copied().unwrap_or(".");
 
-                if let Some(fd) = fd {
+                if let Ok(fd) = syscall::open(path) {
                     let mut buf = [0u8; 512];
                     let len = syscall::readdir(fd, &mut buf).unwrap_or_else(|_| {
                         println!("error: failed to read directory {}", path.to_uppercase());
                         0
                     });
+                    let _ = syscall::close(fd);
 
-                    if len > 0 {
-                        println!("{}", core::str::from_utf8(&buf[..len]).unwrap_or("error reading dir"));
+                    let names = core::str::from_utf8(&buf[..len]).unwrap_or("error reading dir");
+                    if len > 0 && long {
+                        for name in names.lines() {
+                            match syscall::stat(&format!("{}/{}", path, name)) {
+                                Ok(stat) => println!("{} {}", format_stat(&stat), name),
+                                Err(e) => println!("error: cannot stat {}: {:?}", name.to_uppercase(), e),
+                            }
+                        }
+                    } else if len > 0 {
+                        println!("{}", names);
                     } else {
                         println!("error: directory {} is empty or could not be read", path.to_uppercase());
                     }
-
-                    // Cache the directory if it wasn't already cached
-                    if open_dirs.iter().all(|(p, _)| p != &path) {
-                        open_dirs.push((path.clone(), fd));
-                    }
                 } else {
                     println!("error: directory {} not found", path.to_uppercase());
                 }
@@ -179,18 +168,78 @@ fn main(argc: usize, argv_ptr: *const *const u8) {
 
             "cat" => {
                 for file in args.iter().skip(1) {
-                    let file_path = format!("{}/{}", pwd, file);
-                    if let Ok(fd) = syscall::open(&file_path) {
+                    if let Ok(fd) = syscall::open(file) {
                         let mut buf = [0u8; 512];
                         let len = syscall::read(fd, &mut buf).unwrap_or(0);
+                        let _ = syscall::close(fd);
                         println!("{}", core::str::from_utf8(&buf[..len]).unwrap_or("error reading file"));
                     } else {
                         println!("error: file {} not found", file.to_uppercase());
                     }
                 }
             }
+            "touch" => {
+                for file in args.iter().skip(1) {
+                    match syscall::open_with(file, O_CREAT) {
+                        Ok(fd) => {
+                            let _ = syscall::close(fd);
+                        }
+                        Err(e) => println!("error: cannot create {}: {:?}", file.to_uppercase(), e),
+                    }
+                }
+            }
+            "rm" => {
+                for file in args.iter().skip(1) {
+                    if let Err(e) = syscall::unlink(file) {
+                        println!("error: cannot remove {}: {:?}", file.to_uppercase(), e);
+                    }
+                }
+            }
+            "mkdir" => {
+                for dir in args.iter().skip(1) {
+                    if let Err(e) = syscall::mkdir(dir) {
+                        println!("error: cannot create directory {}: {:?}", dir.to_uppercase(), e);
+                    }
+                }
+            }
+            "rmdir" => {
+                for dir in args.iter().skip(1) {
+                    if let Err(e) = syscall::rmdir(dir) {
+                        println!("error: cannot remove directory {}: {:?}", dir.to_uppercase(), e);
+                    }
+                }
+            }
+            "mv" => {
+                if args.len() == 3 {
+                    if let Err(e) = syscall::rename(args[1], args[2]) {
+                        println!("error: cannot move {} to {}: {:?}", args[1].to_uppercase(), args[2].to_uppercase(), e);
+                    }
+                } else {
+                    println!("usage: mv <source> <destination>");
+                }
+            }
+            "date" => {
+                // `date <secs>` sets the clock to a Unix time first
+                let set = match args.get(1).map(|secs| secs.parse::<u64>()) {
+                    Some(Ok(secs)) => syscall::set_wall_time(core::time::Duration::from_secs(secs)),
+                    Some(Err(_)) => {
+                        println!("usage: date [unix seconds]");
+                        continue;
+                    }
+                    None => Ok(()),
+                };
+                match set.and_then(|_| syscall::wall_time()) {
+                    Ok(now) => println!("{}", format_time(now.as_secs())),
+                    Err(e) => println!("error: cannot set the clock: {:?}", e),
+                }
+            }
+            "sync" => {
+                if let Err(e) = syscall::sync() {
+                    println!("error: sync failed: {:?}", e);
+                }
+            }
             "exit" => {
-                syscall::exit();
+                syscall::exit(0);
             }
             "sleep" => {
                 if args.len() == 2 {
@@ -201,14 +250,10 @@ fn main(argc: usize, argv_ptr: *const *const u8) {
                 }
             }
             _ if args[0].starts_with("./") || args[0].starts_with("/") => {
-                let mut path = args[0].to_string();
-                
-                // Prepend current directory if path is relative
-                if !path.starts_with("/") {
-                    let cleaned_path = path.trim_start_matches("./");
-                    path = format!("{}/{}", pwd.trim_end_matches('/'), cleaned_path);
-                }
-            
+                // The kernel resolves relative paths against the working
+                // directory, which the child inherits
+                let path = args[0];
+
                 let pid = syscall::fork();
                 trace!("forked with pid: {:?}", pid);
             
@@ -225,14 +270,21 @@ fn main(argc: usize, argv_ptr: *const *const u8) {
     
                         if syscall::exec(&path, &argv_refs).is_err() {
                             println!("error: failed to execute {}", path);
-                            syscall::exit();
+                            syscall::exit(127);
                         }
                     }
                     Ok(pid) => {
                         trace!("created child process with PID {}", pid);
                         // TODO: add child descriptors, wait, etc.
                         info!("waiting for child process with PID {}", pid);
-                        let _ = syscall::wait(pid);
+                        match syscall::wait(pid) {
+                            Ok(status) if status.killed => println!("process {} killed", pid),
+                            Ok(status) if !status.success() => {
+                                println!("process {} exited with status {}", pid, status.code)
+                            }
+                            Ok(_) => {}
+                            Err(e) => println!("error: wait failed: {:?}", e),
+                        }
                     }
                     Err(_) => {
                         println!("error: fork failed");
@@ -0,0 +1,28 @@
+#![feature(never_type)]
+#![no_std]
+#![no_main]
+
+use user::*;
+
+use kernel_api::{syscall::{sock_bind, sock_create_with, sock_recvfrom, sock_sendto}, OsResult, SocketType};
+
+#[no_mangle]
+fn main(argc: usize, argv_ptr: *const *const u8) {
+    let result = main_inner();
+    if result.is_err() {
+        println!("Terminating with error: {:?}", result);
+    }
+}
+
+fn main_inner() -> OsResult<!> {
+    let sock = sock_create_with(SocketType::Udp)?;
+    sock_bind(sock, 7)?;
+    println!("Echoing datagrams on UDP port 7");
+    loop {
+        let mut buffer = [0u8; 1024];
+        let (bytes_read, peer) = sock_recvfrom(sock, &mut buffer)?;
+        let message = core::str::from_utf8(&buffer[..bytes_read]).unwrap_or("Invalid UTF-8");
+        println!("Received from {:?}: {}", peer, message);
+        sock_sendto(sock, &buffer[..bytes_read], peer)?;
+    }
+}
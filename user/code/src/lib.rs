@@ -31,7 +31,7 @@ fn panic(_info: &PanicInfo) -> ! {
             _info.message()
         );
     }
-    syscall::exit();
+    syscall::exit(101);
 }
 
 #[no_mangle]
@@ -80,7 +80,7 @@ extern "Rust" {
 
 #[no_mangle]
 fn call_exit() -> ! {
-    syscall::exit();
+    syscall::exit(0);
 }
 
 